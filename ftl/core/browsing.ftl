@@ -43,6 +43,8 @@ browsing-enter-tags-to-delete = Enter tags to delete:
 browsing-filtered = (filtered)
 browsing-find = <b>Find</b>:
 browsing-find-and-replace = Find and Replace
+# Shown in the undo menu, eg "Find and Replace: cat → dog"
+browsing-find-and-replace-described = Find and Replace: { $search } → { $replacement }
 browsing-find-duplicates = Find Duplicates
 browsing-first-card = First Card
 browsing-flag = Flag
@@ -65,6 +67,7 @@ browsing-no-selection = No cards or notes selected.
 browsing-note = Note
 browsing-notes = Notes
 browsing-optional-filter = Optional filter:
+browsing-original-due = Original Due
 browsing-override-back-template = Override back template:
 browsing-override-font = Override font:
 browsing-override-front-template = Override front template:
@@ -19,6 +19,8 @@ search-invalid-argument = `{ $term }` was given an invalid argument '`{ $argumen
 search-invalid-flag-2 = `flag:` must be followed by a valid flag number: `1` (red), `2` (orange), `3` (green), `4` (blue), `5` (pink), `6` (turquoise), `7` (purple) or `0` (no flag).
 search-invalid-prop-operator = `prop:{ $val }` must be followed by one of the following comparison operators: `=`, `!=`, `<`, `>`, `<=` or `>=`.
 search-invalid-other = please check for typing mistakes.
+# The details come from the regex library, and are not translated.
+search-invalid-regex = the regular expression `{ $provided }` is invalid: { $details }
 
 ## eg. expected a number in "due>5x", but found "5x"
 
@@ -27,6 +29,7 @@ search-invalid-whole-number = expected a whole number in "`{ $context }`", but f
 search-invalid-positive-whole-number = expected a positive whole number in "`{ $context }`", but found "`{ $provided }`".
 search-invalid-negative-whole-number = expected a whole number less than or equal to 0 in "`{ $context }`", but found "`{ $provided }`".
 search-invalid-answer-button = expected an answer button between 1-4 in "`{ $context }`", but found "`{ $provided }`".
+search-invalid-date = expected a date like 2024-01-15 in "`{ $context }`", but found "`{ $provided }`".
 
 ## Column labels in browse screen
 
@@ -814,16 +814,19 @@ class Collection(DeprecatedNamesMixin):
         existing_node: SearchNode,
         additional_node: SearchNode,
         operator: Literal["AND", "OR"],
+        deduplicate: bool = False,
     ) -> str:
         """
         AND or OR `additional_term` to `existing_term`, without wrapping `existing_term` in brackets.
         Used by the Browse screen to avoid adding extra brackets when joining.
         If you're building a search query yourself, you probably don't need this.
+        If `deduplicate` is set, terms repeating an earlier one are left out.
         """
         search_string = self._backend.join_search_nodes(
             joiner=self._pb_search_separator(operator),
             existing_node=existing_node,
             additional_node=additional_node,
+            deduplicate=deduplicate,
         )
 
         return search_string
@@ -839,6 +842,17 @@ class Collection(DeprecatedNamesMixin):
             existing_node=existing_node, replacement_node=replacement_node
         )
 
+    def remove_from_search_node(
+        self, existing_node: SearchNode, node_to_remove: SearchNode
+    ) -> str:
+        """Remove any nodes of the same type as `node_to_remove` from existing_node.
+
+        If nothing is left, an empty search, matching the whole collection, is returned.
+        """
+        return self._backend.replace_search_node(
+            existing_node=existing_node, replacement_node=node_to_remove, remove=True
+        )
+
     def _pb_search_separator(self, operator: SearchJoiner) -> SearchNode.Group.Joiner.V:
         # pylint: disable=no-member
         if operator == "AND":
@@ -337,8 +337,11 @@ class DataModel(QAbstractTableModel):
             return qfont
         elif role == Qt.ItemDataRole.TextAlignmentRole:
             align: Qt.AlignmentFlag | int = Qt.AlignmentFlag.AlignVCenter
-            if self.column_at(index).alignment == Columns.ALIGNMENT_CENTER:
+            alignment = self.column_at(index).alignment
+            if alignment == Columns.ALIGNMENT_CENTER:
                 align |= Qt.AlignmentFlag.AlignHCenter
+            elif alignment == Columns.ALIGNMENT_END:
+                align |= Qt.AlignmentFlag.AlignTrailing
             return getattr(align, "value", align)
         elif role == Qt.ItemDataRole.DisplayRole:
             return self.get_cell(index).text
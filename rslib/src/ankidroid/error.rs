@@ -131,6 +131,7 @@ pub(crate) fn debug_produce_error(s: &str) -> AnkiError {
         },
         "SearchError" => AnkiError::SearchError {
             source: SearchErrorKind::EmptyGroup,
+            span: None,
         },
         _ => AnkiError::InvalidInput {
             source: InvalidInputError {
@@ -36,6 +36,7 @@ impl From<BoolKeyProto> for BoolKey {
             BoolKeyProto::ResetCountsReviewer => BoolKey::ResetCountsReviewer,
             BoolKeyProto::RandomOrderReposition => BoolKey::RandomOrderReposition,
             BoolKeyProto::ShiftPositionOfExistingCards => BoolKey::ShiftPositionOfExistingCards,
+            BoolKeyProto::SearchDecksByCurrentDeck => BoolKey::SearchDecksByCurrentDeck,
         }
     }
 }
@@ -47,6 +48,7 @@ impl From<StringKeyProto> for StringKey {
             StringKeyProto::SetDueReviewer => StringKey::SetDueReviewer,
             StringKeyProto::DefaultSearchText => StringKey::DefaultSearchText,
             StringKeyProto::CardStateCustomizer => StringKey::CardStateCustomizer,
+            StringKeyProto::LeechTag => StringKey::LeechTag,
         }
     }
 }
@@ -13,6 +13,15 @@ impl AnkiError {
         let help_page = self.help_page().map(|page| page as i32);
         let context = self.context();
         let backtrace = self.backtrace();
+        let search_error_span = match &self {
+            AnkiError::SearchError {
+                span: Some(span), ..
+            } => Some(anki_proto::backend::backend_error::Span {
+                start: span.start as u32,
+                end: span.end as u32,
+            }),
+            _ => None,
+        };
         let kind = match self {
             AnkiError::InvalidInput { .. } => Kind::InvalidInput,
             AnkiError::TemplateError { .. } => Kind::TemplateParse,
@@ -57,6 +66,7 @@ impl AnkiError {
             help_page,
             context,
             backtrace,
+            search_error_span,
         }
     }
 }
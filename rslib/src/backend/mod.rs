@@ -12,6 +12,7 @@ mod error;
 mod i18n;
 mod import_export;
 mod ops;
+mod search;
 mod sync;
 
 use std::ops::Deref;
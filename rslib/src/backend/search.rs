@@ -0,0 +1,17 @@
+// Copyright: Ankitects Pty Ltd and contributors
+// License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
+
+use anki_proto::generic;
+
+use super::Backend;
+use crate::prelude::*;
+use crate::search::service::validate_search;
+use crate::services::BackendSearchService;
+
+// Parsing doesn't need the collection, so it can be done without waiting for
+// the collection lock.
+impl BackendSearchService for Backend {
+    fn validate_search(&self, input: generic::String) -> Result<()> {
+        validate_search(&input.val)
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright: Ankitects Pty Ltd and contributors
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use fsrs::FSRS;
@@ -13,6 +14,7 @@ use strum::IntoEnumIterator;
 use crate::card::CardQueue;
 use crate::card::CardType;
 use crate::card_rendering::prettify_av_tags;
+use crate::config::ConfigKey;
 use crate::notetype::CardTemplate;
 use crate::notetype::NotetypeKind;
 use crate::prelude::*;
@@ -53,12 +55,19 @@ pub enum Column {
     SortField,
     #[strum(serialize = "noteTags")]
     Tags,
+    /// The position of new cards in the new queue.
+    #[strum(serialize = "cardPosition")]
+    Position,
+    /// The due date the card will return to when it leaves a filtered deck,
+    /// or its due date if it's not in one.
+    #[strum(serialize = "cardOriginalDue")]
+    OriginalDue,
     Stability,
     Difficulty,
     Retrievability,
 }
 
-struct RowContext {
+struct RowContext<'a> {
     notes_mode: bool,
     cards: Vec<Card>,
     note: Note,
@@ -68,6 +77,7 @@ struct RowContext {
     tr: I18n,
     timing: SchedTimingToday,
     render_context: RenderContext,
+    flag_colors: &'a HashMap<u8, String>,
 }
 
 enum RenderContext {
@@ -166,6 +176,8 @@ impl Column {
             Self::Reps => tr.scheduling_reviews(),
             Self::SortField => tr.browsing_sort_field(),
             Self::Tags => tr.editing_tags(),
+            Self::Position => tr.card_stats_new_card_position(),
+            Self::OriginalDue => tr.browsing_original_due(),
             Self::Stability => tr.card_stats_fsrs_stability(),
             Self::Difficulty => tr.card_stats_fsrs_difficulty(),
             Self::Retrievability => tr.card_stats_fsrs_retrievability(),
@@ -228,7 +240,11 @@ impl Column {
             | Column::NoteCreation
             | Column::NoteMod
             | Column::Reps => Sorting::Descending,
-            Column::Stability | Column::Difficulty | Column::Retrievability => {
+            Column::Position
+            | Column::OriginalDue
+            | Column::Stability
+            | Column::Difficulty
+            | Column::Retrievability => {
                 if notes {
                     Sorting::None
                 } else {
@@ -252,6 +268,7 @@ impl Column {
             | Self::SortField
             | Self::Notetype
             | Self::Tags => Alignment::Start,
+            Self::Lapses => Alignment::End,
             _ => Alignment::Center,
         }
     }
@@ -269,13 +286,61 @@ impl Collection {
 
     pub fn browser_row_for_id(&mut self, id: i64) -> Result<anki_proto::search::BrowserRow> {
         let notes_mode = self.get_config_bool(BoolKey::BrowserTableShowNotesMode);
-        let columns = Arc::clone(
+        let columns = self.active_browser_columns()?;
+        let timing = self.timing_today()?;
+        let flag_colors = self.get_config_default(ConfigKey::FlagColors);
+        RowContext::new(
+            self,
+            id,
+            notes_mode,
+            card_render_required(&columns),
+            timing,
+            &flag_colors,
+        )?
+        .browser_row(&columns)
+    }
+
+    /// Like [Collection::browser_row_for_id], but for several ids at once,
+    /// returning rows in the same order. Rather than failing, a deleted card
+    /// or note gives an empty row with `deleted` set. If `columns` is
+    /// provided, cells are built for those instead of the active columns, and
+    /// cards are only rendered if a question or answer column is among them.
+    pub fn browser_rows_for_ids(
+        &mut self,
+        ids: &[i64],
+        columns: Option<Vec<Column>>,
+    ) -> Result<Vec<anki_proto::search::BrowserRow>> {
+        let notes_mode = self.get_config_bool(BoolKey::BrowserTableShowNotesMode);
+        let columns = match columns {
+            Some(columns) => Arc::new(columns),
+            None => self.active_browser_columns()?,
+        };
+        let with_card_render = card_render_required(&columns);
+        // looked up once for the whole batch
+        let timing = self.timing_today()?;
+        let flag_colors: HashMap<u8, String> = self.get_config_default(ConfigKey::FlagColors);
+        ids.iter()
+            .map(|&id| {
+                match RowContext::new(self, id, notes_mode, with_card_render, timing, &flag_colors)
+                    .and_then(|context| context.browser_row(&columns))
+                {
+                    Err(AnkiError::Deleted) => Ok(anki_proto::search::BrowserRow {
+                        deleted: true,
+                        ..Default::default()
+                    }),
+                    row => row,
+                }
+            })
+            .collect()
+    }
+
+    fn active_browser_columns(&self) -> Result<Arc<Vec<Column>>> {
+        Ok(Arc::clone(
             self.state
                 .active_browser_columns
                 .as_ref()
                 .or_invalid("Active browser columns not set.")?,
-        );
-        RowContext::new(self, id, notes_mode, card_render_required(&columns))?.browser_row(&columns)
+        ))
     }
 
     fn get_note_maybe_with_fields(&self, id: NoteId, _with_fields: bool) -> Result<Note> {
@@ -337,12 +402,14 @@ fn rendered_nodes_to_str(nodes: &[RenderedNode]) -> String {
     prettify_av_tags(txt)
 }
 
-impl RowContext {
+impl<'a> RowContext<'a> {
     fn new(
         col: &mut Collection,
         id: i64,
         notes_mode: bool,
         with_card_render: bool,
+        timing: SchedTimingToday,
+        flag_colors: &'a HashMap<u8, String>,
     ) -> Result<Self> {
         let cards;
         let note;
@@ -378,7 +445,6 @@ impl RowContext {
         } else {
             None
         };
-        let timing = col.timing_today()?;
         let render_context = if with_card_render {
             RenderContext::new(col, &cards[0], &note, &notetype)
         } else {
@@ -395,6 +461,7 @@ impl RowContext {
             tr: col.tr.clone(),
             timing,
             render_context,
+            flag_colors,
         })
     }
 
@@ -407,6 +474,8 @@ impl RowContext {
             color: self.get_row_color() as i32,
             font_name: self.get_row_font_name()?,
             font_size: self.get_row_font_size()?,
+            color_override: self.get_row_color_override(),
+            deleted: false,
         })
     }
 
@@ -434,6 +503,8 @@ impl RowContext {
             Column::NoteMod => self.note.mtime.date_and_time_string(),
             Column::Tags => self.note.tags.join(" "),
             Column::Notetype => self.notetype.name.to_owned(),
+            Column::Position => self.position_str(),
+            Column::OriginalDue => self.card_due_str_inner(false),
             Column::Stability => self.fsrs_stability_str(),
             Column::Difficulty => self.fsrs_difficulty_str(),
             Column::Retrievability => self.fsrs_retrievability_str(),
@@ -475,10 +546,17 @@ impl RowContext {
     }
 
     fn card_due_str(&self) -> String {
-        let due = if self.cards[0].is_filtered_deck() {
+        self.card_due_str_inner(true)
+    }
+
+    /// If `mark_filtered` is false, cards in filtered decks show the due date
+    /// they'll have when returned to their home deck.
+    fn card_due_str_inner(&self, mark_filtered: bool) -> String {
+        let due = if mark_filtered && self.cards[0].is_filtered_deck() {
             self.tr.browsing_filtered()
         } else if self.cards[0].is_new_type_or_queue() {
-            self.tr.statistics_due_for_new_card(self.cards[0].due)
+            self.tr
+                .statistics_due_for_new_card(self.cards[0].original_or_current_due())
         } else if let Some(time) = self.cards[0].due_time(&self.timing) {
             time.date_string().into()
         } else {
@@ -491,6 +569,15 @@ impl RowContext {
         }
     }
 
+    fn position_str(&self) -> String {
+        let card = &self.cards[0];
+        if card.ctype == CardType::New {
+            card.original_or_current_due().to_string()
+        } else {
+            "".into()
+        }
+    }
+
     fn fsrs_stability_str(&self) -> String {
         self.cards[0]
             .memory_state
@@ -621,6 +708,17 @@ impl RowContext {
         Ok(self.template()?.config.browser_font_size)
     }
 
+    /// Only flag colours can be customised, so this is empty in notes mode
+    /// and for unflagged cards.
+    fn get_row_color_override(&self) -> String {
+        match self.cards[0].flag() {
+            flag if flag != 0 && !self.notes_mode => {
+                self.flag_colors.get(&flag).cloned().unwrap_or_default()
+            }
+            _ => String::new(),
+        }
+    }
+
     fn get_row_color(&self) -> anki_proto::search::browser_row::Color {
         use anki_proto::search::browser_row::Color;
         if self.notes_mode {
@@ -630,7 +728,7 @@ impl RowContext {
                 Color::Default
             }
         } else {
-            match self.cards[0].flags {
+            match self.cards[0].flag() {
                 1 => Color::FlagRed,
                 2 => Color::FlagOrange,
                 3 => Color::FlagGreen,
@@ -653,3 +751,69 @@ impl RowContext {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tests::NoteAdder;
+
+    fn cell_texts(row: &anki_proto::search::BrowserRow) -> Vec<&str> {
+        row.cells.iter().map(|cell| cell.text.as_str()).collect()
+    }
+
+    #[test]
+    fn rows_follow_id_order_and_flag_deleted_ids() -> Result<()> {
+        let mut col = Collection::new();
+        let mut add_note = |front: &str| -> Result<(NoteId, CardId)> {
+            let note = NoteAdder::basic(&mut col)
+                .fields(&[front, ""])
+                .add(&mut col);
+            Ok((note.id, col.storage.card_ids_of_notes(&[note.id])?[0]))
+        };
+        let (_, first) = add_note("first")?;
+        let (_, second) = add_note("second")?;
+        let (removed_note, removed) = add_note("removed")?;
+        col.remove_notes(&[removed_note])?;
+
+        let rows = col.browser_rows_for_ids(
+            &[second.0, removed.0, first.0, second.0],
+            Some(vec![Column::SortField]),
+        )?;
+        let texts: Vec<_> = rows.iter().map(cell_texts).collect();
+        assert_eq!(
+            texts,
+            [vec!["second"], vec![], vec!["first"], vec!["second"]]
+        );
+        let deleted: Vec<_> = rows.iter().map(|row| row.deleted).collect();
+        assert_eq!(deleted, [false, true, false, false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom_flag_colors_override_row_color() -> Result<()> {
+        let mut col = Collection::new();
+        col.set_config(
+            ConfigKey::FlagColors,
+            &HashMap::from([(1u8, "#123456".to_string())]),
+        )?;
+        let mut cids = vec![];
+        for flag in [1, 2, 0] {
+            let note = NoteAdder::basic(&mut col).add(&mut col);
+            let cid = col.storage.card_ids_of_notes(&[note.id])?[0];
+            col.set_card_flag(&[cid], flag)?;
+            cids.push(cid.0);
+        }
+
+        let rows = col.browser_rows_for_ids(&cids, Some(vec![Column::Deck]))?;
+        let overrides: Vec<_> = rows.iter().map(|row| row.color_override.as_str()).collect();
+        assert_eq!(overrides, ["#123456", "", ""]);
+        // the enum colour is still provided for clients that ignore overrides
+        assert_eq!(
+            rows[0].color,
+            anki_proto::search::browser_row::Color::FlagRed as i32
+        );
+
+        Ok(())
+    }
+}
@@ -203,6 +203,11 @@ impl Card {
         }
     }
 
+    /// The user flag (0-7), ignoring the other bits of the flag byte.
+    pub(crate) fn flag(&self) -> u8 {
+        self.flags & 0b111
+    }
+
     /// Return the total number of steps left to do, ignoring the
     /// "steps today" number packed into the DB representation.
     pub fn remaining_steps(&self) -> u32 {
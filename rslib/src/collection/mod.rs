@@ -26,6 +26,7 @@ use crate::notetype::NotetypeId;
 use crate::progress::ProgressState;
 use crate::scheduler::queue::CardQueues;
 use crate::scheduler::SchedulerInfo;
+use crate::search::cache::ParsedSearchCache;
 use crate::storage::SchemaVersion;
 use crate::storage::SqliteStorage;
 use crate::timestamp::TimestampMillis;
@@ -131,6 +132,7 @@ pub struct CollectionState {
     pub(crate) scheduler_info: Option<SchedulerInfo>,
     pub(crate) card_queues: Option<CardQueues>,
     pub(crate) active_browser_columns: Option<Arc<Vec<browser_table::Column>>>,
+    pub(crate) search_cache: ParsedSearchCache,
     /// True if legacy Python code has executed SQL that has modified the
     /// database, requiring modification time to be bumped.
     pub(crate) modified_by_dbproxy: bool,
@@ -33,6 +33,9 @@ pub enum BoolKey {
     ResetCountsBrowser,
     ResetCountsReviewer,
     RandomOrderReposition,
+    /// Match cards in filtered decks by the filtered deck's name, even when
+    /// it is the child of a searched deck.
+    SearchDecksByCurrentDeck,
     Sched2021,
     ShiftPositionOfExistingCards,
     MergeNotetypes,
@@ -54,6 +54,8 @@ pub(crate) enum ConfigKey {
     Backups,
     UpdateNotes,
     UpdateNotetypes,
+    /// Maps flag numbers to user-chosen colour strings.
+    FlagColors,
 
     #[strum(to_string = "timeLim")]
     AnswerTimeLimitSecs,
@@ -71,6 +73,7 @@ pub(crate) enum ConfigKey {
     NextNewCardPosition,
     #[strum(to_string = "schedVer")]
     SchedulerVersion,
+    StudySessionStart,
 }
 
 #[derive(PartialEq, Eq, Serialize_repr, Deserialize_repr, Clone, Copy, Debug)]
@@ -282,6 +285,17 @@ impl Collection {
             .map(|_| ())
     }
 
+    /// When the current study session began, as set by the frontend. Used by
+    /// `rated:session`.
+    pub fn get_study_session_start(&self) -> Option<TimestampSecs> {
+        self.get_config_optional(ConfigKey::StudySessionStart)
+    }
+
+    pub fn set_study_session_start(&mut self, start: TimestampSecs) -> Result<()> {
+        self.set_config(ConfigKey::StudySessionStart, &start)
+            .map(|_| ())
+    }
+
     pub(crate) fn get_backup_limits(&self) -> BackupLimits {
         self.get_config_optional(ConfigKey::Backups).unwrap_or(
             // 2d + 12d + 10w + 9m ≈ 1y
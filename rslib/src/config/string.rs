@@ -12,6 +12,8 @@ pub enum StringKey {
     SetDueReviewer,
     DefaultSearchText,
     CardStateCustomizer,
+    /// The tag added to notes of cards that become leeches.
+    LeechTag,
 }
 
 impl Collection {
@@ -19,6 +21,7 @@ impl Collection {
         let default = match key {
             StringKey::SetDueBrowser => "0",
             StringKey::SetDueReviewer => "1",
+            StringKey::LeechTag => "leech",
             _other => "",
         };
         self.get_config_optional(key)
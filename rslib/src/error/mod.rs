@@ -10,6 +10,8 @@ mod search;
 #[cfg(windows)]
 pub mod windows;
 
+use std::ops::Range;
+
 use anki_i18n::I18n;
 use anki_io::FileIoError;
 use anki_io::FileOp;
@@ -86,9 +88,11 @@ pub enum AnkiError {
     FilteredDeckError {
         source: FilteredDeckError,
     },
-    #[snafu(context(false))]
     SearchError {
         source: SearchErrorKind,
+        /// Byte range of the part of the search text that failed to parse,
+        /// if known.
+        span: Option<Range<usize>>,
     },
     InvalidRegex {
         info: String,
@@ -150,7 +154,7 @@ impl AnkiError {
                 format!("{}<br>{}", header, details)
             }
             AnkiError::DbError { source } => source.message(tr),
-            AnkiError::SearchError { source } => source.message(tr),
+            AnkiError::SearchError { source, .. } => source.message(tr),
             AnkiError::ParseNumError => tr.errors_parse_number_fail().into(),
             AnkiError::FilteredDeckError { source } => source.message(tr),
             AnkiError::InvalidRegex { info: source } => format!("<pre>{}</pre>", source),
@@ -2,6 +2,7 @@
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
 use std::num::ParseIntError;
+use std::ops::Range;
 
 use anki_i18n::I18n;
 use nom::error::ErrorKind as NomErrorKind;
@@ -29,6 +30,8 @@ pub enum SearchErrorKind {
     UnknownEscape { provided: String },
     InvalidState { provided: String },
     InvalidFlag,
+    InvalidWeekday { provided: String },
+    InvalidDataKey { provided: String },
     InvalidPropProperty { provided: String },
     InvalidPropOperator { provided: String },
     InvalidNumber { provided: String, context: String },
@@ -36,15 +39,21 @@ pub enum SearchErrorKind {
     InvalidPositiveWholeNumber { provided: String, context: String },
     InvalidNegativeWholeNumber { provided: String, context: String },
     InvalidAnswerButton { provided: String, context: String },
+    InvalidDate { provided: String, context: String },
+    InvalidRegex { provided: String, details: String },
     Other { info: Option<String> },
 }
 
 impl From<ParseError<'_>> for AnkiError {
     fn from(err: ParseError) -> Self {
         match err {
-            ParseError::Anki(_, kind) => AnkiError::SearchError { source: kind },
+            ParseError::Anki(_, kind) => AnkiError::SearchError {
+                source: kind,
+                span: None,
+            },
             ParseError::Nom(_, _) => AnkiError::SearchError {
                 source: SearchErrorKind::Other { info: None },
+                span: None,
             },
         }
     }
@@ -57,11 +66,40 @@ impl From<nom::Err<ParseError<'_>>> for AnkiError {
             nom::Err::Failure(e) => e.into(),
             nom::Err::Incomplete(_) => AnkiError::SearchError {
                 source: SearchErrorKind::Other { info: None },
+                span: None,
             },
         }
     }
 }
 
+impl AnkiError {
+    /// Like the [From] impl, but also records where in `input` (the full text
+    /// passed to the parser) the error occurred.
+    pub(crate) fn from_search_failure(err: nom::Err<ParseError<'_>>, input: &str) -> Self {
+        let span = match &err {
+            nom::Err::Error(e) | nom::Err::Failure(e) => e.span_in(input),
+            nom::Err::Incomplete(_) => None,
+        };
+        match AnkiError::from(err) {
+            AnkiError::SearchError { source, .. } => AnkiError::SearchError { source, span },
+            other => other,
+        }
+    }
+}
+
+impl ParseError<'_> {
+    /// The failing text is a slice of the input, so its byte range can be
+    /// recovered from its address. Returns None if it isn't part of `input`.
+    fn span_in(&self, input: &str) -> Option<Range<usize>> {
+        let failing = match self {
+            ParseError::Anki(text, _) | ParseError::Nom(text, _) => text,
+        };
+        let start = (failing.as_ptr() as usize).checked_sub(input.as_ptr() as usize)?;
+        let end = start + failing.len();
+        (end <= input.len()).then_some(start..end)
+    }
+}
+
 impl<'a> NomParseError<&'a str> for ParseError<'a> {
     fn from_error_kind(input: &'a str, kind: NomErrorKind) -> Self {
         ParseError::Nom(input, kind)
@@ -97,6 +135,12 @@ impl SearchErrorKind {
             }
 
             SearchErrorKind::InvalidFlag => tr.search_invalid_flag_2(),
+            SearchErrorKind::InvalidWeekday { provided } => {
+                tr.search_invalid_argument("dueweekday:", provided.replace('`', "'"))
+            }
+            SearchErrorKind::InvalidDataKey { provided } => {
+                tr.search_invalid_argument("datakey:", provided.replace('`', "'"))
+            }
             SearchErrorKind::InvalidPropProperty { provided } => {
                 tr.search_invalid_argument("prop:", provided.replace('`', "'"))
             }
@@ -129,6 +173,14 @@ impl SearchErrorKind {
                     context.replace('`', "'"),
                     provided.replace('`', "'"),
                 ),
+
+            SearchErrorKind::InvalidDate { provided, context } => {
+                tr.search_invalid_date(context.replace('`', "'"), provided.replace('`', "'"))
+            }
+
+            SearchErrorKind::InvalidRegex { provided, details } => {
+                tr.search_invalid_regex(provided.replace('`', "'"), details.as_str())
+            }
         };
         tr.search_invalid_search(reason).into()
     }
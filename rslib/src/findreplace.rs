@@ -2,27 +2,73 @@
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
 use std::borrow::Cow;
+use std::ops::Range;
 
+use regex::Captures;
 use regex::Regex;
 
 use crate::collection::Collection;
 use crate::error::Result;
 use crate::notes::NoteId;
+use crate::notes::NoteTags;
 use crate::notes::TransformNoteOutput;
 use crate::prelude::*;
+use crate::tags::join_tags;
+use crate::text::map_html_text;
 use crate::text::normalize_to_nfc;
 
+/// When passed as the only field name, [Collection::find_and_replace] operates
+/// on each note's tags instead of its fields. Field names can't contain colons,
+/// so this can't clash with a real field.
+pub const TAGS_FIELD_NAME: &str = ":tags";
+
+/// When included in the field names passed to [Collection::find_and_replace],
+/// stands for the sort field of each note's notetype, so notes of different
+/// notetypes may have different fields searched.
+pub const SORT_FIELD_NAME: &str = ":sortfield";
+
+/// The search and replacement as the user entered them, before the search was
+/// turned into a regex. Used to name the undo step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindReplaceDescription {
+    pub search: String,
+    pub replacement: String,
+}
+
+/// Build the regex for a user-provided search. Unless `regex` is set, the text
+/// is matched literally, and `whole_word` requires it to start and end at
+/// (Unicode) word boundaries. Regex searches can use their own anchors, so
+/// `whole_word` is ignored for them.
+pub fn search_regex(search: &str, regex: bool, match_case: bool, whole_word: bool) -> String {
+    let mut search = if regex {
+        search.into()
+    } else if whole_word {
+        format!(r"\b{}\b", regex::escape(search))
+    } else {
+        regex::escape(search)
+    };
+    if !match_case {
+        search = format!("(?i){}", search);
+    }
+    search
+}
+
 pub struct FindReplaceContext {
     nids: Vec<NoteId>,
     search: Regex,
     replacement: String,
-    field_name: Option<String>,
+    field_names: Vec<String>,
+    html_aware: bool,
 }
 
-enum FieldForNotetype {
+enum FieldsForNotetype {
     Any,
-    Index(usize),
-    None,
+    Indices(Vec<usize>),
+}
+
+struct NoteReplacement {
+    first_changed_field: usize,
+    matches: usize,
 }
 
 impl FindReplaceContext {
@@ -30,84 +76,401 @@ impl FindReplaceContext {
         nids: Vec<NoteId>,
         search_re: &str,
         repl: impl Into<String>,
-        field_name: Option<String>,
+        field_names: Vec<String>,
+        html_aware: bool,
     ) -> Result<Self> {
         Ok(FindReplaceContext {
             nids,
             search: Regex::new(search_re)?,
             replacement: repl.into(),
-            field_name,
+            field_names,
+            html_aware,
         })
     }
 
-    fn replace_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
-        self.search.replace_all(text, self.replacement.as_str())
+    /// Returns the replaced text, and the number of matches, which don't
+    /// overlap.
+    fn replace_text<'a>(&self, text: &'a str) -> (Cow<'a, str>, usize) {
+        let mut matches = 0;
+        let replaced = self.search.replace_all(text, |caps: &Captures| {
+            matches += 1;
+            let mut expanded = String::new();
+            caps.expand(&self.replacement, &mut expanded);
+            expanded
+        });
+        (replaced, matches)
+    }
+
+    /// Like [Self::replace_text], but if the context is HTML-aware, only text
+    /// between tags is searched, with its entities decoded, so a match can't
+    /// alter a tag or split an entity.
+    fn replace_field<'a>(&self, field: &'a str) -> (Cow<'a, str>, usize) {
+        if !self.html_aware {
+            return self.replace_text(field);
+        }
+        let mut matches = 0;
+        let replaced = map_html_text(field, |run| {
+            let (text, run_matches) = self.replace_in_html_text(run);
+            matches += run_matches;
+            text
+        });
+        (replaced, matches)
+    }
+
+    /// Matches are found in the decoded text, but only the spans of the run
+    /// they came from are replaced, so the rest of the run keeps its entities
+    /// as written. Replacements are escaped, so one containing `<` inserts
+    /// text rather than markup.
+    fn replace_in_html_text<'a>(&self, run: &'a str) -> (Cow<'a, str>, usize) {
+        let (decoded, spans) = decode_entities_with_spans(run);
+        // the start in the run of the character or entity at `pos` in the
+        // decoded text, and the end of the one before it
+        let run_start = |pos: usize| {
+            let idx = spans.partition_point(|(start, _)| *start <= pos);
+            if pos == decoded.len() {
+                run.len()
+            } else {
+                spans[idx - 1].1.start
+            }
+        };
+        let run_end = |pos: usize| {
+            let idx = spans.partition_point(|(start, _)| *start < pos);
+            spans[idx - 1].1.end
+        };
+        let mut out = String::new();
+        let mut last = 0;
+        let mut matches = 0;
+        for caps in self.search.captures_iter(&decoded) {
+            let found = caps.get(0).unwrap();
+            let start = run_start(found.start());
+            let end = if found.is_empty() {
+                start
+            } else {
+                run_end(found.end())
+            };
+            if start < last {
+                // only possible if an entity decoding to several characters
+                // was partly matched
+                continue;
+            }
+            matches += 1;
+            let mut expanded = String::new();
+            caps.expand(&self.replacement, &mut expanded);
+            out.push_str(&run[last..start]);
+            out.push_str(&htmlescape::encode_minimal(&expanded));
+            last = end;
+        }
+        if matches == 0 {
+            return (run.into(), 0);
+        }
+        out.push_str(&run[last..]);
+        (out.into(), matches)
+    }
+
+    /// True if [TAGS_FIELD_NAME] was passed, which can't be combined with
+    /// fields.
+    fn searches_tags(&self) -> Result<bool> {
+        if self.field_names.iter().any(|name| name == TAGS_FIELD_NAME) {
+            require!(
+                self.field_names.len() == 1,
+                "tags can't be combined with fields"
+            );
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn fields_for_notetype(&self, nt: &Notetype) -> FieldsForNotetype {
+        if self.field_names.is_empty() {
+            FieldsForNotetype::Any
+        } else {
+            // notetypes lacking all of the fields leave an empty list, so their
+            // notes are skipped
+            let mut ords: Vec<_> = self
+                .field_names
+                .iter()
+                .filter_map(|name| {
+                    if name == SORT_FIELD_NAME {
+                        Some(nt.config.sort_field_idx as usize)
+                    } else {
+                        nt.get_field_ord(name)
+                    }
+                })
+                .collect();
+            ords.sort_unstable();
+            ords.dedup();
+            FieldsForNotetype::Indices(ords)
+        }
+    }
+
+    /// Apply the replacement to the note's fields, returning the first
+    /// changed field and the total number of matches, if anything changed.
+    fn replace_in_note(
+        &self,
+        note: &mut Note,
+        fields: &FieldsForNotetype,
+    ) -> Option<NoteReplacement> {
+        let ords: Vec<usize> = match fields {
+            FieldsForNotetype::Any => (0..note.fields().len()).collect(),
+            FieldsForNotetype::Indices(ords) => ords.clone(),
+        };
+        let mut replacement: Option<NoteReplacement> = None;
+        for ord in ords {
+            if let Some(txt) = note.fields_mut().get_mut(ord) {
+                if let (Cow::Owned(otxt), matches) = self.replace_field(txt) {
+                    replacement
+                        .get_or_insert(NoteReplacement {
+                            first_changed_field: ord,
+                            matches: 0,
+                        })
+                        .matches += matches;
+                    *txt = otxt;
+                }
+            }
+        }
+        replacement
+    }
+}
+
+/// Decodes the entities in `text`, returning the decoded text, and for each
+/// character or entity, where it starts in the decoded text and its span in
+/// `text`. Ampersands that don't begin a valid entity are kept as they are.
+fn decode_entities_with_spans(text: &str) -> (String, Vec<(usize, Range<usize>)>) {
+    let mut decoded = String::with_capacity(text.len());
+    let mut spans = vec![];
+    let mut idx = 0;
+    while idx < text.len() {
+        let rest = &text[idx..];
+        let entity = rest
+            .strip_prefix('&')
+            .and_then(|name| name.find(';'))
+            .filter(|&end| !rest[1..=end].contains(|c: char| c == '&' || c.is_whitespace()))
+            .and_then(|end| {
+                let len = end + 2;
+                htmlescape::decode_html(&rest[..len])
+                    .ok()
+                    .map(|text| (len, text))
+            });
+        let len = match entity {
+            Some((len, text)) => {
+                spans.push((decoded.len(), idx..idx + len));
+                decoded.push_str(&text);
+                len
+            }
+            None => {
+                let len = rest.chars().next().unwrap().len_utf8();
+                spans.push((decoded.len(), idx..idx + len));
+                decoded.push_str(&rest[..len]);
+                len
+            }
+        };
+        idx += len;
     }
+    (decoded, spans)
+}
+
+/// A note that would be changed by a find and replace.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FindReplacePreview {
+    pub note_id: NoteId,
+    pub field_name: String,
+    /// The first changed field, before and after replacing.
+    pub before: String,
+    pub after: String,
 }
 
 impl Collection {
+    /// Replace matches in the named fields of the provided notes, or in all
+    /// fields if `field_names` is empty. [SORT_FIELD_NAME] picks out the
+    /// sort field of each note. If `field_names` holds only [TAGS_FIELD_NAME],
+    /// the space-separated tags of each note are searched instead, and the
+    /// result is split and normalized back into tags. Returns the number of
+    /// notes changed. All changes are undone in a single step, which shows
+    /// `description` if provided. If `html_aware` is set, tags in the fields
+    /// are left alone, and only the text between them is searched; it has no
+    /// effect on tags.
     pub fn find_and_replace(
         &mut self,
         nids: Vec<NoteId>,
         search_re: &str,
         repl: &str,
-        field_name: Option<String>,
+        field_names: Vec<String>,
+        html_aware: bool,
+        description: Option<FindReplaceDescription>,
     ) -> Result<OpOutput<usize>> {
-        self.transact(Op::FindAndReplace, |col| {
-            let norm = col.get_config_bool(BoolKey::NormalizeNoteText);
-            let search = if norm {
-                normalize_to_nfc(search_re)
+        self.find_and_replace_with_counts(
+            nids,
+            search_re,
+            repl,
+            field_names,
+            html_aware,
+            description,
+        )
+        .map(|out| out.map(|counts| counts.len()))
+    }
+
+    /// Like [Collection::find_and_replace], but returns each changed note
+    /// with the number of replacements made in it.
+    pub fn find_and_replace_with_counts(
+        &mut self,
+        nids: Vec<NoteId>,
+        search_re: &str,
+        repl: &str,
+        field_names: Vec<String>,
+        html_aware: bool,
+        description: Option<FindReplaceDescription>,
+    ) -> Result<OpOutput<Vec<(NoteId, usize)>>> {
+        self.transact(Op::FindAndReplace(description), |col| {
+            let ctx = col.find_replace_context(nids, search_re, repl, field_names, html_aware)?;
+            if ctx.searches_tags()? {
+                col.find_and_replace_in_tags(ctx)
             } else {
-                search_re.into()
-            };
-            let ctx = FindReplaceContext::new(nids, &search, repl, field_name)?;
-            col.find_and_replace_inner(ctx)
+                col.find_and_replace_inner(ctx)
+            }
         })
     }
 
-    fn find_and_replace_inner(&mut self, ctx: FindReplaceContext) -> Result<usize> {
+    /// Work out which notes [Collection::find_and_replace] would change,
+    /// without modifying anything. An invalid regex fails in the same way.
+    pub fn preview_find_and_replace(
+        &mut self,
+        nids: Vec<NoteId>,
+        search_re: &str,
+        repl: &str,
+        field_names: Vec<String>,
+        html_aware: bool,
+    ) -> Result<Vec<FindReplacePreview>> {
+        let ctx = self.find_replace_context(nids, search_re, repl, field_names, html_aware)?;
+        if ctx.searches_tags()? {
+            return self.preview_find_and_replace_in_tags(ctx);
+        }
+        let mut last_ntid = None;
+        let mut fields_for_notetype = FieldsForNotetype::Indices(vec![]);
+        let mut previews = vec![];
+        for &nid in &ctx.nids {
+            let Some(mut note) = self.storage.get_note(nid)? else {
+                continue;
+            };
+            let nt = self
+                .get_notetype(note.notetype_id)?
+                .or_not_found(note.notetype_id)?;
+            if last_ntid != Some(nt.id) {
+                fields_for_notetype = ctx.fields_for_notetype(&nt);
+                last_ntid = Some(nt.id);
+            }
+            let before = note.fields().to_vec();
+            if let Some(replacement) = ctx.replace_in_note(&mut note, &fields_for_notetype) {
+                let ord = replacement.first_changed_field;
+                previews.push(FindReplacePreview {
+                    note_id: nid,
+                    // notes can have more fields than their notetype, eg
+                    // if a notetype change didn't reach them
+                    field_name: nt
+                        .fields
+                        .get(ord)
+                        .map(|field| field.name.clone())
+                        .unwrap_or_default(),
+                    before: before[ord].clone(),
+                    after: note.fields()[ord].clone(),
+                });
+            }
+        }
+        Ok(previews)
+    }
+
+    /// Like [Collection::find_and_replace_in_tags], but new tags are not
+    /// registered. Previews are named [TAGS_FIELD_NAME], and hold the tags as
+    /// a space-separated string.
+    fn preview_find_and_replace_in_tags(
+        &mut self,
+        ctx: FindReplaceContext,
+    ) -> Result<Vec<FindReplacePreview>> {
+        let usn = self.usn()?;
+        let mut previews = vec![];
+        for original in self.storage.get_note_tags_by_id_list(&ctx.nids)? {
+            let (Cow::Owned(replaced), _) = ctx.replace_text(original.tags.trim()) else {
+                continue;
+            };
+            let tags = join_tags(&self.canonify_tags_without_registering(vec![replaced], usn)?);
+            if tags == original.tags {
+                continue;
+            }
+            previews.push(FindReplacePreview {
+                note_id: original.id,
+                field_name: TAGS_FIELD_NAME.into(),
+                before: original.tags.trim().into(),
+                after: tags.trim().into(),
+            });
+        }
+        Ok(previews)
+    }
+
+    fn find_replace_context(
+        &self,
+        nids: Vec<NoteId>,
+        search_re: &str,
+        repl: &str,
+        field_names: Vec<String>,
+        html_aware: bool,
+    ) -> Result<FindReplaceContext> {
+        let norm = self.get_config_bool(BoolKey::NormalizeNoteText);
+        let search = if norm {
+            normalize_to_nfc(search_re)
+        } else {
+            search_re.into()
+        };
+        FindReplaceContext::new(nids, &search, repl, field_names, html_aware)
+    }
+
+    fn find_and_replace_inner(&mut self, ctx: FindReplaceContext) -> Result<Vec<(NoteId, usize)>> {
         let mut last_ntid = None;
-        let mut field_for_notetype = FieldForNotetype::None;
+        let mut fields_for_notetype = FieldsForNotetype::Indices(vec![]);
+        let mut counts = vec![];
         self.transform_notes(&ctx.nids, |note, nt| {
             if last_ntid != Some(nt.id) {
-                field_for_notetype = match ctx.field_name.as_ref() {
-                    None => FieldForNotetype::Any,
-                    Some(name) => match nt.get_field_ord(name) {
-                        None => FieldForNotetype::None,
-                        Some(ord) => FieldForNotetype::Index(ord),
-                    },
-                };
+                fields_for_notetype = ctx.fields_for_notetype(nt);
                 last_ntid = Some(nt.id);
             }
 
-            let mut changed = false;
-            match field_for_notetype {
-                FieldForNotetype::Any => {
-                    for txt in note.fields_mut() {
-                        if let Cow::Owned(otxt) = ctx.replace_text(txt) {
-                            changed = true;
-                            *txt = otxt;
-                        }
-                    }
-                }
-                FieldForNotetype::Index(ord) => {
-                    if let Some(txt) = note.fields_mut().get_mut(ord) {
-                        if let Cow::Owned(otxt) = ctx.replace_text(txt) {
-                            changed = true;
-                            *txt = otxt;
-                        }
-                    }
-                }
-                FieldForNotetype::None => (),
+            let replacement = ctx.replace_in_note(note, &fields_for_notetype);
+            if let Some(replacement) = &replacement {
+                counts.push((note.id, replacement.matches));
             }
 
             Ok(TransformNoteOutput {
-                changed,
+                changed: replacement.is_some(),
                 generate_cards: true,
                 mark_modified: true,
                 update_tags: false,
             })
-        })
+        })?;
+        Ok(counts)
+    }
+
+    fn find_and_replace_in_tags(
+        &mut self,
+        ctx: FindReplaceContext,
+    ) -> Result<Vec<(NoteId, usize)>> {
+        let usn = self.usn()?;
+        let mut counts = vec![];
+        for original in self.storage.get_note_tags_by_id_list(&ctx.nids)? {
+            let (Cow::Owned(replaced), matches) = ctx.replace_text(original.tags.trim()) else {
+                continue;
+            };
+            // canonifying also fixes up any invalid or empty components the
+            // replacement produced, such as "a::::b" or a trailing "::"
+            let (tags, _) = self.canonify_tags(vec![replaced], usn)?;
+            let tags = join_tags(&tags);
+            if tags == original.tags {
+                continue;
+            }
+            let mut note = NoteTags { tags, ..original };
+            note.set_modified(usn);
+            self.update_note_tags_undoable(&note, original)?;
+            counts.push((note.id, matches));
+        }
+        Ok(counts)
     }
 }
 
@@ -115,6 +478,7 @@ impl Collection {
 mod test {
     use super::*;
     use crate::decks::DeckId;
+    use crate::tests::NoteAdder;
 
     #[test]
     fn findreplace() -> Result<()> {
@@ -132,7 +496,7 @@ mod test {
         col.add_note(&mut note2, DeckId(1))?;
 
         let nids = col.search_notes_unordered("")?;
-        let out = col.find_and_replace(nids.clone(), "(?i)AAA", "BBB", None)?;
+        let out = col.find_and_replace(nids.clone(), "(?i)AAA", "BBB", vec![], false, None)?;
         assert_eq!(out.output, 2);
 
         let note = col.storage.get_note(note.id)?.unwrap();
@@ -151,7 +515,14 @@ mod test {
                 "Text".into()
             ]
         );
-        let out = col.find_and_replace(nids, "BBB", "ccc", Some("Front".into()))?;
+        let out = col.find_and_replace(
+            nids.clone(),
+            "BBB",
+            "ccc",
+            vec!["Front".into()],
+            false,
+            None,
+        )?;
         // 1, because notes without the specified field should be skipped
         assert_eq!(out.output, 1);
 
@@ -159,6 +530,392 @@ mod test {
         // the update should be limited to the specified field when it was available
         assert_eq!(&note.fields()[..], &["one ccc", "two BBB"]);
 
+        // multiple fields can be provided, and each note is counted once
+        let out = col.find_and_replace(
+            nids,
+            "BBB|ccc",
+            "ddd",
+            vec!["Front".into(), "Back".into(), "Missing".into()],
+            false,
+            None,
+        )?;
+        assert_eq!(out.output, 1);
+        let note = col.storage.get_note(note.id)?.unwrap();
+        assert_eq!(&note.fields()[..], &["one ddd", "two ddd"]);
+        let note2 = col.storage.get_note(note2.id)?.unwrap();
+        assert_eq!(&note2.fields()[..], &["three BBB", ""]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replacement_counts() -> Result<()> {
+        let mut col = Collection::new();
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut note = nt.new_note();
+        note.set_field(0, "aaaa")?;
+        note.set_field(1, "a b a")?;
+        col.add_note(&mut note, DeckId(1))?;
+        let mut unmatched = nt.new_note();
+        unmatched.set_field(0, "b")?;
+        col.add_note(&mut unmatched, DeckId(1))?;
+        let nids = vec![note.id, unmatched.id];
+
+        // matches don't overlap, so "aaaa" holds two matches of "aa"
+        let out =
+            col.find_and_replace_with_counts(nids.clone(), "aa|a", "($0)", vec![], false, None)?;
+        assert_eq!(out.output, [(note.id, 4)]);
+        let note = col.storage.get_note(note.id)?.unwrap();
+        assert_eq!(&note.fields()[..], &["(aa)(aa)", "(a) b (a)"]);
+
+        // the aggregate count is unchanged
+        let out = col.find_and_replace(nids, "b", "c", vec![], false, None)?;
+        assert_eq!(out.output, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn described_undo_step() -> Result<()> {
+        let mut col = Collection::new();
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut notes = vec![];
+        for text in ["cat", "two cats"] {
+            let mut note = nt.new_note();
+            note.set_field(0, text)?;
+            col.add_note(&mut note, DeckId(1))?;
+            notes.push(note);
+        }
+        let nids = notes.iter().map(|note| note.id).collect();
+        let description = FindReplaceDescription {
+            search: "cat".into(),
+            replacement: "dog".into(),
+        };
+        col.find_and_replace(nids, "cat", "dog", vec![], false, Some(description))?;
+        let op = col.undo_status().undo.unwrap();
+        assert_eq!(
+            op.describe(&col.tr),
+            col.tr.browsing_find_and_replace_described("cat", "dog")
+        );
+
+        // one undo restores every note
+        col.undo()?;
+        for note in notes {
+            assert_eq!(col.storage.get_note(note.id)?.unwrap(), note);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn whole_words() -> Result<()> {
+        let mut col = Collection::new();
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut note = nt.new_note();
+        note.set_field(0, "café Café cafés écafé (café)")?;
+        col.add_note(&mut note, DeckId(1))?;
+        let mut replace = |search: &str, match_case| -> Result<String> {
+            let search = search_regex(search, false, match_case, true);
+            col.find_and_replace(vec![note.id], &search, "x", vec![], false, None)?;
+            Ok(col.storage.get_note(note.id)?.unwrap().fields()[0].clone())
+        };
+
+        // accented letters are part of words
+        assert_eq!(replace("café", true)?, "x Café cafés écafé (x)");
+        assert_eq!(replace("CAFÉ", false)?, "x x cafés écafé (x)");
+        assert_eq!(replace("caf", false)?, "x x cafés écafé (x)");
+        // regex searches are left alone
+        assert_eq!(search_regex("a|b", true, true, true), "a|b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn html_aware() -> Result<()> {
+        let mut col = Collection::new();
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut note = nt.new_note();
+        let original = r#"<div class="cat">cat &amp; <b>dog</b> cats<!-- cat --></div>"#;
+        note.set_field(0, original)?;
+        col.add_note(&mut note, DeckId(1))?;
+        let mut replace = |search: &str, repl: &str, html_aware| -> Result<(usize, String)> {
+            let out = col.find_and_replace_with_counts(
+                vec![note.id],
+                search,
+                repl,
+                vec![],
+                html_aware,
+                None,
+            )?;
+            let field = col.storage.get_note(note.id)?.unwrap().fields()[0].clone();
+            if !out.output.is_empty() {
+                col.undo()?;
+            }
+            Ok((out.output.first().map_or(0, |(_, count)| *count), field))
+        };
+
+        // tags, attributes and comments are skipped
+        assert_eq!(
+            replace("cat", "bird", true)?,
+            (
+                2,
+                r#"<div class="cat">bird &amp; <b>dog</b> birds<!-- cat --></div>"#.into()
+            )
+        );
+        assert_eq!(replace("cat", "bird", false)?.0, 4);
+        // capture groups work within a run of text, but matches can't span tags
+        assert_eq!(
+            replace(r"(\w+)s\b", "$1", true)?.1,
+            r#"<div class="cat">cat &amp; <b>dog</b> cat<!-- cat --></div>"#
+        );
+        assert_eq!(replace("dog cats", "x", true)?.0, 0);
+        // entities are matched as the text they stand for, and the replacement
+        // is escaped
+        assert_eq!(replace("amp", "x", true)?.0, 0);
+        assert_eq!(
+            replace("cat & ", "<i>", true)?.1,
+            r#"<div class="cat">&lt;i&gt;<b>dog</b> cats<!-- cat --></div>"#
+        );
+        assert_eq!(
+            col.storage.get_note(note.id)?.unwrap().fields()[0],
+            original
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn html_aware_keeps_unmatched_text() -> Result<()> {
+        let mut col = Collection::new();
+        let note = NoteAdder::basic(&mut col)
+            .fields(&[r#"a&nbsp;"cat"&#39;s &amp;c. & co"#, ""])
+            .add(&mut col);
+        let mut replace = |search: &str, repl: &str| -> Result<String> {
+            col.find_and_replace(vec![note.id], search, repl, vec![], true, None)?;
+            Ok(col.storage.get_note(note.id)?.unwrap().fields()[0].clone())
+        };
+
+        // entities and quotes next to a match are left as written
+        assert_eq!(replace("cat", "dog")?, r#"a&nbsp;"dog"&#39;s &amp;c. & co"#);
+        // a match covering entities replaces them whole
+        assert_eq!(
+            replace("\u{a0}\"dog\"'", "<x>")?,
+            r#"a&lt;x&gt;s &amp;c. & co"#
+        );
+        assert_eq!(replace("&c", "etc")?, r#"a&lt;x&gt;s etc. & co"#);
+        assert_eq!(replace("& co", "and co")?, r#"a&lt;x&gt;s etc. and co"#);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sort_field() -> Result<()> {
+        let mut col = Collection::new();
+        let mut by_back = col.basic_notetype();
+        by_back.id = NotetypeId(0);
+        by_back.name = "Basic sorted by back".into();
+        by_back.config.sort_field_idx = 1;
+        col.add_notetype(&mut by_back, false)?;
+        let front = NoteAdder::basic(&mut col)
+            .fields(&["aaa", "aaa"])
+            .add(&mut col);
+        let back = NoteAdder::new(&by_back)
+            .fields(&["aaa", "aaa"])
+            .add(&mut col);
+        let nids = vec![front.id, back.id];
+
+        let out = col.find_and_replace(
+            nids.clone(),
+            "a",
+            "b",
+            vec![SORT_FIELD_NAME.into()],
+            false,
+            None,
+        )?;
+        assert_eq!(out.output, 2);
+        let note = col.storage.get_note(front.id)?.unwrap();
+        assert_eq!(note.fields(), &["bbb", "aaa"]);
+        let note = col.storage.get_note(back.id)?.unwrap();
+        assert_eq!(note.fields(), &["aaa", "bbb"]);
+
+        // named fields can be included too
+        let out = col.find_and_replace_with_counts(
+            nids,
+            "a",
+            "c",
+            vec![SORT_FIELD_NAME.into(), "Front".into()],
+            false,
+            None,
+        )?;
+        assert_eq!(out.output, [(back.id, 3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tags() -> Result<()> {
+        let mut col = Collection::new();
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut note = nt.new_note();
+        note.tags = vec![
+            "project-alpha".into(),
+            "project-beta".into(),
+            "other".into(),
+        ];
+        col.add_note(&mut note, DeckId(1))?;
+        let mut untagged = nt.new_note();
+        col.add_note(&mut untagged, DeckId(1))?;
+        let nids = vec![note.id, untagged.id];
+        let tags = || vec![TAGS_FIELD_NAME.to_string()];
+
+        let out = col.find_and_replace_with_counts(
+            nids.clone(),
+            r"project-(\w+)",
+            "project::$1",
+            tags(),
+            false,
+            None,
+        )?;
+        assert_eq!(out.output, [(note.id, 2)]);
+        let note = col.storage.get_note(note.id)?.unwrap();
+        assert_eq!(&note.tags, &["other", "project::alpha", "project::beta"]);
+        // fields are left alone
+        assert_eq!(note.fields(), untagged.fields());
+
+        // the whole tag string is searched, and the result is normalized
+        let out = col.find_and_replace(
+            nids.clone(),
+            "other project",
+            "a:: b::::c",
+            tags(),
+            false,
+            None,
+        )?;
+        assert_eq!(out.output, 1);
+        let note = col.storage.get_note(note.id)?.unwrap();
+        assert_eq!(
+            &note.tags,
+            &["a::blank", "b::blank::c::alpha", "project::beta"]
+        );
+
+        // tags can't be mixed with fields
+        assert!(col
+            .find_and_replace(
+                nids,
+                "a",
+                "b",
+                vec![TAGS_FIELD_NAME.into(), "Front".into()],
+                false,
+                None
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview() -> Result<()> {
+        let mut col = Collection::new();
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut note = nt.new_note();
+        note.set_field(0, "one")?;
+        note.set_field(1, "two aaa aaa")?;
+        col.add_note(&mut note, DeckId(1))?;
+        let mut unmatched = nt.new_note();
+        unmatched.set_field(0, "three")?;
+        col.add_note(&mut unmatched, DeckId(1))?;
+        let nids = vec![note.id, unmatched.id];
+
+        assert_eq!(
+            col.preview_find_and_replace(nids.clone(), "a+", "b", vec![], false)?,
+            [FindReplacePreview {
+                note_id: note.id,
+                field_name: "Back".into(),
+                before: "two aaa aaa".into(),
+                after: "two b b".into(),
+            }]
+        );
+        // nothing was written
+        let stored = col.storage.get_note(note.id)?.unwrap();
+        assert_eq!(stored.fields(), note.fields());
+        assert_eq!(stored.mtime, note.mtime);
+        assert!(col
+            .preview_find_and_replace(nids.clone(), "a", "b", vec!["Front".into()], false)?
+            .is_empty());
+        // invalid regexes fail as they would when replacing
+        assert!(matches!(
+            col.preview_find_and_replace(nids, "(", "b", vec![], false),
+            Err(AnkiError::InvalidRegex { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_tags() -> Result<()> {
+        let mut col = Collection::new();
+        let mut note = NoteAdder::basic(&mut col).note();
+        note.tags = vec!["project-alpha".into(), "other".into()];
+        col.add_note(&mut note, DeckId(1))?;
+        let untagged = NoteAdder::basic(&mut col).add(&mut col);
+        let nids = vec![note.id, untagged.id];
+        let tags = || vec![TAGS_FIELD_NAME.to_string()];
+
+        assert_eq!(
+            col.preview_find_and_replace(
+                nids.clone(),
+                r"project-(\w+)",
+                "project::$1",
+                tags(),
+                false
+            )?,
+            [FindReplacePreview {
+                note_id: note.id,
+                field_name: TAGS_FIELD_NAME.into(),
+                before: "other project-alpha".into(),
+                after: "other project::alpha".into(),
+            }]
+        );
+        // nothing was written or registered
+        let stored = col.storage.get_note(note.id)?.unwrap();
+        assert_eq!(stored.tags, note.tags);
+        assert!(col.storage.get_tag("project::alpha")?.is_none());
+        // tags can't be mixed with fields
+        assert!(col
+            .preview_find_and_replace(
+                nids,
+                "a",
+                "b",
+                vec![TAGS_FIELD_NAME.into(), "Front".into()],
+                false
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_extra_stored_field() -> Result<()> {
+        let mut col = Collection::new();
+        let note = NoteAdder::basic(&mut col)
+            .fields(&["one", "two"])
+            .add(&mut col);
+        col.storage.db.execute(
+            "update notes set flds = ? where id = ?",
+            ("one\x1ftwo\x1faaa", note.id),
+        )?;
+
+        // a field beyond the notetype's has no name
+        assert_eq!(
+            col.preview_find_and_replace(vec![note.id], "a+", "b", vec![], false)?,
+            [FindReplacePreview {
+                note_id: note.id,
+                field_name: "".into(),
+                before: "aaa".into(),
+                after: "b".into(),
+            }]
+        );
+
         Ok(())
     }
 }
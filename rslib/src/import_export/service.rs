@@ -7,6 +7,10 @@ use anki_proto::import_export::ExportLimit;
 use crate::prelude::*;
 use crate::search::SearchNode;
 
+/// The most rows one NoteRowsForSearch response holds, so that a large
+/// export doesn't have to be built as a single message.
+const NOTE_ROWS_PAGE_SIZE: u32 = 1000;
+
 impl crate::services::ImportExportService for Collection {
     fn import_anki_package(
         &mut self,
@@ -83,6 +87,32 @@ impl crate::services::ImportExportService for Collection {
         .map(Into::into)
     }
 
+    fn note_rows_for_search(
+        &mut self,
+        input: anki_proto::import_export::NoteRowsForSearchRequest,
+    ) -> Result<anki_proto::import_export::NoteRowsForSearchResponse> {
+        let limit = match input.limit {
+            0 => NOTE_ROWS_PAGE_SIZE,
+            n => n.min(NOTE_ROWS_PAGE_SIZE),
+        };
+        let mut rows = vec![];
+        let mut has_more = false;
+        // one extra row is requested to find out if there are more
+        let request = anki_proto::import_export::NoteRowsForSearchRequest {
+            limit: limit + 1,
+            ..input
+        };
+        self.for_each_note_row_in_search(request, |cells| {
+            if rows.len() == limit as usize {
+                has_more = true;
+            } else {
+                rows.push(anki_proto::import_export::note_rows_for_search_response::Row { cells });
+            }
+            Ok(())
+        })?;
+        Ok(anki_proto::import_export::NoteRowsForSearchResponse { rows, has_more })
+    }
+
     fn import_json_file(
         &mut self,
         input: generic::String,
@@ -7,7 +7,9 @@ use std::fs::File;
 use std::io::Write;
 use std::sync::Arc;
 
+use anki_proto::import_export::note_rows_for_search_request::column::Kind as RowColumn;
 use anki_proto::import_export::ExportNoteCsvRequest;
+use anki_proto::import_export::NoteRowsForSearchRequest;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -68,6 +70,64 @@ impl Collection {
         Ok(incrementor.count())
     }
 
+    /// Calls `func` with the requested columns of each note matching the
+    /// search, in the requested order. Notes are loaded one at a time, so
+    /// only the current row is held in memory. At most `limit` rows are
+    /// produced after skipping the first `offset` matches, so a large result
+    /// can be fetched page by page; as long as the collection is not modified
+    /// in between, every row will be produced exactly once. Returns the number
+    /// of rows produced.
+    pub fn for_each_note_row_in_search(
+        &mut self,
+        request: NoteRowsForSearchRequest,
+        mut func: impl FnMut(Vec<String>) -> Result<()>,
+    ) -> Result<usize> {
+        let order = request.order.unwrap_or_default().value.try_into()?;
+        let limit = match request.limit {
+            0 => usize::MAX,
+            n => n as usize,
+        };
+        let nids: Vec<_> = self
+            .search_notes(request.search.as_str(), order)?
+            .into_iter()
+            .skip(request.offset as usize)
+            .take(limit)
+            .collect();
+        let deck_ids = self
+            .storage
+            .with_ids_in_searched_notes_table(&nids, || self.storage.all_decks_of_search_notes())?;
+        let deck_names = HashMap::<DeckId, String>::from_iter(self.storage.get_all_deck_names()?);
+
+        for &nid in &nids {
+            let note = self.storage.get_note(nid)?.or_not_found(nid)?;
+            let notetype = self
+                .get_notetype(note.notetype_id)?
+                .or_not_found(note.notetype_id)?;
+            let row = request
+                .columns
+                .iter()
+                .map(|column| match &column.kind {
+                    Some(RowColumn::Field(name)) => notetype
+                        .get_field_ord(name)
+                        .and_then(|ord| note.fields().get(ord))
+                        .map(|field| field_to_record_field(field, request.with_html).into())
+                        .unwrap_or_default(),
+                    Some(RowColumn::Deck(_)) => deck_ids
+                        .get(&nid)
+                        .and_then(|did| deck_names.get(did))
+                        .cloned()
+                        .unwrap_or_default(),
+                    Some(RowColumn::Notetype(_)) => notetype.name.clone(),
+                    Some(RowColumn::Tags(_)) => note.tags.join(" "),
+                    None => String::new(),
+                })
+                .collect();
+            func(row)?;
+        }
+
+        Ok(nids.len())
+    }
+
     fn card_record(&mut self, card: CardId, with_html: bool) -> Result<[String; 2]> {
         let RenderCardOutput { qnodes, anodes, .. } =
             self.render_existing_card(card, false, false)?;
@@ -282,3 +342,101 @@ impl From<&mut ExportNoteCsvRequest> for SearchNode {
         SearchNode::from(req.limit.take().unwrap_or_default())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use anki_proto::import_export::note_rows_for_search_request::Column;
+    use anki_proto::search::sort_order::Builtin;
+    use anki_proto::search::sort_order::Value;
+    use anki_proto::search::SortOrder;
+
+    use super::*;
+    use crate::services::ImportExportService;
+    use crate::tests::DeckAdder;
+    use crate::tests::NoteAdder;
+
+    fn column(kind: RowColumn) -> Column {
+        Column { kind: Some(kind) }
+    }
+
+    fn request(offset: u32, limit: u32) -> NoteRowsForSearchRequest {
+        NoteRowsForSearchRequest {
+            search: "".into(),
+            order: Some(SortOrder {
+                value: Some(Value::Builtin(Builtin {
+                    column: "noteFld".into(),
+                    ..Default::default()
+                })),
+            }),
+            columns: vec![
+                column(RowColumn::Field("Front".into())),
+                column(RowColumn::Deck(Default::default())),
+                column(RowColumn::Notetype(Default::default())),
+                column(RowColumn::Tags(Default::default())),
+            ],
+            with_html: false,
+            offset,
+            limit,
+        }
+    }
+
+    fn add_notes(col: &mut Collection) -> Result<()> {
+        let deck = DeckAdder::new("Deck").add(col);
+        let mut note = NoteAdder::basic(col).fields(&["<b>b</b>", ""]).note();
+        note.tags = vec!["x".into(), "y".into()];
+        col.add_note(&mut note, deck.id)?;
+        NoteAdder::basic(col).fields(&["a", ""]).add(col);
+        NoteAdder::cloze(col).fields(&["c {{c1::d}}", ""]).add(col);
+        Ok(())
+    }
+
+    fn rows(col: &mut Collection, request: NoteRowsForSearchRequest) -> Result<Vec<Vec<String>>> {
+        let mut rows = vec![];
+        col.for_each_note_row_in_search(request, |row| {
+            rows.push(row);
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    #[test]
+    fn note_rows_follow_search_order() -> Result<()> {
+        let mut col = Collection::new();
+        add_notes(&mut col)?;
+
+        // the cloze notetype has no Front field, so its cell is empty
+        assert_eq!(
+            rows(&mut col, request(0, 0))?,
+            [
+                ["a", "Default", "Basic", ""],
+                ["b", "Deck", "Basic", "x y"],
+                ["", "Default", "Cloze", ""],
+            ]
+        );
+        assert_eq!(
+            rows(&mut col, request(1, 1))?,
+            [["b", "Deck", "Basic", "x y"]]
+        );
+        assert!(rows(&mut col, request(3, 0))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn note_rows_rpc_reports_more_rows() -> Result<()> {
+        let mut col = Collection::new();
+        add_notes(&mut col)?;
+
+        let page = ImportExportService::note_rows_for_search(&mut col, request(0, 2))?;
+        assert_eq!(page.rows.len(), 2);
+        assert!(page.has_more);
+        let page = ImportExportService::note_rows_for_search(&mut col, request(2, 2))?;
+        assert_eq!(page.rows[0].cells[2], "Cloze");
+        assert!(!page.has_more);
+        let page = ImportExportService::note_rows_for_search(&mut col, request(0, 0))?;
+        assert_eq!(page.rows.len(), 3);
+        assert!(!page.has_more);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright: Ankitects Pty Ltd and contributors
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
+use crate::findreplace::FindReplaceDescription;
 use crate::prelude::*;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,7 +17,7 @@ pub enum Op {
     ClearUnusedTags,
     CreateCustomStudy,
     EmptyFilteredDeck,
-    FindAndReplace,
+    FindAndReplace(Option<FindReplaceDescription>),
     ImageOcclusion,
     Import,
     RebuildFilteredDeck,
@@ -72,7 +73,10 @@ impl Op {
             Op::UpdateTag => tr.actions_update_tag(),
             Op::SetCardDeck => tr.browsing_change_deck(),
             Op::SetFlag => tr.actions_set_flag(),
-            Op::FindAndReplace => tr.browsing_find_and_replace(),
+            Op::FindAndReplace(None) => tr.browsing_find_and_replace(),
+            Op::FindAndReplace(Some(desc)) => {
+                tr.browsing_find_and_replace_described(&desc.search, &desc.replacement)
+            }
             Op::ClearUnusedTags => tr.browsing_clear_unused_tags(),
             Op::SortCards => tr.browsing_reschedule(),
             Op::RenameTag => tr.actions_rename_tag(),
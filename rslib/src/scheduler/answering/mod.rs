@@ -26,12 +26,14 @@ use super::timespan::answer_button_time_collapsible;
 use super::timing::SchedTimingToday;
 use crate::card::CardQueue;
 use crate::card::CardType;
+use crate::config::StringKey;
 use crate::deckconfig::DeckConfig;
 use crate::deckconfig::LeechAction;
 use crate::decks::Deck;
 use crate::prelude::*;
 use crate::scheduler::fsrs::memory_state::single_card_revlog_to_item;
 use crate::search::SearchNode;
+use crate::search::SortMode;
 
 #[derive(Copy, Clone)]
 pub enum Rating {
@@ -267,6 +269,39 @@ impl Collection {
         ])
     }
 
+    /// Return the interval in seconds that each card matching the search
+    /// would get if it were answered with `rating` now, using the same
+    /// calculation and fuzz as answering. Cards are not modified. New and
+    /// learning cards report the delay until their next step, unless it
+    /// would pass the next day rollover, in which case it's rounded up to
+    /// whole days as it is on the answer buttons.
+    pub fn project_intervals(
+        &mut self,
+        search: impl TryIntoSearch,
+        rating: Rating,
+    ) -> Result<Vec<(CardId, u32)>> {
+        let now = TimestampSecs::now();
+        let timing = self.timing_for_timestamp(now)?;
+        let secs_until_rollover = timing.next_day_at.elapsed_secs_since(now).max(0) as u32;
+        self.search_cards(search, SortMode::NoOrder)?
+            .into_iter()
+            .map(|cid| {
+                let states = self.get_scheduling_states(cid)?;
+                let state = match rating {
+                    Rating::Again => states.again,
+                    Rating::Hard => states.hard,
+                    Rating::Good => states.good,
+                    Rating::Easy => states.easy,
+                };
+                let secs = state
+                    .interval_kind()
+                    .maybe_as_days(secs_until_rollover)
+                    .as_seconds();
+                Ok((cid, secs))
+            })
+            .collect()
+    }
+
     /// Answer card, writing its new state to the database.
     /// Provided [CardAnswer] has its answer time capped to deck preset.
     pub fn answer_card(&mut self, answer: &mut CardAnswer) -> Result<OpOutput<()>> {
@@ -432,7 +467,8 @@ impl Collection {
     }
 
     fn add_leech_tag(&mut self, nid: NoteId) -> Result<()> {
-        self.add_tags_to_notes_inner(&[nid], "leech")?;
+        let tag = self.get_config_string(StringKey::LeechTag);
+        self.add_tags_to_notes_inner(&[nid], &tag)?;
         Ok(())
     }
 
@@ -562,12 +598,36 @@ mod test {
     use super::*;
     use crate::card::CardType;
     use crate::deckconfig::ReviewMix;
-    use crate::search::SortMode;
 
     fn current_state(col: &mut Collection, card_id: CardId) -> CardState {
         col.get_scheduling_states(card_id).unwrap().current
     }
 
+    #[test]
+    fn projected_intervals() -> Result<()> {
+        let mut col = Collection::new();
+        if col.timing_today()?.near_cutoff() {
+            return Ok(());
+        }
+        let nt = col.get_notetype_by_name("Basic")?.unwrap();
+        let mut note = nt.new_note();
+        col.add_note(&mut note, DeckId(1))?;
+        let cid = col.search_cards("", SortMode::NoOrder)?[0];
+        let card = col.storage.get_card(cid)?.unwrap();
+
+        // default learning steps are 1m 10m, and easy graduates with a fuzzed
+        // 4 days
+        assert_eq!(col.project_intervals("", Rating::Again)?, [(cid, 60)]);
+        assert_eq!(col.project_intervals("", Rating::Good)?, [(cid, 600)]);
+        let easy = col.project_intervals("", Rating::Easy)?[0].1;
+        assert!((3 * 86_400..=5 * 86_400).contains(&easy));
+        assert!(col.project_intervals("is:review", Rating::Good)?.is_empty());
+        // the card itself is untouched
+        assert_eq!(col.storage.get_card(cid)?.unwrap(), card);
+
+        Ok(())
+    }
+
     // make sure the 'current' state for a card matches the
     // state we applied to it
     #[test]
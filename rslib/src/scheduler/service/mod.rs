@@ -215,6 +215,24 @@ impl crate::services::SchedulerService for Collection {
         self.describe_next_states(&states).map(Into::into)
     }
 
+    fn project_intervals(
+        &mut self,
+        input: scheduler::ProjectIntervalsRequest,
+    ) -> Result<scheduler::ProjectIntervalsResponse> {
+        let rating = input.rating().into();
+        let intervals = self
+            .project_intervals(input.search.as_str(), rating)?
+            .into_iter()
+            .map(
+                |(cid, secs)| scheduler::project_intervals_response::Interval {
+                    card_id: cid.0,
+                    secs,
+                },
+            )
+            .collect();
+        Ok(scheduler::ProjectIntervalsResponse { intervals })
+    }
+
     fn state_is_leech(&mut self, input: scheduler::SchedulingState) -> Result<generic::Bool> {
         let state: CardState = input.into();
         Ok(state.leeched().into())
@@ -6,6 +6,7 @@ use std::mem;
 use itertools::Itertools;
 
 use super::writer::write_nodes;
+use super::writer::write_nodes_canonical;
 use super::Node;
 use super::SearchNode;
 use super::StateKind;
@@ -80,6 +81,14 @@ impl SearchBuilder {
         }
     }
 
+    /// Construct [SearchBuilder] with this [Node], unwrapping [Node::Group]s
+    /// whose [Node]s are all joined by `joiner`, so that joining the result
+    /// with `joiner` doesn't produce redundant parentheses. Groups with any
+    /// other joiner are kept, as removing them could change the meaning.
+    pub fn from_root_joined_by(node: Node, joiner: &Node) -> Self {
+        Self(flatten_groups_joined_by(vec![node], joiner))
+    }
+
     /// Construct [SearchBuilder] where given [Node]s are joined by [Node::And].
     pub fn all(iter: impl IntoIterator<Item = impl Into<Node>>) -> Self {
         Self(Itertools::intersperse(iter.into_iter().map(Into::into), Node::And).collect())
@@ -118,10 +127,41 @@ impl SearchBuilder {
         self
     }
 
+    /// Drop [Node]s equal to an earlier one, along with their joiner. This is
+    /// only done if all [Node]s are joined by `joiner`, since a repeated term
+    /// can change the meaning of a search with mixed joiners.
+    pub fn without_duplicates(mut self, joiner: &Node) -> Self {
+        let alternating = self.0.iter().enumerate().all(|(idx, node)| {
+            let is_joiner = matches!(node, Node::And | Node::Or);
+            if idx % 2 == 1 {
+                node == joiner
+            } else {
+                !is_joiner
+            }
+        });
+        if alternating {
+            let mut terms: Vec<Node> = vec![];
+            for node in self.0.into_iter().step_by(2) {
+                if !terms.contains(&node) {
+                    terms.push(node);
+                }
+            }
+            self.0 = Itertools::intersperse(terms.into_iter(), joiner.clone()).collect();
+        }
+        self
+    }
+
     pub fn write(&self) -> String {
         write_nodes(&self.0)
     }
 
+    /// Like [SearchBuilder::write], but terms joined by the same operator are
+    /// sorted and redundant parentheses dropped, so reordered copies of a
+    /// search, eg saved searches, give the same string.
+    pub fn write_canonical(&self) -> String {
+        write_nodes_canonical(&self.0)
+    }
+
     /// Construct [SearchBuilder] matching any given deck, excluding children.
     pub fn from_decks(decks: &[DeckId]) -> Self {
         SearchNode::DeckIdsWithoutChildren(comma_separated_ids(decks)).into()
@@ -138,6 +178,23 @@ impl SearchBuilder {
     }
 }
 
+fn flatten_groups_joined_by(nodes: Vec<Node>, joiner: &Node) -> Vec<Node> {
+    let mut flattened = vec![];
+    for node in nodes {
+        match node {
+            Node::Group(inner)
+                if inner
+                    .iter()
+                    .all(|n| !matches!(n, Node::And | Node::Or) || n == joiner) =>
+            {
+                flattened.extend(flatten_groups_joined_by(inner, joiner))
+            }
+            _ => flattened.push(node),
+        }
+    }
+    flattened
+}
+
 impl<T: Into<Node>> From<T> for SearchBuilder {
     fn from(node: T) -> Self {
         Self(vec![node.into()])
@@ -281,4 +338,55 @@ mod test {
             "is:due OR is:new OR is:learn OR is:review"
         );
     }
+
+    #[test]
+    fn flattening_groups() {
+        let flat_or = |node: SearchBuilder, other: SearchBuilder| {
+            let node = node.try_into_search().unwrap();
+            let other = other.try_into_search().unwrap();
+            SearchBuilder::from_root_joined_by(node, &Node::Or)
+                .or_flat(SearchBuilder::from_root_joined_by(other, &Node::Or))
+                .write()
+        };
+        let flat_and = |node: SearchBuilder, other: SearchBuilder| {
+            let node = node.try_into_search().unwrap();
+            let other = other.try_into_search().unwrap();
+            SearchBuilder::from_root_joined_by(node, &Node::And)
+                .and_flat(SearchBuilder::from_root_joined_by(other, &Node::And))
+                .write()
+        };
+
+        // matching joiners are flattened, including nested groups
+        assert_eq!(
+            flat_or(
+                StateKind::Due.or(StateKind::New.or(StateKind::Learning)),
+                StateKind::Review.into()
+            ),
+            "is:due OR is:new OR is:learn OR is:review"
+        );
+        assert_eq!(
+            flat_and(StateKind::Due.and(StateKind::New), StateKind::Review.into()),
+            "is:due is:new is:review"
+        );
+        // other joiners keep their parentheses, and so do groups nested inside
+        // them
+        assert_eq!(
+            flat_or(StateKind::Due.and(StateKind::New), StateKind::Review.into()),
+            "(is:due is:new) OR is:review"
+        );
+        assert_eq!(
+            flat_and(
+                StateKind::Due.or(StateKind::New),
+                StateKind::Review.and(StateKind::Learning.or(StateKind::Buried))
+            ),
+            "(is:due OR is:new) is:review (is:learn OR is:buried)"
+        );
+        assert_eq!(
+            flat_or(
+                StateKind::Due.and(StateKind::New.or(StateKind::Learning)),
+                StateKind::Review.into()
+            ),
+            "(is:due (is:new OR is:learn)) OR is:review"
+        );
+    }
 }
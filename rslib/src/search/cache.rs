@@ -0,0 +1,94 @@
+// Copyright: Ankitects Pty Ltd and contributors
+// License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
+
+use std::collections::VecDeque;
+
+use super::parser::parse;
+use super::Node;
+use crate::prelude::*;
+
+const CAPACITY: usize = 32;
+
+/// Recently parsed search texts. Only the parsed nodes are kept: deck,
+/// notetype and other names are resolved when the SQL is built, so changes to
+/// the collection can't make an entry stale, and nothing needs invalidating.
+#[derive(Debug, Default)]
+pub(crate) struct ParsedSearchCache {
+    /// Least recently used first.
+    entries: VecDeque<(String, Node)>,
+}
+
+impl ParsedSearchCache {
+    fn get_or_parse(&mut self, text: &str) -> Result<Node> {
+        if let Some(idx) = self.entries.iter().position(|(entry, _)| entry == text) {
+            let entry = self.entries.remove(idx).unwrap();
+            let node = entry.1.clone();
+            self.entries.push_back(entry);
+            return Ok(node);
+        }
+        let node = Node::Group(parse(text)?);
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((text.into(), node.clone()));
+        Ok(node)
+    }
+}
+
+impl Collection {
+    /// Parse a search typed by the user, reusing the result if the same text
+    /// was searched for recently, as happens when the browser searches on
+    /// each keystroke.
+    pub(crate) fn parse_search_cached(&mut self, text: &str) -> Result<Node> {
+        self.state.search_cache.get_or_parse(text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::search::SortMode;
+    use crate::tests::DeckAdder;
+    use crate::tests::NoteAdder;
+
+    #[test]
+    fn least_recently_used_search_is_dropped() -> Result<()> {
+        let mut cache = ParsedSearchCache::default();
+        for i in 0..CAPACITY {
+            cache.get_or_parse(&format!("nid:{i}"))?;
+        }
+        // using the oldest entry again keeps it
+        cache.get_or_parse("nid:0")?;
+        cache.get_or_parse("extra")?;
+        let texts: Vec<_> = cache
+            .entries
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect();
+        assert_eq!(texts.len(), CAPACITY);
+        assert!(!texts.contains(&"nid:1"));
+        assert_eq!(&texts[CAPACITY - 2..], ["nid:0", "extra"]);
+
+        // errors aren't cached
+        assert!(cache.get_or_parse("(").is_err());
+        assert_eq!(cache.entries.len(), CAPACITY);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cached_search_follows_renamed_deck() -> Result<()> {
+        let mut col = Collection::new();
+        let mut deck = DeckAdder::new("old").add(&mut col);
+        NoteAdder::basic(&mut col).deck(deck.id).add(&mut col);
+        let search = col.parse_search_cached("deck:old")?;
+        assert_eq!(col.search_cards(search, SortMode::NoOrder)?.len(), 1);
+
+        deck.name = NativeDeckName::from_human_name("new");
+        col.update_deck(&mut deck)?;
+        let search = col.parse_search_cached("deck:old")?;
+        assert!(col.search_cards(search, SortMode::NoOrder)?.is_empty());
+
+        Ok(())
+    }
+}
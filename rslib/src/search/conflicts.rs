@@ -0,0 +1,163 @@
+// Copyright: Ankitects Pty Ltd and contributors
+// License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
+
+use super::parser::Node;
+use super::parser::PropertyKind;
+use super::parser::SearchNode;
+use super::parser::StateKind;
+use super::writer::write_nodes;
+
+/// A term every match must satisfy, or one no match may satisfy.
+enum Term<'a> {
+    Required(&'a SearchNode),
+    Excluded(&'a SearchNode),
+}
+
+/// Pairs of terms, as they would be written, that the search requires but
+/// that no card can satisfy together, eg `is:new` and `prop:ivl>0`. Only terms
+/// joined by "and" are compared, and only combinations that can be shown to
+/// exclude each other are reported, so the search may match nothing even when
+/// the list is empty.
+pub(crate) fn contradictory_terms(nodes: &[Node]) -> Vec<(String, String)> {
+    let mut terms = vec![];
+    add_required_terms(nodes, &mut terms);
+    let mut conflicts = vec![];
+    for (idx, first) in terms.iter().enumerate() {
+        for second in &terms[idx + 1..] {
+            if terms_conflict(first, second) {
+                conflicts.push((write_term(first), write_term(second)));
+            }
+        }
+    }
+    conflicts
+}
+
+fn add_required_terms<'a>(nodes: &'a [Node], terms: &mut Vec<Term<'a>>) {
+    // with an "or" at this level, none of its terms is required on its own
+    if nodes.iter().any(|node| matches!(node, Node::Or)) {
+        return;
+    }
+    for node in nodes {
+        match node {
+            Node::Search(search) => terms.push(Term::Required(search)),
+            Node::Not(inner) => {
+                if let Node::Search(search) = inner.as_ref() {
+                    terms.push(Term::Excluded(search));
+                }
+            }
+            Node::Group(inner) => add_required_terms(inner, terms),
+            Node::And | Node::Or => (),
+        }
+    }
+}
+
+fn write_term(term: &Term) -> String {
+    match term {
+        Term::Required(search) => write_nodes(&[Node::Search((*search).clone())]),
+        Term::Excluded(search) => {
+            write_nodes(&[Node::Not(Box::new(Node::Search((*search).clone())))])
+        }
+    }
+}
+
+fn terms_conflict(first: &Term, second: &Term) -> bool {
+    match (first, second) {
+        (Term::Required(first), Term::Required(second)) => {
+            searches_conflict(first, second) || searches_conflict(second, first)
+        }
+        (Term::Required(required), Term::Excluded(excluded))
+        | (Term::Excluded(excluded), Term::Required(required)) => required == excluded,
+        (Term::Excluded(_), Term::Excluded(_)) => false,
+    }
+}
+
+/// Only checked in one direction; the caller tries both.
+fn searches_conflict(first: &SearchNode, second: &SearchNode) -> bool {
+    match (first, second) {
+        (SearchNode::State(first), SearchNode::State(second)) => states_conflict(first, second),
+        // new cards have no interval
+        (
+            SearchNode::State(StateKind::New),
+            SearchNode::Property {
+                operator,
+                kind: PropertyKind::Interval { days, .. },
+            },
+        ) => match operator.as_str() {
+            ">" => true,
+            ">=" | "=" => *days > 0,
+            _ => false,
+        },
+        (SearchNode::Flag(first), SearchNode::Flag(second)) => {
+            !first.iter().any(|flag| second.contains(flag))
+        }
+        (SearchNode::AnyFlag, SearchNode::Flag(flags)) => flags == &[0],
+        _ => false,
+    }
+}
+
+/// Based on the card types and queues each state matches.
+fn states_conflict(first: &StateKind, second: &StateKind) -> bool {
+    use StateKind::*;
+    matches!(
+        (first, second),
+        (New, Review | Learning)
+            | (Due, Scheduled)
+            | (UserBuried, SchedBuried)
+            | (Suspended | NoteSuspended, Buried | UserBuried | SchedBuried)
+            | (
+                Suspended | NoteSuspended | Buried | UserBuried | SchedBuried,
+                Due | Scheduled | Overdue
+            )
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::search::parser::parse;
+
+    fn conflicts(search: &str) -> Vec<(String, String)> {
+        contradictory_terms(&parse(search).unwrap())
+    }
+
+    fn pair(first: &str, second: &str) -> Vec<(String, String)> {
+        vec![(first.into(), second.into())]
+    }
+
+    #[test]
+    fn contradictions() {
+        assert_eq!(conflicts("is:new prop:ivl>0"), pair("is:new", "prop:ivl>0"));
+        assert_eq!(
+            conflicts("prop:ivl>=1w deck:x is:new"),
+            pair("prop:ivl>=1w", "is:new")
+        );
+        assert_eq!(conflicts("is:review (is:new)"), pair("is:review", "is:new"));
+        assert_eq!(
+            conflicts("is:suspended is:due"),
+            pair("is:suspended", "is:due")
+        );
+        assert_eq!(conflicts("flag:1,2 flag:3"), pair("flag:1,2", "flag:3"));
+        assert_eq!(conflicts("flag:any flag:0"), pair("flag:any", "flag:0"));
+        assert_eq!(conflicts("tag:x front:y -tag:x"), pair("tag:x", "-tag:x"));
+        assert_eq!(conflicts("is:new is:review is:learn").len(), 2);
+    }
+
+    #[test]
+    fn satisfiable_combinations() {
+        for search in [
+            "is:new prop:ivl>=0",
+            "is:new prop:ivl=0",
+            "is:review is:learn",
+            "is:buried is:userburied",
+            "flag:1,2 flag:2",
+            "flag:any flag:0,1",
+            "-tag:x -tag:x",
+            // only one side of an "or" needs to match
+            "is:new or prop:ivl>0",
+            "is:new (is:review or tag:x)",
+            "-(is:new is:review)",
+        ] {
+            assert_eq!(conflicts(search), vec![], "{search}");
+        }
+    }
+}
@@ -2,17 +2,25 @@
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
 mod builder;
+pub(crate) mod cache;
+mod conflicts;
 mod parser;
-mod service;
+pub(crate) mod service;
 mod sqlwriter;
 pub(crate) mod writer;
 
 use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::str::FromStr;
 
 pub use builder::JoinSearches;
 pub use builder::Negated;
 pub use builder::SearchBuilder;
+pub(crate) use conflicts::contradictory_terms;
 pub use parser::parse as parse_search;
+pub use parser::IntervalUnit;
 pub use parser::Node;
 pub use parser::PropertyKind;
 pub use parser::RatingKind;
@@ -23,12 +31,14 @@ use rusqlite::params_from_iter;
 use rusqlite::types::FromSql;
 use sqlwriter::RequiredTable;
 use sqlwriter::SqlWriter;
+pub use writer::remove_search_node;
 pub use writer::replace_search_node;
 
 use crate::browser_table::Column;
 use crate::card::CardType;
 use crate::prelude::*;
 use crate::scheduler::timing::SchedTimingToday;
+use crate::text::strip_html_preserving_media_filenames;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ReturnItemType {
@@ -39,7 +49,28 @@ pub enum ReturnItemType {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum SortMode {
     NoOrder,
-    Builtin { column: Column, reverse: bool },
+    Builtin {
+        column: Column,
+        reverse: bool,
+    },
+    /// Most answers in the last `days` days first, and unreviewed items
+    /// last, unless reversed.
+    RecentReviews {
+        days: u32,
+        reverse: bool,
+    },
+    /// Like sorting by [Column::SortField], but numbers in the field are
+    /// compared by value.
+    NumericSortField {
+        reverse: bool,
+    },
+    /// A shuffled order that is the same each time the same seed is used,
+    /// unlike `order by random()`.
+    Random {
+        seed: u64,
+    },
+    /// Spliced into the query as-is, so must not come from user input. See
+    /// [SortMode::from_column_key] instead.
     Custom(String),
 }
 
@@ -69,10 +100,23 @@ impl ReturnItemType {
 }
 
 impl SortMode {
+    /// Sort by the browser column with the given key, eg "noteCrt". Unknown
+    /// keys are rejected rather than falling back on another order.
+    pub fn from_column_key(key: &str, reverse: bool) -> Result<Self> {
+        let column = Column::from_str(key)
+            .ok()
+            .filter(|column| *column != Column::Custom)
+            .or_invalid(format!("unknown sort column: {key}"))?;
+        Ok(SortMode::Builtin { column, reverse })
+    }
+
     fn required_table(&self) -> RequiredTable {
         match self {
             SortMode::NoOrder => RequiredTable::CardsOrNotes,
             SortMode::Builtin { column, .. } => column.required_table(),
+            SortMode::RecentReviews { .. } => RequiredTable::CardsOrNotes,
+            SortMode::NumericSortField { .. } => RequiredTable::Notes,
+            SortMode::Random { .. } => RequiredTable::CardsOrNotes,
             SortMode::Custom(ref text) => {
                 if text.contains("n.") {
                     if text.contains("c.") {
@@ -168,12 +212,215 @@ impl Collection {
         self.search(search, mode)
     }
 
+    /// Like [Collection::search_cards], but skips the first `offset` cards
+    /// and returns at most `limit` of the rest. Both are applied after
+    /// sorting.
+    pub fn search_cards_page<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<CardId>>
+    where
+        N: TryIntoSearch,
+    {
+        self.search_page(search, mode, limit, offset, false)
+            .map(|(ids, _)| ids)
+    }
+
+    /// Like [Collection::search_cards_page], but also returns the number of
+    /// cards the search matches before `limit` and `offset` are applied.
+    pub fn search_cards_page_with_total<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<(Vec<CardId>, usize)>
+    where
+        N: TryIntoSearch,
+    {
+        self.search_page(search, mode, limit, offset, true)
+            .map(|(ids, total)| (ids, total.unwrap_or_default()))
+    }
+
+    /// Like [Collection::search_notes], but skips the first `offset` notes
+    /// and returns at most `limit` of the rest. Both are applied after
+    /// sorting.
+    pub fn search_notes_page<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<NoteId>>
+    where
+        N: TryIntoSearch,
+    {
+        self.search_page(search, mode, limit, offset, false)
+            .map(|(ids, _)| ids)
+    }
+
+    /// Like [Collection::search_notes_page], but also returns the number of
+    /// notes the search matches before `limit` and `offset` are applied.
+    pub fn search_notes_page_with_total<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<(Vec<NoteId>, usize)>
+    where
+        N: TryIntoSearch,
+    {
+        self.search_page(search, mode, limit, offset, true)
+            .map(|(ids, total)| (ids, total.unwrap_or_default()))
+    }
+
+    /// Like [Collection::search_notes], but notes that are duplicates of one
+    /// another (see `dupe:`) are returned once, as the oldest of them, in the
+    /// position that note would have had. Only duplicates within the results
+    /// are considered, and notes with an empty first field are kept.
+    pub fn search_notes_without_duplicates<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+    ) -> Result<Vec<NoteId>>
+    where
+        N: TryIntoSearch,
+    {
+        let nids = self.search_notes(search, mode)?;
+        let fields = self.storage.with_ids_in_searched_notes_table(&nids, || {
+            self.storage.first_fields_of_searched_notes()
+        })?;
+        let mut oldest: HashMap<(NotetypeId, String), NoteId> = HashMap::new();
+        let mut duplicates = HashSet::new();
+        for (nid, ntid, field) in fields {
+            let stripped = strip_html_preserving_media_filenames(&field);
+            if stripped.trim().is_empty() {
+                continue;
+            }
+            match oldest.entry((ntid, stripped.into_owned())) {
+                Entry::Occupied(mut entry) => {
+                    let newer = if nid < *entry.get() {
+                        entry.insert(nid)
+                    } else {
+                        nid
+                    };
+                    duplicates.insert(newer);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(nid);
+                }
+            }
+        }
+        Ok(nids
+            .into_iter()
+            .filter(|nid| !duplicates.contains(nid))
+            .collect())
+    }
+
     pub fn search_notes_unordered<N>(&mut self, search: N) -> Result<Vec<NoteId>>
     where
         N: TryIntoSearch,
     {
         self.search(search, SortMode::NoOrder)
     }
+
+    /// The number of cards [Collection::search_cards] would return, counted
+    /// without fetching their ids.
+    pub fn count_cards<N>(&mut self, search: N) -> Result<usize>
+    where
+        N: TryIntoSearch,
+    {
+        self.count_matches(search, ReturnItemType::Cards)
+    }
+
+    /// The number of notes [Collection::search_notes] would return.
+    pub fn count_notes<N>(&mut self, search: N) -> Result<usize>
+    where
+        N: TryIntoSearch,
+    {
+        self.count_matches(search, ReturnItemType::Notes)
+    }
+
+    /// Returns the statement and arguments [Collection::search_cards_page]
+    /// would use, without running it. Some searches, like dupe: and deck:,
+    /// look up ids while the statement is built, and those ids are included.
+    pub fn explain_search<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<(String, Vec<String>)>
+    where
+        N: TryIntoSearch,
+    {
+        self.build_search_sql(search, mode, ReturnItemType::Cards, limit, offset)
+    }
+
+    /// Calls `func` with the id of each matching card, in `mode` order, as
+    /// they are read from the database, so that large results don't need to
+    /// be held in memory. Stops at the first error. Returns the number of
+    /// cards passed to `func`.
+    pub fn for_each_card_id_in_search<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        mut func: impl FnMut(CardId) -> Result<()>,
+    ) -> Result<usize>
+    where
+        N: TryIntoSearch,
+    {
+        let (sql, args) = self.build_search_sql(search, mode, ReturnItemType::Cards, None, None)?;
+        let mut stmt = self.storage.db.prepare(&sql)?;
+        let mut rows = stmt.query(params_from_iter(args.iter()))?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            func(row.get(0)?)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns the notes of the cards matching a card search, each note
+    /// appearing once at the position of its first matching card in `mode`
+    /// order. Unlike [Collection::search_notes], the order comes from the
+    /// cards, and card columns can be used to sort. Cards are ordered as
+    /// [Collection::search_cards] orders them, and all cards are taken in card
+    /// id order if there is no order.
+    pub fn note_ids_of_card_search<N>(&mut self, search: N, mode: SortMode) -> Result<Vec<NoteId>>
+    where
+        N: TryIntoSearch,
+    {
+        let mode = self.card_order_with_id_tiebreak(mode)?;
+        let guard = self.search_cards_into_table(search, mode)?;
+        guard.col.storage.note_ids_of_searched_cards()
+    }
+
+    /// Like [Collection::search_cards], but if `group_by_note` is true, only
+    /// the first matching card of each note in `mode` order is returned.
+    /// Cards are ordered as [Collection::search_cards] orders them, and any
+    /// still tied are taken in card id order, so the same card represents its
+    /// note each time.
+    pub fn search_cards_grouped<N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        group_by_note: bool,
+    ) -> Result<Vec<CardId>>
+    where
+        N: TryIntoSearch,
+    {
+        if !group_by_note {
+            return self.search_cards(search, mode);
+        }
+        let mode = self.card_order_with_id_tiebreak(mode)?;
+        let guard = self.search_cards_into_table(search, mode)?;
+        guard.col.storage.first_card_of_each_note_in_search()
+    }
 }
 
 impl Collection {
@@ -182,19 +429,83 @@ impl Collection {
         N: TryIntoSearch,
         T: FromSql + AsReturnItemType,
     {
-        let item_type = T::as_return_item_type();
-        let top_node = search.try_into_search()?;
-        let writer = SqlWriter::new(self, item_type);
+        self.search_page(search, mode, None, None, false)
+            .map(|(ids, _)| ids)
+    }
 
-        let (mut sql, args) = writer.build_query(&top_node, mode.required_table())?;
+    /// If `with_total` is true, the unpaged number of matches is also
+    /// returned. It is counted with the same query, before ordering and
+    /// paging are added.
+    fn search_page<T, N>(
+        &mut self,
+        search: N,
+        mode: SortMode,
+        limit: Option<u32>,
+        offset: Option<u32>,
+        with_total: bool,
+    ) -> Result<(Vec<T>, Option<usize>)>
+    where
+        N: TryIntoSearch,
+        T: FromSql + AsReturnItemType,
+    {
+        let item_type = T::as_return_item_type();
+        let (mut sql, args) = self.build_unordered_search_sql(search, &mode, item_type)?;
+        let total = if with_total {
+            Some(self.count_rows(&sql, &args)?)
+        } else {
+            None
+        };
         self.add_order(&mut sql, item_type, mode)?;
-
+        add_page(&mut sql, limit, offset);
         let mut stmt = self.storage.db.prepare(&sql)?;
         let ids: Vec<_> = stmt
             .query_map(params_from_iter(args.iter()), |row| row.get(0))?
             .collect::<std::result::Result<_, _>>()?;
 
-        Ok(ids)
+        Ok((ids, total))
+    }
+
+    fn count_matches(
+        &mut self,
+        search: impl TryIntoSearch,
+        item_type: ReturnItemType,
+    ) -> Result<usize> {
+        let (sql, args) = self.build_unordered_search_sql(search, &SortMode::NoOrder, item_type)?;
+        self.count_rows(&sql, &args)
+    }
+
+    fn count_rows(&self, sql: &str, args: &[String]) -> Result<usize> {
+        Ok(self.storage.db.query_row(
+            &format!("select count() from ({sql})"),
+            params_from_iter(args.iter()),
+            |row| row.get(0),
+        )?)
+    }
+
+    fn build_search_sql(
+        &mut self,
+        search: impl TryIntoSearch,
+        mode: SortMode,
+        item_type: ReturnItemType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<(String, Vec<String>)> {
+        let (mut sql, args) = self.build_unordered_search_sql(search, &mode, item_type)?;
+        self.add_order(&mut sql, item_type, mode)?;
+        add_page(&mut sql, limit, offset);
+        Ok((sql, args))
+    }
+
+    /// The tables `mode` needs are joined, but the order is not added.
+    fn build_unordered_search_sql(
+        &mut self,
+        search: impl TryIntoSearch,
+        mode: &SortMode,
+        item_type: ReturnItemType,
+    ) -> Result<(String, Vec<String>)> {
+        let top_node = search.try_into_search()?;
+        let writer = SqlWriter::new(self, item_type);
+        writer.build_query(&top_node, mode.required_table())
     }
 
     fn add_order(
@@ -203,19 +514,61 @@ impl Collection {
         item_type: ReturnItemType,
         mode: SortMode,
     ) -> Result<()> {
-        match mode {
-            SortMode::NoOrder => (),
+        let order = self.order_clause(&mode, item_type)?;
+        if !order.is_empty() {
+            sql.push_str(" order by ");
+            sql.push_str(&order);
+        }
+        Ok(())
+    }
+
+    /// The order by clause for `mode`, or an empty string if there is none.
+    /// Builtin sorts of cards, and sorts by recent reviews, then order items
+    /// that sort equally by id, in the direction of the sort.
+    fn order_clause(&mut self, mode: &SortMode, item_type: ReturnItemType) -> Result<String> {
+        let mut order = String::new();
+        let reverse = match *mode {
+            SortMode::NoOrder => return Ok(order),
             SortMode::Builtin { column, reverse } => {
                 prepare_sort(self, column, item_type)?;
-                sql.push_str(" order by ");
-                write_order(sql, item_type, column, reverse, self.timing_today()?)?;
+                write_order(&mut order, item_type, column, reverse, self.timing_today()?)?;
+                reverse
             }
-            SortMode::Custom(order_clause) => {
-                sql.push_str(" order by ");
-                sql.push_str(&order_clause);
+            SortMode::RecentReviews { days, reverse } => {
+                let timing = self.timing_today()?;
+                write_recent_reviews_order(&mut order, item_type, days, reverse, timing);
+                reverse
             }
-        }
-        Ok(())
+            SortMode::NumericSortField { reverse } => {
+                write_numeric_sort_field_order(&mut order, item_type, reverse);
+                reverse
+            }
+            SortMode::Random { seed } => {
+                write_random_order(&mut order, item_type, seed);
+                return Ok(order);
+            }
+            SortMode::Custom(ref clause) => return Ok(clause.clone()),
+        };
+        // keep items that sort equally in a stable order
+        let id = match (item_type, mode) {
+            (ReturnItemType::Cards, _) => "c.id",
+            (ReturnItemType::Notes, SortMode::RecentReviews { .. }) => "n.id",
+            _ => return Ok(order),
+        };
+        order.push_str(&format!(", {id} {}", if reverse { "desc" } else { "asc" }));
+        Ok(order)
+    }
+
+    /// Cards are ordered as [Collection::search_cards] orders them, and any
+    /// that are still tied, or all cards if there is no order, in card id
+    /// order.
+    fn card_order_with_id_tiebreak(&mut self, mode: SortMode) -> Result<SortMode> {
+        let order = self.order_clause(&mode, ReturnItemType::Cards)?;
+        Ok(SortMode::Custom(if order.is_empty() {
+            "c.id asc".into()
+        } else {
+            format!("{order}, c.id asc")
+        }))
     }
 
     /// Place the matched card ids into a temporary 'search_cids' table
@@ -327,6 +680,59 @@ impl Collection {
     }
 }
 
+fn add_page(sql: &mut String, limit: Option<u32>, offset: Option<u32>) {
+    if limit.is_some() || offset.is_some() {
+        // SQLite requires a limit for an offset; a negative one means none
+        let limit = limit.map_or(-1, i64::from);
+        sql.push_str(&format!(" limit {limit} offset {}", offset.unwrap_or(0)));
+    }
+}
+
+/// Manual reschedulings are not counted as reviews.
+fn write_recent_reviews_order(
+    sql: &mut String,
+    item_type: ReturnItemType,
+    days: u32,
+    reverse: bool,
+    timing: SchedTimingToday,
+) {
+    let cutoff = timing
+        .next_day_at
+        .adding_secs(-86_400 * i64::from(days.max(1)))
+        .as_millis();
+    let cards = match item_type {
+        ReturnItemType::Cards => "cid = c.id",
+        ReturnItemType::Notes => "cid in (select id from cards where nid = n.id)",
+    };
+    let direction = if reverse { "asc" } else { "desc" };
+    sql.push_str(&format!(
+        "(select count() from revlog where {cards} and id >= {cutoff} and ease > 0) {direction}"
+    ));
+}
+
+/// Ties between equal hashes are broken by id, so the order is fully
+/// determined by the seed.
+fn write_random_order(sql: &mut String, item_type: ReturnItemType, seed: u64) {
+    let id = match item_type {
+        ReturnItemType::Cards => "c.id",
+        ReturnItemType::Notes => "n.id",
+    };
+    // sqlite integers are signed
+    let seed = seed as i64;
+    sql.push_str(&format!("fnvhash({id}, {seed}), {id}"));
+}
+
+/// Uses the natural collation, which compares runs of digits by value. The
+/// column has integer affinity, so purely numeric fields are stored as
+/// integers, and must be cast so that they aren't all placed before text.
+fn write_numeric_sort_field_order(sql: &mut String, item_type: ReturnItemType, reverse: bool) {
+    let direction = if reverse { "desc" } else { "asc" };
+    sql.push_str(&format!("cast(n.sfld as text) collate natural {direction}"));
+    if item_type == ReturnItemType::Cards {
+        sql.push_str(&format!(", c.ord {direction}"));
+    }
+}
+
 /// Add the order clause to the sql.
 fn write_order(
     sql: &mut String,
@@ -373,15 +779,27 @@ fn card_order_from_sort_column(column: Column, timing: SchedTimingToday) -> Cow<
         Column::Reps => "c.reps asc".into(),
         Column::SortField => "n.sfld collate nocase asc, c.ord asc".into(),
         Column::Tags => "n.tags asc".into(),
+        // the first term has no explicit direction, so reversing the sort keeps
+        // cards that aren't new at the end
+        Column::Position => format!(
+            "c.type != {}, (case when c.odid != 0 then c.odue else c.due end) asc",
+            CardType::New as i8
+        )
+        .into(),
+        Column::OriginalDue => "(case when c.odid != 0 then c.odue else c.due end) asc".into(),
         Column::Answer | Column::Custom | Column::Question => "".into(),
         Column::Stability => "extract_fsrs_variable(c.data, 's') asc".into(),
         Column::Difficulty => "extract_fsrs_variable(c.data, 'd') asc".into(),
-        Column::Retrievability => format!(
-            "extract_fsrs_retrievability(c.data, case when c.odue !=0 then c.odue else c.due end, c.ivl, {}, {}) asc",
-            timing.days_elapsed,
-            timing.next_day_at.0
-        )
-        .into(),
+        // the first term has no explicit direction, so reversing the sort
+        // keeps cards without a memory state at the end
+        Column::Retrievability => {
+            let retrievability = format!(
+                "extract_fsrs_retrievability(c.data, case when c.odue !=0 then c.odue else c.due end, c.ivl, {}, {})",
+                timing.days_elapsed,
+                timing.next_day_at.0
+            );
+            format!("{retrievability} is null, {retrievability} asc").into()
+        }
     }
 }
 
@@ -403,6 +821,8 @@ fn note_order_from_sort_column(column: Column) -> Cow<'static, str> {
         Column::Answer
         | Column::Custom
         | Column::Question
+        | Column::Position
+        | Column::OriginalDue
         | Column::Stability
         | Column::Difficulty
         | Column::Retrievability => "".into(),
@@ -443,9 +863,13 @@ fn prepare_sort(col: &mut Collection, column: Column, item_type: ReturnItemType)
 #[cfg(test)]
 mod test {
     use anki_proto::search::browser_columns::Sorting;
+    use rusqlite::params;
     use strum::IntoEnumIterator;
 
     use super::*;
+    use crate::card::CardQueue;
+    use crate::card::FsrsMemoryState;
+    use crate::tests::NoteAdder;
 
     impl SchedTimingToday {
         pub(crate) fn zero() -> Self {
@@ -471,4 +895,610 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn paging() -> Result<()> {
+        let mut col = Collection::new();
+        let nids: Vec<_> = (0..5)
+            .map(|_| NoteAdder::basic(&mut col).add(&mut col).id)
+            .collect();
+        let newest_first = || SortMode::Builtin {
+            column: Column::NoteCreation,
+            reverse: true,
+        };
+        let page = |col: &mut Collection, limit, offset| {
+            col.search_notes_page("", newest_first(), limit, offset)
+        };
+
+        assert_eq!(page(&mut col, Some(2), None)?, [nids[4], nids[3]]);
+        assert_eq!(page(&mut col, Some(2), Some(2))?, [nids[2], nids[1]]);
+        assert_eq!(page(&mut col, None, Some(4))?, [nids[0]]);
+        assert!(page(&mut col, Some(2), Some(10))?.is_empty());
+        assert_eq!(
+            page(&mut col, None, None)?,
+            col.search_notes("", newest_first())?
+        );
+        let cids = col.search_cards("", newest_first())?;
+        assert_eq!(
+            col.search_cards_page("", newest_first(), Some(1), Some(1))?,
+            [cids[1]]
+        );
+
+        // the total ignores paging
+        assert_eq!(
+            col.search_notes_page_with_total("", newest_first(), Some(2), Some(1))?,
+            (vec![nids[3], nids[2]], 5)
+        );
+        assert_eq!(
+            col.search_cards_page_with_total(
+                format!("nid:{},{}", nids[0], nids[1]).as_str(),
+                newest_first(),
+                Some(1),
+                None
+            )?,
+            (vec![cids[3]], 2)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn explain_search() -> Result<()> {
+        let mut col = Collection::new();
+        NoteAdder::basic(&mut col)
+            .fields(&["foo", ""])
+            .add(&mut col);
+        NoteAdder::basic(&mut col)
+            .fields(&["foo", ""])
+            .add(&mut col);
+        NoteAdder::basic(&mut col)
+            .fields(&["bar", ""])
+            .add(&mut col);
+        let mode = || SortMode::Builtin {
+            column: Column::SortField,
+            reverse: true,
+        };
+
+        let (sql, args) = col.explain_search("foo", mode(), Some(1), Some(1))?;
+        assert_eq!(args, ["%foo%"]);
+        assert!(sql.ends_with(" limit 1 offset 1"));
+        // running the statement gives the same result as the search
+        let cids: Vec<CardId> = col
+            .storage
+            .db
+            .prepare(&sql)?
+            .query_map(params_from_iter(args.iter()), |row| row.get(0))?
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(
+            cids,
+            col.search_cards_page("foo", mode(), Some(1), Some(1))?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_search_skips_filtering() -> Result<()> {
+        let mut col = Collection::new();
+        let notes: Vec<_> = (0..3)
+            .map(|_| NoteAdder::basic(&mut col).add(&mut col))
+            .collect();
+        col.remove_notes(&[notes[1].id])?;
+        let mut all_cids: Vec<CardId> = col
+            .storage
+            .db
+            .prepare("select id from cards")?
+            .query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<_, _>>()?;
+        all_cids.sort();
+
+        let (sql, _) = col.explain_search("", SortMode::NoOrder, None, None)?;
+        assert_eq!(sql, "select c.id from cards c");
+        let mut cids = col.search_cards("", SortMode::NoOrder)?;
+        cids.sort();
+        assert_eq!(cids, all_cids);
+        assert_eq!(cids.len(), 2);
+
+        // sorting and paging still apply, and may need the notes table
+        let mode = || SortMode::Builtin {
+            column: Column::NoteCreation,
+            reverse: true,
+        };
+        let (sql, _) = col.explain_search("", mode(), Some(1), None)?;
+        assert!(sql.starts_with("select c.id from cards c, notes n where c.nid=n.id order by "));
+        assert_eq!(
+            col.search_cards_page("", mode(), Some(1), None)?,
+            [all_cids[1]]
+        );
+        assert_eq!(col.search_notes("", mode())?, [notes[2].id, notes[0].id]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn card_ids_in_search_are_streamed_in_order() -> Result<()> {
+        let mut col = Collection::new();
+        for _ in 0..3 {
+            NoteAdder::basic(&mut col).add(&mut col);
+        }
+        let newest_first = || SortMode::Builtin {
+            column: Column::NoteCreation,
+            reverse: true,
+        };
+
+        let mut cids = vec![];
+        let count = col.for_each_card_id_in_search("", newest_first(), |cid| {
+            cids.push(cid);
+            Ok(())
+        })?;
+        assert_eq!(count, 3);
+        assert_eq!(cids, col.search_cards("", newest_first())?);
+
+        // an error from the callback ends the search
+        let mut calls = 0;
+        let result = col.for_each_card_id_in_search("", newest_first(), |_| {
+            calls += 1;
+            Err(AnkiError::Interrupted)
+        });
+        assert_eq!(result, Err(AnkiError::Interrupted));
+        assert_eq!(calls, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_order() -> Result<()> {
+        let mut col = Collection::new();
+        let mut cids = vec![];
+        for (due, ctype) in [
+            (5, CardType::New),
+            (2, CardType::Review),
+            (3, CardType::New),
+        ] {
+            let note = NoteAdder::basic(&mut col).add(&mut col);
+            let cid = col.search_cards(&format!("nid:{}", note.id), SortMode::NoOrder)?[0];
+            col.storage.db.execute(
+                "update cards set due = ?, type = ? where id = ?",
+                params![due, ctype as i8, cid],
+            )?;
+            cids.push(cid);
+        }
+        let order = |reverse| SortMode::Builtin {
+            column: Column::Position,
+            reverse,
+        };
+
+        // cards that aren't new come last in either direction
+        assert_eq!(
+            col.search_cards("", order(false))?,
+            [cids[2], cids[0], cids[1]]
+        );
+        assert_eq!(
+            col.search_cards("", order(true))?,
+            [cids[0], cids[2], cids[1]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn original_due_order() -> Result<()> {
+        let mut col = Collection::new();
+        let mut cids = vec![];
+        for (due, odid, odue) in [(-100_000, 1, 1), (5, 0, 0), (0, 1, 10)] {
+            let note = NoteAdder::basic(&mut col).add(&mut col);
+            let cid = col.search_cards(note.id, SortMode::NoOrder)?[0];
+            col.storage.db.execute(
+                "update cards set due = ?, odid = ?, odue = ? where id = ?",
+                params![due, odid, odue, cid],
+            )?;
+            cids.push(cid);
+        }
+        let order = |reverse| SortMode::Builtin {
+            column: Column::OriginalDue,
+            reverse,
+        };
+
+        // cards in filtered decks sort by the due they'll return to
+        assert_eq!(col.search_cards("", order(false))?, cids);
+        cids.reverse();
+        assert_eq!(col.search_cards("", order(true))?, cids);
+
+        Ok(())
+    }
+
+    #[test]
+    fn builtin_card_sorts_are_stable() -> Result<()> {
+        let mut col = Collection::new();
+        for _ in 0..4 {
+            NoteAdder::basic(&mut col).add(&mut col);
+        }
+        // all cards share the same due number
+        col.storage.db.execute("update cards set due = 1", [])?;
+        let mut cids = col.search_cards("", SortMode::NoOrder)?;
+        cids.sort();
+
+        for reverse in [false, true] {
+            let by_due = || SortMode::Builtin {
+                column: Column::Due,
+                reverse,
+            };
+            let first = col.search_cards("", by_due())?;
+            assert_eq!(first, col.search_cards("", by_due())?);
+            // ties are broken by card id, in the direction of the sort
+            let mut expected = cids.clone();
+            if reverse {
+                expected.reverse();
+            }
+            assert_eq!(first, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn recent_review_sort() -> Result<()> {
+        use crate::revlog::RevlogEntry;
+
+        let mut col = Collection::new();
+        let now = TimestampMillis::now().0;
+        let old = now - 30 * 86_400_000;
+        // revlog ids must be unique
+        let mut offset = 0;
+        let mut add_card_with_reviews = |times: &[i64]| -> Result<CardId> {
+            let note = NoteAdder::basic(&mut col).add(&mut col);
+            let cid = col.storage.card_ids_of_notes(&[note.id])?[0];
+            for time in times {
+                offset += 1;
+                col.storage.add_revlog_entry(
+                    &RevlogEntry {
+                        id: RevlogId(time - offset),
+                        cid,
+                        button_chosen: 3,
+                        ..Default::default()
+                    },
+                    false,
+                )?;
+            }
+            Ok(cid)
+        };
+        let unreviewed = add_card_with_reviews(&[])?;
+        let busy = add_card_with_reviews(&[now, now])?;
+        let once_recently = add_card_with_reviews(&[now, old, old, old])?;
+        let only_long_ago = add_card_with_reviews(&[old])?;
+
+        let by_recent_reviews = |reverse| SortMode::RecentReviews { days: 7, reverse };
+        assert_eq!(
+            col.search_cards("", by_recent_reviews(false))?,
+            [busy, once_recently, unreviewed, only_long_ago]
+        );
+        assert_eq!(
+            col.search_cards("", by_recent_reviews(true))?,
+            [only_long_ago, unreviewed, once_recently, busy]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn retrievability_order() -> Result<()> {
+        let mut col = Collection::new();
+        let next_day_at = col.timing_today()?.next_day_at;
+        // learning cards, so the time since review is taken from the due
+        // timestamp
+        let mut add_card = |days_since_review: Option<i64>| -> Result<CardId> {
+            let note = NoteAdder::basic(&mut col).add(&mut col);
+            let mut card = col.storage.all_cards_of_note(note.id)?.pop().unwrap();
+            if let Some(days) = days_since_review {
+                card.ctype = CardType::Learn;
+                card.queue = CardQueue::Learn;
+                card.due = next_day_at.adding_secs(-86_400 * days).0 as i32;
+                card.memory_state = Some(FsrsMemoryState {
+                    stability: 5.0,
+                    difficulty: 5.0,
+                });
+                col.storage.update_card(&card)?;
+            }
+            Ok(card.id)
+        };
+        let unscheduled = add_card(None)?;
+        let recent = add_card(Some(1))?;
+        let forgotten = add_card(Some(20))?;
+        let fading = add_card(Some(6))?;
+
+        let by_retrievability = |reverse| SortMode::Builtin {
+            column: Column::Retrievability,
+            reverse,
+        };
+        // cards without a memory state come last in either direction
+        assert_eq!(
+            col.search_cards("", by_retrievability(false))?,
+            [forgotten, fading, recent, unscheduled]
+        );
+        assert_eq!(
+            col.search_cards("", by_retrievability(true))?,
+            [recent, fading, forgotten, unscheduled]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn counts_match_searches() -> Result<()> {
+        let mut col = Collection::new();
+        let notetype = col.basic_rev_notetype();
+        NoteAdder::new(&notetype).fields(&["a", "b"]).add(&mut col);
+        NoteAdder::basic(&mut col).fields(&["a", ""]).add(&mut col);
+        NoteAdder::basic(&mut col).fields(&["c", ""]).add(&mut col);
+
+        for search in ["", "a", "card:2", "front:c or back:b", "-a", "deck:missing"] {
+            assert_eq!(
+                col.count_cards(search)?,
+                col.search_cards(search, SortMode::NoOrder)?.len(),
+                "{search}"
+            );
+            assert_eq!(
+                col.count_notes(search)?,
+                col.search_notes_unordered(search)?.len(),
+                "{search}"
+            );
+        }
+        assert_eq!(col.count_cards("a")?, 3);
+        assert_eq!(col.count_notes("a")?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_random_order() -> Result<()> {
+        let mut col = Collection::new();
+        for _ in 0..20 {
+            NoteAdder::basic(&mut col).add(&mut col);
+        }
+        let by_seed = |col: &mut Collection, seed| col.search_cards("", SortMode::Random { seed });
+
+        let first = by_seed(&mut col, 1)?;
+        assert_eq!(by_seed(&mut col, 1)?, first);
+        assert_eq!(first.len(), 20);
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_ne!(first, sorted);
+        assert_ne!(by_seed(&mut col, 2)?, first);
+        // seeds above i64::MAX are accepted
+        assert_eq!(by_seed(&mut col, u64::MAX)?, by_seed(&mut col, u64::MAX)?);
+        let notes = col.search_notes("", SortMode::Random { seed: 1 })?;
+        assert_eq!(col.search_notes("", SortMode::Random { seed: 1 })?, notes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_sort_field_order() -> Result<()> {
+        let mut col = Collection::new();
+        let mut nids = vec![];
+        for text in [
+            "Chapter 10",
+            "Chapter 2",
+            "chapter 2b",
+            "10",
+            "9",
+            "apple",
+            "Chapter 02",
+        ] {
+            nids.push(
+                NoteAdder::basic(&mut col)
+                    .fields(&[text, ""])
+                    .add(&mut col)
+                    .id,
+            );
+        }
+        let expected = [
+            nids[4], nids[3], nids[5], nids[6], nids[1], nids[2], nids[0],
+        ];
+        assert_eq!(
+            col.search_notes("", SortMode::NumericSortField { reverse: false })?,
+            expected
+        );
+        let mut reversed = expected;
+        reversed.reverse();
+        assert_eq!(
+            col.search_notes("", SortMode::NumericSortField { reverse: true })?,
+            reversed
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn note_sorts_match_card_sorts_for_note_columns() -> Result<()> {
+        let mut col = Collection::new();
+        for (front, tags, mtime) in [("b", "z", 3), ("c", "x", 1), ("a", "y", 2)] {
+            let mut note = NoteAdder::basic(&mut col).fields(&[front, ""]).note();
+            note.tags = vec![tags.into()];
+            col.add_note(&mut note, DeckId(1))?;
+            col.storage.db.execute(
+                "update notes set mod = ? where id = ?",
+                params![mtime, note.id],
+            )?;
+        }
+
+        for column in [
+            Column::NoteCreation,
+            Column::NoteMod,
+            Column::SortField,
+            Column::Tags,
+        ] {
+            for reverse in [false, true] {
+                let mode = SortMode::Builtin { column, reverse };
+                assert_eq!(
+                    col.search_notes("", mode.clone())?,
+                    col.note_ids_of_card_search("", mode)?,
+                    "{column:?}"
+                );
+            }
+        }
+        // columns that only make sense for cards are rejected
+        let by_position = SortMode::Builtin {
+            column: Column::Position,
+            reverse: false,
+        };
+        assert!(matches!(
+            col.search_notes("", by_position),
+            Err(AnkiError::InvalidInput { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn note_ids_of_card_search() -> Result<()> {
+        let mut col = Collection::new();
+        let reversed = col
+            .get_notetype_by_name("Basic (and reversed card)")?
+            .unwrap();
+        let both = NoteAdder::new(&reversed)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        let single = NoteAdder::basic(&mut col).add(&mut col);
+
+        // a note is listed once, when its first card appears
+        let newest_first = SortMode::Custom("c.id desc".into());
+        assert_eq!(
+            col.note_ids_of_card_search("", newest_first.clone())?,
+            [single.id, both.id]
+        );
+        assert_eq!(
+            col.note_ids_of_card_search("", SortMode::NoOrder)?,
+            [both.id, single.id]
+        );
+        // notes are included when only some of their cards match
+        assert_eq!(
+            col.note_ids_of_card_search("card:2", newest_first)?,
+            [both.id]
+        );
+        // all cards are in the same deck, so the card id decides, in the
+        // direction of the sort as when searching cards
+        for (reverse, expected) in [(false, [both.id, single.id]), (true, [single.id, both.id])] {
+            let by_deck = SortMode::Builtin {
+                column: Column::Deck,
+                reverse,
+            };
+            assert_eq!(col.note_ids_of_card_search("", by_deck)?, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn deck_sort_follows_hierarchy() -> Result<()> {
+        use crate::tests::DeckAdder;
+
+        let mut col = Collection::new();
+        // created in the order their full names sort as text; "::" sorts after
+        // a space or hyphen
+        let names = ["Lang", "Lang Extra", "Lang-Audio", "Lang::Verbs"];
+        let mut nids = HashMap::new();
+        for name in names {
+            let deck = DeckAdder::new(name).add(&mut col);
+            let note = NoteAdder::basic(&mut col).deck(deck.id).add(&mut col);
+            nids.insert(name, note.id);
+        }
+        let expected: Vec<_> = ["Lang", "Lang::Verbs", "Lang Extra", "Lang-Audio"]
+            .iter()
+            .map(|name| nids[name])
+            .collect();
+
+        // subdecks come straight after their parent
+        for reverse in [false, true] {
+            let by_deck = SortMode::Builtin {
+                column: Column::Deck,
+                reverse,
+            };
+            let mut expected = expected.clone();
+            if reverse {
+                expected.reverse();
+            }
+            let cids = col.search_cards("deck:lang*", by_deck.clone())?;
+            let card_nids: Vec<_> = cids
+                .into_iter()
+                .map(|cid| col.storage.get_card(cid).map(|card| card.unwrap().note_id))
+                .collect::<Result<_>>()?;
+            assert_eq!(card_nids, expected);
+            assert_eq!(col.search_notes("deck:lang*", by_deck)?, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_cards_grouped_by_note() -> Result<()> {
+        let mut col = Collection::new();
+        let reversed = col
+            .get_notetype_by_name("Basic (and reversed card)")?
+            .unwrap();
+        let both = NoteAdder::new(&reversed)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        let single = NoteAdder::basic(&mut col).add(&mut col);
+        let mut cids = col.search_cards(format!("nid:{}", both.id).as_str(), SortMode::NoOrder)?;
+        cids.sort();
+        let single_cid =
+            col.search_cards(format!("nid:{}", single.id).as_str(), SortMode::NoOrder)?[0];
+
+        // the first card of each note in sort order represents it
+        let newest_first = SortMode::Custom("c.id desc".into());
+        assert_eq!(
+            col.search_cards_grouped("", newest_first.clone(), true)?,
+            [single_cid, cids[1]]
+        );
+        assert_eq!(
+            col.search_cards_grouped("", newest_first.clone(), false)?,
+            [single_cid, cids[1], cids[0]]
+        );
+        // only matching cards can represent a note
+        assert_eq!(
+            col.search_cards_grouped("card:1", newest_first, true)?,
+            [single_cid, cids[0]]
+        );
+        // cards tied across notes are ordered as search_cards orders them, by
+        // card id in the direction of the sort
+        for (reverse, all, grouped) in [
+            (
+                false,
+                vec![cids[0], cids[1], single_cid],
+                [cids[0], single_cid],
+            ),
+            (
+                true,
+                vec![single_cid, cids[1], cids[0]],
+                [single_cid, cids[1]],
+            ),
+        ] {
+            let by_deck = SortMode::Builtin {
+                column: Column::Deck,
+                reverse,
+            };
+            assert_eq!(col.search_cards("", by_deck.clone())?, all);
+            assert_eq!(col.search_cards_grouped("", by_deck, true)?, grouped);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sort_mode_from_column_key() {
+        assert_eq!(
+            SortMode::from_column_key("noteCrt", true).unwrap(),
+            SortMode::Builtin {
+                column: Column::NoteCreation,
+                reverse: true
+            }
+        );
+        for key in ["", "c.id desc", "nonsense"] {
+            assert!(matches!(
+                SortMode::from_column_key(key, false),
+                Err(AnkiError::InvalidInput { .. })
+            ));
+        }
+    }
 }
@@ -1,6 +1,8 @@
 // Copyright: Ankitects Pty Ltd and contributors
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
+use chrono::NaiveDate;
+use chrono::Weekday;
 use lazy_static::lazy_static;
 use nom::branch::alt;
 use nom::bytes::complete::escaped;
@@ -9,6 +11,7 @@ use nom::bytes::complete::tag;
 use nom::character::complete::alphanumeric1;
 use nom::character::complete::anychar;
 use nom::character::complete::char;
+use nom::character::complete::digit0;
 use nom::character::complete::none_of;
 use nom::character::complete::one_of;
 use nom::combinator::map;
@@ -55,9 +58,21 @@ pub enum SearchNode {
         field: String,
         text: String,
         is_re: bool,
+        /// Only used when not a regex, which can set its own case handling.
+        match_case: bool,
     },
     AddedInDays(u32),
+    /// Cards added on any of the days from `min` to `max`, counted like
+    /// [SearchNode::AddedInDays], so 1 is today.
+    AddedInDayRange {
+        min: u32,
+        max: u32,
+    },
     EditedInDays(u32),
+    /// Unlike the day-based forms, counted back from the current time rather
+    /// than the next day rollover.
+    AddedInHours(u32),
+    EditedInHours(u32),
     CardTemplate(TemplateKind),
     Deck(String),
     /// Matches cards in a list of decks (original_deck_id is not checked).
@@ -66,12 +81,21 @@ pub enum SearchNode {
     /// checked).
     DeckIdWithChildren(DeckId),
     IntroducedInDays(u32),
+    /// Review cards due on one of the given days of the week.
+    DueOnWeekdays(Vec<Weekday>),
     NotetypeId(NotetypeId),
     Notetype(String),
     Rated {
         days: u32,
         ease: RatingKind,
     },
+    /// Like [SearchNode::Rated], but for answers given since the study
+    /// session began, rather than in a number of days.
+    RatedInSession {
+        ease: RatingKind,
+    },
+    /// Cards whose most recent review was answered with the given button.
+    LastRating(u8),
     Tag {
         tag: String,
         is_re: bool,
@@ -79,9 +103,28 @@ pub enum SearchNode {
     Duplicates {
         notetype_id: NotetypeId,
         text: String,
+        /// Ignore case and combining characters when comparing.
+        fold: bool,
     },
     State(StateKind),
-    Flag(u8),
+    /// Notes whose stored first-field checksum no longer matches the field.
+    StaleChecksum,
+    /// New cards sharing their position with a card of another note in the
+    /// same deck.
+    PositionCollision,
+    /// Notes whose number of fields differs from their notetype's.
+    FieldCountMismatch,
+    /// Notes with no tags, or only separators.
+    Untagged,
+    /// Notes carrying the tag that leeches are given, which is configurable.
+    Leech,
+    /// Notes whose first field duplicates that of another note of the same
+    /// notetype, compared as [SearchNode::Duplicates] does.
+    AnyDuplicate,
+    /// Matches any of the flags, which are sorted and deduplicated.
+    Flag(Vec<u8>),
+    /// Cards with a flag of any colour.
+    AnyFlag,
     NoteIds(String),
     CardIds(String),
     Property {
@@ -93,23 +136,103 @@ pub enum SearchNode {
     NoCombining(String),
     WordBoundary(String),
     CustomData(String),
+    /// Cards whose data has the given top-level key, whatever its value.
+    DataKey(String),
     Preset(String),
+    /// Cards in decks whose preset has a number of learning steps matching
+    /// the comparison, eg preset:steps>2.
+    PresetLearningSteps {
+        operator: String,
+        count: u32,
+    },
+    /// Notes referring to the given media file. As keywords take precedence
+    /// over field names, a field called "media" has to be searched with a
+    /// wildcard, eg medi_:foo.mp3.
+    MediaRef(String),
+    /// Text anywhere in the stored fields, HTML included, eg raw:<img. Unlike
+    /// [SearchNode::UnqualifiedText], fields excluded from searches and the
+    /// stripped sort field aren't treated specially. As keywords take
+    /// precedence over field names, a field called "raw" has to be searched
+    /// with a wildcard, eg ra_:text.
+    RawText(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IntervalUnit {
+    Days,
+    Weeks,
+    Months,
+    Years,
+}
+
+impl IntervalUnit {
+    pub fn days(self) -> u32 {
+        match self {
+            IntervalUnit::Days => 1,
+            IntervalUnit::Weeks => 7,
+            IntervalUnit::Months => 30,
+            IntervalUnit::Years => 365,
+        }
+    }
+
+    pub fn suffix(self) -> char {
+        match self {
+            IntervalUnit::Days => 'd',
+            IntervalUnit::Weeks => 'w',
+            IntervalUnit::Months => 'm',
+            IntervalUnit::Years => 'y',
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum PropertyKind {
     Due(i32),
-    Interval(u32),
+    /// Kept apart from [PropertyKind::Due] so the search can be written as it
+    /// was typed; the day number depends on the rollover hour, so it's only
+    /// worked out when building the SQL.
+    DueOnDate(NaiveDate),
+    /// Days from the day of the latest answer to the due day of a review
+    /// card, eg prop:duegap<2.
+    DueAfterLastReview(i32),
+    /// The unit is kept so the search can be written as it was typed.
+    Interval {
+        days: u32,
+        unit: Option<IntervalUnit>,
+    },
     Reps(u32),
     Lapses(u32),
-    Ease(f32),
+    /// Either a ratio like 2.5, or if `percent` is set, a percentage like
+    /// 250, as it was typed.
+    Ease {
+        value: f32,
+        percent: bool,
+    },
+    EaseDeviation(f32),
     Position(u32),
+    FieldCount(u32),
+    /// The number of cards the note has, whatever their templates.
+    CardCount(u32),
+    /// The number of tags on the note.
+    TagCount(u32),
     Rated(i32, RatingKind),
+    /// Days relative to today, so never positive.
+    Edited(i32),
+    RatedCount {
+        days: Option<u32>,
+        count: u32,
+    },
     Stability(f32),
     Difficulty(f32),
     Retrievability(f32),
-    CustomDataNumber { key: String, value: f32 },
-    CustomDataString { key: String, value: String },
+    CustomDataNumber {
+        key: String,
+        value: f32,
+    },
+    CustomDataString {
+        key: String,
+        value: String,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -118,10 +241,20 @@ pub enum StateKind {
     Review,
     Learning,
     Due,
+    /// Review or learning cards that aren't due yet.
+    Scheduled,
+    /// Review cards that have been due for longer than their interval, so
+    /// unlike [StateKind::Due], cards only a day or two late don't match.
+    Overdue,
     Buried,
     UserBuried,
     SchedBuried,
+    /// Suspended cards.
     Suspended,
+    /// Cards of notes whose cards are all suspended. Unlike
+    /// [StateKind::Suspended], a suspended card doesn't match if a sibling is
+    /// still being studied, and a note's cards match or don't match together.
+    NoteSuspended,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -133,22 +266,31 @@ pub enum TemplateKind {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum RatingKind {
     AnswerButton(u8),
+    /// An inclusive range of buttons, the first lower than the second.
+    AnswerButtonRange(u8, u8),
     AnyAnswerButton,
     ManualReschedule,
+    /// Again, while the card was in review.
+    Lapse,
 }
 
 /// Parse the input string into a list of nodes.
+/// Errors include the byte range of the problematic text in `input`. For an
+/// unclosed group, it starts at the opening bracket.
 pub fn parse(input: &str) -> Result<Vec<Node>> {
-    let input = input.trim();
-    if input.is_empty() {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
         return Ok(vec![Node::Search(SearchNode::WholeCollection)]);
     }
 
-    match group_inner(input) {
+    match group_inner(trimmed) {
         Ok(("", nodes)) => Ok(nodes),
         // unmatched ) is only char not consumed by any node parser
-        Ok((remaining, _)) => Err(parse_failure(remaining, FailKind::UnopenedGroup).into()),
-        Err(err) => Err(err.into()),
+        Ok((remaining, _)) => Err(AnkiError::from_search_failure(
+            parse_failure(remaining, FailKind::UnopenedGroup),
+            input,
+        )),
+        Err(err) => Err(AnkiError::from_search_failure(err, input)),
     }
 }
 
@@ -344,7 +486,9 @@ fn search_node_for_text_with_argument<'a>(
         "added" => parse_added(val)?,
         "edited" => parse_edited(val)?,
         "introduced" => parse_introduced(val)?,
+        "dueweekday" => parse_due_weekdays(val)?,
         "rated" => parse_rated(val)?,
+        "lastrating" => parse_last_rating(val)?,
         "is" => parse_state(val)?,
         "did" => SearchNode::DeckIdsWithoutChildren(check_id_list(val, key)?.into()),
         "mid" => parse_mid(val)?,
@@ -353,20 +497,47 @@ fn search_node_for_text_with_argument<'a>(
         "re" => SearchNode::Regex(unescape_quotes(val)),
         "nc" => SearchNode::NoCombining(unescape(val)?),
         "w" => SearchNode::WordBoundary(unescape(val)?),
-        "dupe" => parse_dupe(val)?,
+        "dupe" => parse_dupe(val, false)?,
+        "dupefold" => parse_dupe(val, true)?,
         "has-cd" => SearchNode::CustomData(unescape(val)?),
-        "preset" => SearchNode::Preset(val.into()),
+        "datakey" => parse_data_key(val)?,
+        "preset" => parse_preset(val)?,
+        "media" => SearchNode::MediaRef(unescape(val)?),
+        "raw" => SearchNode::RawText(unescape(val)?),
         // anything else is a field search
         _ => parse_single_field(key, val)?,
     })
 }
 
+/// eg preset:default, preset:steps>2
+fn parse_preset(s: &str) -> ParseResult<SearchNode> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^steps(<=|>=|!=|=|<|>)(.+)$").unwrap();
+    }
+    Ok(if let Some(caps) = RE.captures(s) {
+        SearchNode::PresetLearningSteps {
+            operator: caps[1].into(),
+            count: parse_u32(caps.get(2).unwrap().as_str(), s)?,
+        }
+    } else {
+        SearchNode::Preset(s.into())
+    })
+}
+
 fn parse_tag(s: &str) -> ParseResult<SearchNode> {
     Ok(if let Some(re) = s.strip_prefix("re:") {
-        SearchNode::Tag {
-            tag: unescape_quotes(re),
-            is_re: true,
+        let tag = unescape_quotes(re);
+        // checked here so the error can point at the pattern
+        if let Err(err) = Regex::new(&format!("(?i){tag}")) {
+            return Err(parse_failure(
+                re,
+                FailKind::InvalidRegex {
+                    provided: tag,
+                    details: err.to_string(),
+                },
+            ));
         }
+        SearchNode::Tag { tag, is_re: true }
     } else {
         SearchNode::Tag {
             tag: unescape(s)?,
@@ -382,36 +553,52 @@ fn parse_template(s: &str) -> ParseResult<SearchNode> {
     }))
 }
 
-/// flag:0-7
+/// flag:0-7, or several separated by commas, eg flag:1,2, or flag:any
 fn parse_flag(s: &str) -> ParseResult<SearchNode> {
-    if let Ok(flag) = s.parse::<u8>() {
-        if flag > 7 {
-            Err(parse_failure(s, FailKind::InvalidFlag))
-        } else {
-            Ok(SearchNode::Flag(flag))
-        }
-    } else {
-        Err(parse_failure(s, FailKind::InvalidFlag))
+    if s.eq_ignore_ascii_case("any") {
+        return Ok(SearchNode::AnyFlag);
     }
+    let mut flags = s
+        .split(',')
+        .map(|flag| match flag.parse::<u8>() {
+            Ok(flag) if flag <= 7 => Ok(flag),
+            _ => Err(parse_failure(s, FailKind::InvalidFlag)),
+        })
+        .collect::<ParseResult<Vec<_>>>()?;
+    flags.sort_unstable();
+    flags.dedup();
+    Ok(SearchNode::Flag(flags))
 }
 
 /// eg resched:3
 fn parse_resched(s: &str) -> ParseResult<SearchNode> {
+    if s.eq_ignore_ascii_case("session") {
+        return Ok(SearchNode::RatedInSession {
+            ease: RatingKind::ManualReschedule,
+        });
+    }
     parse_u32(s, "resched:").map(|days| SearchNode::Rated {
         days,
         ease: RatingKind::ManualReschedule,
     })
 }
 
-/// eg prop:ivl>3, prop:ease!=2.5
+/// eg prop:ivl>3, prop:ease!=2.5, prop:rated:14>3
 fn parse_prop(prop_clause: &str) -> ParseResult<SearchNode> {
     let (tail, prop) = alt::<_, _, ParseError, _>((
         tag("ivl"),
+        tag("duegap"),
         tag("due"),
         tag("reps"),
         tag("lapses"),
+        tag("easedev"),
         tag("ease"),
+        tag("ed"),
         tag("pos"),
+        tag("fields"),
+        tag("cards"),
+        tag("tags"),
+        recognize(preceded(tag("rated:"), digit0)),
         tag("rated"),
         tag("resched"),
         tag("s"),
@@ -447,20 +634,33 @@ fn parse_prop(prop_clause: &str) -> ParseResult<SearchNode> {
     })?;
 
     let kind = match prop {
-        "ease" => PropertyKind::Ease(parse_f32(num, prop_clause)?),
-        "due" => PropertyKind::Due(parse_i32(num, prop_clause)?),
+        "ease" => parse_prop_ease(num, prop_clause)?,
+        "easedev" => PropertyKind::EaseDeviation(parse_f32(num, prop_clause)?),
+        "due" => parse_prop_due(num, prop_clause)?,
+        "duegap" => PropertyKind::DueAfterLastReview(parse_i32(num, prop_clause)?),
         "rated" => parse_prop_rated(num, prop_clause)?,
         "resched" => PropertyKind::Rated(
             parse_negative_i32(num, prop_clause)?,
             RatingKind::ManualReschedule,
         ),
-        "ivl" => PropertyKind::Interval(parse_u32(num, prop_clause)?),
+        "ivl" => parse_prop_interval(num, prop_clause)?,
+        "ed" => PropertyKind::Edited(parse_negative_i32(num, prop_clause)?),
         "reps" => PropertyKind::Reps(parse_u32(num, prop_clause)?),
         "lapses" => PropertyKind::Lapses(parse_u32(num, prop_clause)?),
         "pos" => PropertyKind::Position(parse_u32(num, prop_clause)?),
+        "fields" => PropertyKind::FieldCount(parse_u32(num, prop_clause)?),
+        "cards" => PropertyKind::CardCount(parse_u32(num, prop_clause)?),
+        "tags" => PropertyKind::TagCount(parse_u32(num, prop_clause)?),
         "s" => PropertyKind::Stability(parse_f32(num, prop_clause)?),
         "d" => PropertyKind::Difficulty(parse_f32(num, prop_clause)?),
         "r" => PropertyKind::Retrievability(parse_f32(num, prop_clause)?),
+        prop if prop.starts_with("rated:") => PropertyKind::RatedCount {
+            days: match prop.strip_prefix("rated:").unwrap() {
+                "" => None,
+                days => Some(parse_u32(days, prop_clause)?.max(1)),
+            },
+            count: parse_u32(num, prop_clause)?,
+        },
         prop if prop.starts_with("cdn:") => PropertyKind::CustomDataNumber {
             key: prop.strip_prefix("cdn:").unwrap().into(),
             value: parse_f32(num, prop_clause)?,
@@ -478,6 +678,53 @@ fn parse_prop(prop_clause: &str) -> ParseResult<SearchNode> {
     })
 }
 
+/// eg -1, 3, 2024-01-15
+fn parse_prop_due<'a>(num: &'a str, context: &'a str) -> ParseResult<'a, PropertyKind> {
+    if !num.chars().skip(1).any(|c| c == '-') {
+        return Ok(PropertyKind::Due(parse_i32(num, context)?));
+    }
+    NaiveDate::parse_from_str(num, "%Y-%m-%d")
+        .map(PropertyKind::DueOnDate)
+        .map_err(|_| {
+            parse_failure(
+                num,
+                FailKind::InvalidDate {
+                    context: context.into(),
+                    provided: num.into(),
+                },
+            )
+        })
+}
+
+/// eg 21, 3w, 2m
+fn parse_prop_interval<'a>(num: &str, context: &'a str) -> ParseResult<'a, PropertyKind> {
+    let unit = match num.chars().last() {
+        Some('d') => Some(IntervalUnit::Days),
+        Some('w') => Some(IntervalUnit::Weeks),
+        Some('m') => Some(IntervalUnit::Months),
+        Some('y') => Some(IntervalUnit::Years),
+        _ => None,
+    };
+    let count_str = if unit.is_some() {
+        &num[..num.len() - 1]
+    } else {
+        num
+    };
+    let count = parse_u32(count_str, context)?;
+    let days = count
+        .checked_mul(unit.map_or(1, IntervalUnit::days))
+        .ok_or_else(|| {
+            parse_failure(
+                context,
+                FailKind::InvalidPositiveWholeNumber {
+                    context: context.into(),
+                    provided: num.into(),
+                },
+            )
+        })?;
+    Ok(PropertyKind::Interval { days, unit })
+}
+
 fn parse_u32<'a>(num: &str, context: &'a str) -> ParseResult<'a, u32> {
     num.parse().map_err(|_e| {
         parse_failure(
@@ -529,6 +776,25 @@ fn parse_f32<'a>(num: &str, context: &'a str) -> ParseResult<'a, f32> {
     })
 }
 
+/// eg 2.5 or 250%
+fn parse_prop_ease<'a>(num: &str, context: &'a str) -> ParseResult<'a, PropertyKind> {
+    let (digits, percent) = match num.strip_suffix('%') {
+        Some(digits) => (digits, true),
+        None => (num, false),
+    };
+    // "inf" and "nan" parse as floats too
+    match digits.parse::<f32>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(PropertyKind::Ease { value, percent }),
+        _ => Err(parse_failure(
+            context,
+            FailKind::InvalidNumber {
+                context: context.into(),
+                provided: num.into(),
+            },
+        )),
+    }
+}
+
 fn parse_i64<'a>(num: &str, context: &'a str) -> ParseResult<'a, i64> {
     num.parse().map_err(|_e| {
         parse_failure(
@@ -562,21 +828,72 @@ fn parse_answer_button<'a>(num: Option<&str>, context: &'a str) -> ParseResult<'
     })
 }
 
+/// Like [parse_answer_button], but also accepts "lapse" and a range of
+/// buttons like "2-3".
+fn parse_rating_kind<'a>(num: Option<&str>, context: &'a str) -> ParseResult<'a, RatingKind> {
+    match num {
+        Some("lapse") => Ok(RatingKind::Lapse),
+        Some(range) if range.contains('-') => parse_answer_button_range(range, context),
+        _ => parse_answer_button(num, context),
+    }
+}
+
+fn parse_answer_button_range<'a>(range: &str, context: &'a str) -> ParseResult<'a, RatingKind> {
+    let invalid = || {
+        parse_failure(
+            context,
+            FailKind::InvalidAnswerButton {
+                context: context.into(),
+                provided: range.into(),
+            },
+        )
+    };
+    let (low, high) = range.split_once('-').unwrap();
+    match (
+        parse_answer_button(Some(low), context),
+        parse_answer_button(Some(high), context),
+    ) {
+        (Ok(RatingKind::AnswerButton(low)), Ok(RatingKind::AnswerButton(high))) => {
+            match low.cmp(&high) {
+                std::cmp::Ordering::Less => Ok(RatingKind::AnswerButtonRange(low, high)),
+                std::cmp::Ordering::Equal => Ok(RatingKind::AnswerButton(low)),
+                std::cmp::Ordering::Greater => Err(invalid()),
+            }
+        }
+        _ => Err(invalid()),
+    }
+}
+
 fn parse_prop_rated<'a>(num: &str, context: &'a str) -> ParseResult<'a, PropertyKind> {
     let mut it = num.splitn(2, ':');
     let days = parse_negative_i32(it.next().unwrap(), context)?;
-    let button = parse_answer_button(it.next(), context)?;
+    let button = parse_rating_kind(it.next(), context)?;
     Ok(PropertyKind::Rated(days, button))
 }
 
-/// eg added:1
+/// eg added:1, added:6h, added:3-7
 fn parse_added(s: &str) -> ParseResult<SearchNode> {
-    parse_u32(s, "added:").map(|n| SearchNode::AddedInDays(n.max(1)))
+    if let Some(hours) = s.strip_suffix('h') {
+        parse_u32(hours, "added:").map(|n| SearchNode::AddedInHours(n.max(1)))
+    } else if let Some((first, second)) = s.split_once('-') {
+        let first = parse_u32(first, "added:")?.max(1);
+        let second = parse_u32(second, "added:")?.max(1);
+        Ok(SearchNode::AddedInDayRange {
+            min: first.min(second),
+            max: first.max(second),
+        })
+    } else {
+        parse_u32(s, "added:").map(|n| SearchNode::AddedInDays(n.max(1)))
+    }
 }
 
-/// eg edited:1
+/// eg edited:1, edited:6h
 fn parse_edited(s: &str) -> ParseResult<SearchNode> {
-    parse_u32(s, "edited:").map(|n| SearchNode::EditedInDays(n.max(1)))
+    if let Some(hours) = s.strip_suffix('h') {
+        parse_u32(hours, "edited:").map(|n| SearchNode::EditedInHours(n.max(1)))
+    } else {
+        parse_u32(s, "edited:").map(|n| SearchNode::EditedInDays(n.max(1)))
+    }
 }
 
 /// eg introduced:1
@@ -584,27 +901,113 @@ fn parse_introduced(s: &str) -> ParseResult<SearchNode> {
     parse_u32(s, "introduced:").map(|n| SearchNode::IntroducedInDays(n.max(1)))
 }
 
-/// eg rated:3 or rated:10:2
-/// second arg must be between 1-4
+/// eg dueweekday:sat,sun
+fn parse_due_weekdays(s: &str) -> ParseResult<SearchNode> {
+    s.split(',')
+        .map(|day| {
+            parse_weekday(day).ok_or_else(|| {
+                parse_failure(
+                    s,
+                    FailKind::InvalidWeekday {
+                        provided: day.into(),
+                    },
+                )
+            })
+        })
+        .collect::<ParseResult<_>>()
+        .map(SearchNode::DueOnWeekdays)
+}
+
+/// eg datakey:pos
+/// Keys are limited to ASCII letters, digits and underscores, so that they can
+/// be used in a JSON path without quoting.
+fn parse_data_key(s: &str) -> ParseResult<SearchNode> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Ok(SearchNode::DataKey(s.into()))
+    } else {
+        Err(parse_failure(
+            s,
+            FailKind::InvalidDataKey { provided: s.into() },
+        ))
+    }
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    use Weekday::*;
+    Some(match s.to_ascii_lowercase().as_str() {
+        "mon" | "monday" => Mon,
+        "tue" | "tuesday" => Tue,
+        "wed" | "wednesday" => Wed,
+        "thu" | "thursday" => Thu,
+        "fri" | "friday" => Fri,
+        "sat" | "saturday" => Sat,
+        "sun" | "sunday" => Sun,
+        _ => return None,
+    })
+}
+
+/// eg rated:3 or rated:10:2 or rated:7:lapse or rated:session:1
+/// second arg must be between 1-4, or lapse
 fn parse_rated(s: &str) -> ParseResult<SearchNode> {
     let mut it = s.splitn(2, ':');
-    let days = parse_u32(it.next().unwrap(), "rated:")?.max(1);
-    let button = parse_answer_button(it.next(), s)?;
+    let days = it.next().unwrap();
+    if days.eq_ignore_ascii_case("session") {
+        let button = parse_rating_kind(it.next(), s)?;
+        return Ok(SearchNode::RatedInSession { ease: button });
+    }
+    let days = parse_u32(days, "rated:")?.max(1);
+    let button = parse_rating_kind(it.next(), s)?;
     Ok(SearchNode::Rated { days, ease: button })
 }
 
+/// eg lastrating:hard or lastrating:2
+fn parse_last_rating(s: &str) -> ParseResult<SearchNode> {
+    let button = match s.to_ascii_lowercase().as_str() {
+        "again" => 1,
+        "hard" => 2,
+        "good" => 3,
+        "easy" => 4,
+        _ => s
+            .parse::<u8>()
+            .ok()
+            .filter(|button| matches!(button, 1..=4))
+            .ok_or_else(|| {
+                parse_failure(
+                    s,
+                    FailKind::InvalidAnswerButton {
+                        context: "lastrating:".into(),
+                        provided: s.into(),
+                    },
+                )
+            })?,
+    };
+    Ok(SearchNode::LastRating(button))
+}
+
 /// eg is:due
 fn parse_state(s: &str) -> ParseResult<SearchNode> {
     use StateKind::*;
+    match s {
+        "staledupecheck" => return Ok(SearchNode::StaleChecksum),
+        "poscollision" => return Ok(SearchNode::PositionCollision),
+        "fieldmismatch" => return Ok(SearchNode::FieldCountMismatch),
+        "duplicate" => return Ok(SearchNode::AnyDuplicate),
+        "untagged" => return Ok(SearchNode::Untagged),
+        "leech" => return Ok(SearchNode::Leech),
+        _ => (),
+    }
     Ok(SearchNode::State(match s {
         "new" => New,
         "review" => Review,
         "learn" => Learning,
         "due" => Due,
+        "scheduled" => Scheduled,
+        "overdue" => Overdue,
         "buried" => Buried,
         "buried-manually" => UserBuried,
         "buried-sibling" => SchedBuried,
         "suspended" => Suspended,
+        "note-suspended" => NoteSuspended,
         _ => {
             return Err(parse_failure(
                 s,
@@ -637,21 +1040,23 @@ fn check_id_list<'a>(s: &'a str, context: &str) -> ParseResult<'a, &'a str> {
     }
 }
 
-/// eg dupe:1231,hello
-fn parse_dupe(s: &str) -> ParseResult<SearchNode> {
+/// eg dupe:1231,hello or dupefold:1231,hello
+fn parse_dupe(s: &str, fold: bool) -> ParseResult<SearchNode> {
     let mut it = s.splitn(2, ',');
     let ntid = parse_i64(it.next().unwrap(), s)?;
     if let Some(text) = it.next() {
         Ok(SearchNode::Duplicates {
             notetype_id: ntid.into(),
             text: unescape_quotes_and_backslashes(text),
+            fold,
         })
     } else {
+        let key = if fold { "dupefold" } else { "dupe" };
         // this is an undocumented keyword, so no translation/help
         Err(parse_failure(
             s,
             FailKind::Other {
-                info: Some("invalid 'dupe:' search".into()),
+                info: Some(format!("invalid '{key}:' search")),
             },
         ))
     }
@@ -663,12 +1068,21 @@ fn parse_single_field<'a>(key: &'a str, val: &'a str) -> ParseResult<'a, SearchN
             field: unescape(key)?,
             text: unescape_quotes(stripped),
             is_re: true,
+            match_case: false,
+        }
+    } else if let Some(stripped) = val.strip_prefix("cs:") {
+        SearchNode::SingleField {
+            field: unescape(key)?,
+            text: unescape(stripped)?,
+            is_re: false,
+            match_case: true,
         }
     } else {
         SearchNode::SingleField {
             field: unescape(key)?,
             text: unescape(val)?,
             is_re: false,
+            match_case: false,
         }
     })
 }
@@ -760,6 +1174,7 @@ fn is_parser_escape(txt: &str) -> bool {
 mod test {
     use super::*;
     use crate::error::SearchErrorKind;
+    use crate::search::writer::write_nodes;
 
     #[test]
     fn parsing() -> Result<()> {
@@ -801,6 +1216,7 @@ mod test {
                         field: "foo".into(),
                         text: "bar baz".into(),
                         is_re: false,
+                        match_case: false,
                     })
                 ]))),
                 Or,
@@ -813,7 +1229,26 @@ mod test {
             vec![Search(SingleField {
                 field: "foo".into(),
                 text: "bar".into(),
-                is_re: true
+                is_re: true,
+                match_case: false
+            })]
+        );
+        assert_eq!(
+            parse("foo:cs:Bar*")?,
+            vec![Search(SingleField {
+                field: "foo".into(),
+                text: "Bar*".into(),
+                is_re: false,
+                match_case: true
+            })]
+        );
+        assert_eq!(
+            parse(r"foo:cs\:bar")?,
+            vec![Search(SingleField {
+                field: "foo".into(),
+                text: "cs:bar".into(),
+                is_re: false,
+                match_case: false
             })]
         );
 
@@ -823,7 +1258,8 @@ mod test {
             vec![Search(SingleField {
                 field: "field".into(),
                 text: "va\"lue".into(),
-                is_re: false
+                is_re: false,
+                match_case: false
             })]
         );
         assert_eq!(parse(r#""field:va\"lue""#)?, parse(r#"field:"va\"lue""#)?,);
@@ -871,6 +1307,19 @@ mod test {
         assert_eq!(parse(r#"a"b"(c)"#)?, parse("a b (c)")?);
 
         assert_eq!(parse("added:3")?, vec![Search(AddedInDays(3))]);
+        assert_eq!(parse("added:6h")?, vec![Search(AddedInHours(6))]);
+        assert_eq!(parse("edited:0h")?, vec![Search(EditedInHours(1))]);
+        assert!(parse("added:h").is_err());
+        assert_eq!(
+            parse("added:3-7 added:7-0")?,
+            vec![
+                Search(AddedInDayRange { min: 3, max: 7 }),
+                And,
+                Search(AddedInDayRange { min: 1, max: 7 })
+            ]
+        );
+        assert!(parse("added:3-").is_err());
+        assert!(parse("added:-3").is_err());
         assert_eq!(
             parse("card:front")?,
             vec![Search(CardTemplate(TemplateKind::Name("front".into())))]
@@ -894,6 +1343,51 @@ mod test {
             parse("preset:default")?,
             vec![Search(Preset("default".into()))]
         );
+        assert_eq!(
+            parse("preset:steps>=2")?,
+            vec![Search(PresetLearningSteps {
+                operator: ">=".into(),
+                count: 2
+            })]
+        );
+        assert_eq!(parse("preset:steps")?, vec![Search(Preset("steps".into()))]);
+        assert!(parse("preset:steps>two").is_err());
+        let media = r#""media:my \"pic\".jpg""#;
+        assert_eq!(
+            parse(media)?,
+            vec![Search(MediaRef(r#"my "pic".jpg"#.into()))]
+        );
+        assert_eq!(write_nodes(&parse(media)?), media);
+        // this replaces searches of a field called "media", in any case, which
+        // now need a wildcard
+        assert_eq!(
+            parse("Media:foo.mp3")?,
+            vec![Search(MediaRef("foo.mp3".into()))]
+        );
+        assert_eq!(
+            parse("medi_:foo.mp3")?,
+            vec![Search(SingleField {
+                field: "medi_".into(),
+                text: "foo.mp3".into(),
+                is_re: false,
+                match_case: false
+            })]
+        );
+        assert_eq!(
+            parse(r#""raw:<img src=a.jpg>""#)?,
+            vec![Search(RawText("<img src=a.jpg>".into()))]
+        );
+        // likewise for a field called "raw"
+        assert_eq!(parse("Raw:text")?, vec![Search(RawText("text".into()))]);
+        assert_eq!(
+            parse("ra_:text")?,
+            vec![Search(SingleField {
+                field: "ra_".into(),
+                text: "text".into(),
+                is_re: false,
+                match_case: false
+            })]
+        );
 
         assert_eq!(parse("note:basic")?, vec![Search(Notetype("basic".into()))]);
         assert_eq!(
@@ -915,20 +1409,204 @@ mod test {
             vec![Search(NoteIds("1237123712,2,3".into()))]
         );
         assert_eq!(parse("is:due")?, vec![Search(State(StateKind::Due))]);
-        assert_eq!(parse("flag:3")?, vec![Search(Flag(3))]);
+        assert_eq!(
+            parse("is:scheduled")?,
+            vec![Search(State(StateKind::Scheduled))]
+        );
+        assert_eq!(
+            parse("is:overdue")?,
+            vec![Search(State(StateKind::Overdue))]
+        );
+        assert_eq!(parse("is:staledupecheck")?, vec![Search(StaleChecksum)]);
+        assert_eq!(parse("is:poscollision")?, vec![Search(PositionCollision)]);
+        assert_eq!(parse("is:fieldmismatch")?, vec![Search(FieldCountMismatch)]);
+        assert_eq!(parse("is:duplicate")?, vec![Search(AnyDuplicate)]);
+        assert_eq!(parse("is:untagged")?, vec![Search(Untagged)]);
+        assert_eq!(parse("is:leech")?, vec![Search(Leech)]);
+        assert_eq!(parse("flag:3")?, vec![Search(Flag(vec![3]))]);
+        assert_eq!(parse("flag:2,1,2")?, vec![Search(Flag(vec![1, 2]))]);
+        assert_eq!(parse("flag:any")?, vec![Search(AnyFlag)]);
+        assert_eq!(parse("introduced:7")?, vec![Search(IntroducedInDays(7))]);
+        assert_eq!(
+            parse("dupefold:12,Café")?,
+            vec![Search(Duplicates {
+                notetype_id: NotetypeId(12),
+                text: "Café".into(),
+                fold: true
+            })]
+        );
+        assert_eq!(parse("lastrating:hard")?, vec![Search(LastRating(2))]);
+        assert_eq!(parse("lastrating:4")?, vec![Search(LastRating(4))]);
+        assert_eq!(
+            parse("dueweekday:sat,Sunday")?,
+            vec![Search(DueOnWeekdays(vec![Weekday::Sat, Weekday::Sun]))]
+        );
 
         assert_eq!(
             parse("prop:ivl>3")?,
             vec![Search(Property {
                 operator: ">".into(),
-                kind: PropertyKind::Interval(3)
+                kind: PropertyKind::Interval {
+                    days: 3,
+                    unit: None
+                }
+            })]
+        );
+        assert_eq!(
+            parse("prop:ivl>=3w")?,
+            vec![Search(Property {
+                operator: ">=".into(),
+                kind: PropertyKind::Interval {
+                    days: 21,
+                    unit: Some(IntervalUnit::Weeks)
+                }
+            })]
+        );
+        assert_eq!(
+            parse("prop:ivl<2y")?,
+            vec![Search(Property {
+                operator: "<".into(),
+                kind: PropertyKind::Interval {
+                    days: 730,
+                    unit: Some(IntervalUnit::Years)
+                }
             })]
         );
+        assert_eq!(
+            parse("rated:7:2-3")?,
+            vec![Search(Rated {
+                days: 7,
+                ease: RatingKind::AnswerButtonRange(2, 3)
+            })]
+        );
+        assert_eq!(
+            parse("rated:session RATED:Session:2-3 resched:session")?,
+            vec![
+                Search(RatedInSession {
+                    ease: RatingKind::AnyAnswerButton
+                }),
+                And,
+                Search(RatedInSession {
+                    ease: RatingKind::AnswerButtonRange(2, 3)
+                }),
+                And,
+                Search(RatedInSession {
+                    ease: RatingKind::ManualReschedule
+                }),
+            ]
+        );
+        assert_eq!(
+            parse("prop:ed>=-3")?,
+            vec![Search(Property {
+                operator: ">=".into(),
+                kind: PropertyKind::Edited(-3)
+            })]
+        );
+        assert!(parse("prop:ed=1").is_err());
+        assert_eq!(
+            parse("prop:due=2024-01-15")?,
+            vec![Search(Property {
+                operator: "=".into(),
+                kind: PropertyKind::DueOnDate(NaiveDate::from_ymd_opt(2024, 1, 15).unwrap())
+            })]
+        );
+        assert_eq!(
+            parse("prop:due>-2")?,
+            vec![Search(Property {
+                operator: ">".into(),
+                kind: PropertyKind::Due(-2)
+            })]
+        );
+        assert_eq!(
+            parse("prop:duegap<2")?,
+            vec![Search(Property {
+                operator: "<".into(),
+                kind: PropertyKind::DueAfterLastReview(2)
+            })]
+        );
+        assert!(parse("prop:ivl>3x").is_err());
+        assert!(parse("prop:ivl>w").is_err());
+        assert!(parse("prop:ivl>4294967295m").is_err());
         assert_eq!(
             parse("prop:ease<=3.3")?,
             vec![Search(Property {
                 operator: "<=".into(),
-                kind: PropertyKind::Ease(3.3)
+                kind: PropertyKind::Ease {
+                    value: 3.3,
+                    percent: false
+                }
+            })]
+        );
+        assert_eq!(
+            parse("prop:ease>=250%")?,
+            vec![Search(Property {
+                operator: ">=".into(),
+                kind: PropertyKind::Ease {
+                    value: 250.0,
+                    percent: true
+                }
+            })]
+        );
+        assert_eq!(
+            parse("prop:s>30")?,
+            vec![Search(Property {
+                operator: ">".into(),
+                kind: PropertyKind::Stability(30.0)
+            })]
+        );
+        assert_eq!(
+            parse("prop:d<0.3")?,
+            vec![Search(Property {
+                operator: "<".into(),
+                kind: PropertyKind::Difficulty(0.3)
+            })]
+        );
+        assert_eq!(
+            parse("prop:easedev<-0.3")?,
+            vec![Search(Property {
+                operator: "<".into(),
+                kind: PropertyKind::EaseDeviation(-0.3)
+            })]
+        );
+        assert_eq!(
+            parse("prop:rated:14>3 prop:rated:>=2")?,
+            vec![
+                Search(Property {
+                    operator: ">".into(),
+                    kind: PropertyKind::RatedCount {
+                        days: Some(14),
+                        count: 3
+                    }
+                }),
+                And,
+                Search(Property {
+                    operator: ">=".into(),
+                    kind: PropertyKind::RatedCount {
+                        days: None,
+                        count: 2
+                    }
+                })
+            ]
+        );
+        assert_eq!(
+            parse("prop:fields<3")?,
+            vec![Search(Property {
+                operator: "<".into(),
+                kind: PropertyKind::FieldCount(3)
+            })]
+        );
+        assert_eq!(
+            parse("prop:cards!=2")?,
+            vec![Search(Property {
+                operator: "!=".into(),
+                kind: PropertyKind::CardCount(2)
+            })]
+        );
+        assert_eq!(
+            parse("prop:tags=1")?,
+            vec![Search(Property {
+                operator: "=".into(),
+                kind: PropertyKind::TagCount(1)
             })]
         );
         assert_eq!(
@@ -962,28 +1640,54 @@ mod test {
             })]
         );
         assert_eq!(parse("has-cd:r")?, vec![Search(CustomData("r".into()))]);
+        assert_eq!(
+            parse("datakey:my_addon_2")?,
+            vec![Search(DataKey("my_addon_2".into()))]
+        );
 
         Ok(())
     }
 
     #[test]
     fn errors() {
+        use std::ops::Range;
+
         use FailKind::*;
 
         use crate::error::AnkiError;
 
         fn assert_err_kind(input: &str, kind: FailKind) {
-            assert_eq!(parse(input), Err(AnkiError::SearchError { source: kind }));
+            assert_eq!(failkind(input), kind);
         }
 
         fn failkind(input: &str) -> SearchErrorKind {
-            if let Err(AnkiError::SearchError { source: err }) = parse(input) {
+            if let Err(AnkiError::SearchError { source: err, .. }) = parse(input) {
                 err
             } else {
                 panic!("expected search error");
             }
         }
 
+        fn failspan(input: &str) -> Range<usize> {
+            if let Err(AnkiError::SearchError {
+                span: Some(span), ..
+            }) = parse(input)
+            {
+                span
+            } else {
+                panic!("expected search error with span");
+            }
+        }
+
+        // spans are byte offsets into the untrimmed input
+        assert_eq!(failspan("foo ( bar"), 4..9);
+        assert_eq!(failspan("  (foo"), 2..6);
+        assert_eq!(failspan("é (foo (bar)"), 3..13);
+        assert_eq!(failspan("foo ) bar"), 4..9);
+        assert_eq!(failspan("ä flag:9"), 8..9);
+        assert_eq!(failspan("foo prop:due<2024-02-30"), 13..23);
+        assert_eq!(failspan("tag:re:(proj"), 7..12);
+
         assert_err_kind("foo and", MisplacedAnd);
         assert_err_kind("and foo", MisplacedAnd);
         assert_err_kind("and", MisplacedAnd);
@@ -1098,11 +1802,39 @@ mod test {
             },
         );
 
+        assert_err_kind(
+            "dueweekday:sat,sonday",
+            InvalidWeekday {
+                provided: "sonday".into(),
+            },
+        );
+        assert_err_kind(
+            "dueweekday:",
+            InvalidWeekday {
+                provided: "".into(),
+            },
+        );
+
+        assert_err_kind(
+            "datakey:",
+            InvalidDataKey {
+                provided: "".into(),
+            },
+        );
+        assert_err_kind(
+            "datakey:a.b",
+            InvalidDataKey {
+                provided: "a.b".into(),
+            },
+        );
+
         assert_err_kind(r#""flag: ""#, InvalidFlag);
         assert_err_kind("flag:-0", InvalidFlag);
         assert_err_kind("flag:", InvalidFlag);
         assert_err_kind("flag:8", InvalidFlag);
         assert_err_kind("flag:1.1", InvalidFlag);
+        assert_err_kind("flag:1,", InvalidFlag);
+        assert_err_kind("flag:1,8", InvalidFlag);
 
         for term in &["added", "edited", "rated", "resched"] {
             assert!(matches!(
@@ -1139,6 +1871,39 @@ mod test {
             failkind("rated:0:foo"),
             SearchErrorKind::InvalidAnswerButton { .. }
         ));
+        assert!(matches!(
+            failkind("rated:7:3-2"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+        assert!(matches!(
+            failkind("rated:7:2-5"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+        assert!(matches!(
+            failkind("rated:7:1-2-3"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+        assert!(matches!(
+            failkind("lastrating:2-3"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+        assert!(matches!(
+            failkind("lastrating:lapse"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+
+        assert!(matches!(
+            failkind("lastrating:meh"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+        assert!(matches!(
+            failkind("lastrating:5"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
+        assert!(matches!(
+            failkind("lastrating:0"),
+            SearchErrorKind::InvalidAnswerButton { .. }
+        ));
 
         assert!(matches!(
             failkind("dupe:"),
@@ -1183,6 +1948,13 @@ mod test {
                 provided: "cdn:=5".to_string(),
             },
         );
+        // keys are inlined into the SQL, so only alphanumerics are accepted
+        assert_err_kind(
+            "prop:cdn:a')>0--=5",
+            InvalidPropOperator {
+                provided: "cdn:a".to_string(),
+            },
+        );
         assert_err_kind(
             "prop:cds=s",
             InvalidPropProperty {
@@ -1247,6 +2019,25 @@ mod test {
             SearchErrorKind::InvalidWholeNumber { .. }
         ));
 
+        assert!(matches!(
+            failkind("tag:re:[a"),
+            SearchErrorKind::InvalidRegex { provided, .. } if provided == "[a"
+        ));
+
+        // dates
+
+        assert_err_kind(
+            "prop:due=2024-13-01",
+            InvalidDate {
+                context: "due=2024-13-01".into(),
+                provided: "2024-13-01".into(),
+            },
+        );
+        assert!(matches!(
+            failkind("prop:due=2024-1"),
+            SearchErrorKind::InvalidDate { .. }
+        ));
+
         // float
 
         assert!(matches!(
@@ -1261,5 +2052,14 @@ mod test {
             failkind("prop:ease<1,3"),
             SearchErrorKind::InvalidNumber { .. }
         ));
+        for ease in ["%", "250%%", "-2.5", "inf", "NaN%"] {
+            assert_eq!(
+                failkind(&format!("prop:ease>{ease}")),
+                SearchErrorKind::InvalidNumber {
+                    provided: ease.into(),
+                    context: format!("ease>{ease}"),
+                }
+            );
+        }
     }
 }
@@ -4,19 +4,26 @@
 mod browser_table;
 mod search_node;
 
-use std::str::FromStr;
 use std::sync::Arc;
 
 use anki_proto::generic;
 use anki_proto::search::sort_order::Value as SortOrderProto;
 
 use crate::browser_table::Column;
+use crate::findreplace::search_regex;
+use crate::findreplace::FindReplaceDescription;
 use crate::notes::service::to_note_ids;
 use crate::prelude::*;
+use crate::search::contradictory_terms;
+use crate::search::parse_search;
+use crate::search::remove_search_node;
 use crate::search::replace_search_node;
 use crate::search::service::browser_table::string_list_to_browser_columns;
 use crate::search::JoinSearches;
+use crate::search::Negated;
 use crate::search::Node;
+use crate::search::SearchBuilder;
+use crate::search::SearchNode;
 use crate::search::SortMode;
 
 impl crate::services::SearchService for Collection {
@@ -32,21 +39,75 @@ impl crate::services::SearchService for Collection {
         &mut self,
         input: anki_proto::search::SearchRequest,
     ) -> Result<anki_proto::search::SearchResponse> {
-        let order = input.order.unwrap_or_default().value.into();
-        let cids = self.search_cards(&input.search, order)?;
+        let order = input.order.unwrap_or_default().value.try_into()?;
+        let mut search = self.parse_search_cached(&input.search)?;
+        if input.exclude_filtered {
+            // joined at the top level, so no term in the search can undo it
+            search = search
+                .and(SearchNode::Deck("filtered".into()).negated())
+                .try_into_search()?;
+        }
+        let (cids, total) = if input.include_total {
+            let (cids, total) =
+                self.search_cards_page_with_total(search, order, input.limit, input.offset)?;
+            (cids, Some(total as u32))
+        } else {
+            let cids = self.search_cards_page(search, order, input.limit, input.offset)?;
+            (cids, None)
+        };
         Ok(anki_proto::search::SearchResponse {
             ids: cids.into_iter().map(|v| v.0).collect(),
+            total,
         })
     }
 
+    fn explain_search(
+        &mut self,
+        input: anki_proto::search::SearchRequest,
+    ) -> Result<anki_proto::search::ExplainSearchResponse> {
+        let order = input.order.unwrap_or_default().value.try_into()?;
+        let (sql, args) = self.explain_search(&input.search, order, input.limit, input.offset)?;
+        Ok(anki_proto::search::ExplainSearchResponse { sql, args })
+    }
+
     fn search_notes(
         &mut self,
         input: anki_proto::search::SearchRequest,
     ) -> Result<anki_proto::search::SearchResponse> {
-        let order = input.order.unwrap_or_default().value.into();
-        let nids = self.search_notes(&input.search, order)?;
+        let order = input.order.unwrap_or_default().value.try_into()?;
+        let search = self.parse_search_cached(&input.search)?;
+        let (nids, total) = if input.collapse_duplicates {
+            let nids = self.search_notes_without_duplicates(search, order)?;
+            let total = input.include_total.then_some(nids.len() as u32);
+            let nids = nids
+                .into_iter()
+                .skip(input.offset.unwrap_or_default() as usize)
+                .take(input.limit.map_or(usize::MAX, |limit| limit as usize))
+                .collect();
+            (nids, total)
+        } else if input.include_total {
+            let (nids, total) =
+                self.search_notes_page_with_total(search, order, input.limit, input.offset)?;
+            (nids, Some(total as u32))
+        } else {
+            let nids = self.search_notes_page(search, order, input.limit, input.offset)?;
+            (nids, None)
+        };
+        Ok(anki_proto::search::SearchResponse {
+            ids: nids.into_iter().map(|v| v.0).collect(),
+            total,
+        })
+    }
+
+    fn note_ids_of_card_search(
+        &mut self,
+        input: anki_proto::search::SearchRequest,
+    ) -> Result<anki_proto::search::SearchResponse> {
+        let order = input.order.unwrap_or_default().value.try_into()?;
+        let nids = self.note_ids_of_card_search(&input.search, order)?;
         Ok(anki_proto::search::SearchResponse {
             ids: nids.into_iter().map(|v| v.0).collect(),
+            total: None,
         })
     }
 
@@ -55,22 +116,22 @@ impl crate::services::SearchService for Collection {
         input: anki_proto::search::JoinSearchNodesRequest,
     ) -> Result<generic::String> {
         let existing_node: Node = input.existing_node.unwrap_or_default().try_into()?;
-        let additional_node: Node = input.additional_node.unwrap_or_default().try_into()?;
-
-        Ok(
-            match anki_proto::search::search_node::group::Joiner::try_from(input.joiner)
-                .unwrap_or_default()
-            {
-                anki_proto::search::search_node::group::Joiner::And => {
-                    existing_node.and_flat(additional_node)
-                }
-                anki_proto::search::search_node::group::Joiner::Or => {
-                    existing_node.or_flat(additional_node)
-                }
-            }
-            .write()
-            .into(),
-        )
+        let additional_nodes =
+            if input.additional_node.is_none() && !input.additional_nodes.is_empty() {
+                input.additional_nodes
+            } else {
+                let mut nodes = vec![input.additional_node.unwrap_or_default()];
+                nodes.extend(input.additional_nodes);
+                nodes
+            };
+        let additional_nodes = additional_nodes
+            .into_iter()
+            .map(Node::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        let joiner = anki_proto::search::search_node::group::Joiner::try_from(input.joiner)
+            .unwrap_or_default();
+
+        Ok(join_search_nodes(existing_node, additional_nodes, joiner, input.deduplicate).into())
     }
 
     fn replace_search_node(
@@ -86,34 +147,79 @@ impl crate::services::SearchService for Collection {
             }
         };
         let replacement = input.replacement_node.unwrap_or_default().try_into()?;
-        Ok(replace_search_node(existing, replacement).into())
+        Ok(if input.remove {
+            remove_search_node(existing, replacement)
+        } else {
+            replace_search_node(existing, replacement)
+        }
+        .into())
     }
 
     fn find_and_replace(
         &mut self,
         input: anki_proto::search::FindAndReplaceRequest,
     ) -> Result<anki_proto::collection::OpChangesWithCount> {
-        let mut search = if input.regex {
-            input.search
-        } else {
-            regex::escape(&input.search)
-        };
-        if !input.match_case {
-            search = format!("(?i){}", search);
-        }
-        let mut nids = to_note_ids(input.nids);
-        let field_name = if input.field_name.is_empty() {
-            None
-        } else {
-            Some(input.field_name)
-        };
-        let repl = input.replacement;
+        let html_aware = input.html_aware;
+        let (nids, search, repl, field_names, description) = find_and_replace_args(self, input)?;
+        self.find_and_replace(
+            nids,
+            &search,
+            &repl,
+            field_names,
+            html_aware,
+            Some(description),
+        )
+        .map(Into::into)
+    }
 
-        if nids.is_empty() {
-            nids = self.search_notes_unordered("")?
-        };
-        self.find_and_replace(nids, &search, &repl, field_name)
-            .map(Into::into)
+    fn find_and_replace_with_counts(
+        &mut self,
+        input: anki_proto::search::FindAndReplaceRequest,
+    ) -> Result<anki_proto::search::FindAndReplaceCountsResponse> {
+        let html_aware = input.html_aware;
+        let (nids, search, repl, field_names, description) = find_and_replace_args(self, input)?;
+        let out = self.find_and_replace_with_counts(
+            nids,
+            &search,
+            &repl,
+            field_names,
+            html_aware,
+            Some(description),
+        )?;
+        Ok(anki_proto::search::FindAndReplaceCountsResponse {
+            changes: Some(out.changes.into()),
+            notes: out
+                .output
+                .into_iter()
+                .map(|(nid, replacements)| {
+                    anki_proto::search::find_and_replace_counts_response::Note {
+                        note_id: nid.0,
+                        replacements: replacements as u32,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    fn preview_find_and_replace(
+        &mut self,
+        input: anki_proto::search::FindAndReplaceRequest,
+    ) -> Result<anki_proto::search::FindAndReplacePreviewResponse> {
+        let html_aware = input.html_aware;
+        let (nids, search, repl, field_names, _) = find_and_replace_args(self, input)?;
+        let notes = self
+            .preview_find_and_replace(nids, &search, &repl, field_names, html_aware)?
+            .into_iter()
+            .map(
+                |preview| anki_proto::search::find_and_replace_preview_response::Note {
+                    note_id: preview.note_id.0,
+                    field_name: preview.field_name,
+                    before: preview.before,
+                    after: preview.after,
+                },
+            )
+            .collect();
+        Ok(anki_proto::search::FindAndReplacePreviewResponse { notes })
     }
 
     fn all_browser_columns(&mut self) -> Result<anki_proto::search::BrowserColumns> {
@@ -131,18 +237,490 @@ impl crate::services::SearchService for Collection {
     ) -> Result<anki_proto::search::BrowserRow> {
         self.browser_row_for_id(input.val).map(Into::into)
     }
+
+    fn browser_rows_for_ids(
+        &mut self,
+        input: anki_proto::search::BrowserRowsRequest,
+    ) -> Result<anki_proto::search::BrowserRows> {
+        let columns = (!input.columns.is_empty()).then(|| {
+            string_list_to_browser_columns(generic::StringList {
+                vals: input.columns,
+            })
+        });
+        let rows = self.browser_rows_for_ids(&input.ids, columns)?;
+        Ok(anki_proto::search::BrowserRows { rows })
+    }
+
+    fn validate_search(&mut self, input: generic::String) -> Result<()> {
+        validate_search(&input.val)
+    }
+
+    fn count_cards(&mut self, input: generic::String) -> Result<generic::UInt32> {
+        let search = self.parse_search_cached(&input.val)?;
+        self.count_cards(search).map(Into::into)
+    }
+
+    fn count_notes(&mut self, input: generic::String) -> Result<generic::UInt32> {
+        let search = self.parse_search_cached(&input.val)?;
+        self.count_notes(search).map(Into::into)
+    }
+
+    fn analyze_search(
+        &mut self,
+        input: generic::String,
+    ) -> Result<anki_proto::search::AnalyzeSearchResponse> {
+        use anki_proto::search::analyze_search_response::Conflict;
+
+        let conflicts = contradictory_terms(&parse_search(&input.val)?)
+            .into_iter()
+            .map(|(first, second)| Conflict { first, second })
+            .collect();
+        Ok(anki_proto::search::AnalyzeSearchResponse { conflicts })
+    }
+}
+
+/// Parse the search without running it.
+pub(crate) fn validate_search(search: &str) -> Result<()> {
+    parse_search(search).map(|_| ())
 }
 
-impl From<Option<SortOrderProto>> for SortMode {
-    fn from(order: Option<SortOrderProto>) -> Self {
+fn join_search_nodes(
+    existing_node: Node,
+    additional_nodes: Vec<Node>,
+    joiner: anki_proto::search::search_node::group::Joiner,
+    deduplicate: bool,
+) -> String {
+    use anki_proto::search::search_node::group::Joiner;
+    let separator = match joiner {
+        Joiner::And => Node::And,
+        Joiner::Or => Node::Or,
+    };
+    let joined = additional_nodes.into_iter().fold(
+        SearchBuilder::from_root_joined_by(existing_node, &separator),
+        |builder, node| {
+            let additional = SearchBuilder::from_root_joined_by(node, &separator);
+            match joiner {
+                Joiner::And => builder.and_flat(additional),
+                Joiner::Or => builder.or_flat(additional),
+            }
+        },
+    );
+    if deduplicate {
+        joined.without_duplicates(&separator).write()
+    } else {
+        joined.write()
+    }
+}
+
+/// Returns (nids, search regex, replacement, field names, description).
+fn find_and_replace_args(
+    col: &mut Collection,
+    input: anki_proto::search::FindAndReplaceRequest,
+) -> Result<(
+    Vec<NoteId>,
+    String,
+    String,
+    Vec<String>,
+    FindReplaceDescription,
+)> {
+    let search = search_regex(
+        &input.search,
+        input.regex,
+        input.match_case,
+        input.whole_word,
+    );
+    let mut nids = to_note_ids(input.nids);
+    let mut field_names = input.field_names;
+    if !input.field_name.is_empty() {
+        field_names.push(input.field_name);
+    }
+    if nids.is_empty() {
+        nids = col.search_notes_unordered("")?
+    };
+    let description = FindReplaceDescription {
+        search: input.search,
+        replacement: input.replacement.clone(),
+    };
+    Ok((nids, search, input.replacement, field_names, description))
+}
+
+impl TryFrom<Option<SortOrderProto>> for SortMode {
+    type Error = AnkiError;
+
+    fn try_from(order: Option<SortOrderProto>) -> Result<Self> {
         use anki_proto::search::sort_order::Value as V;
-        match order.unwrap_or(V::None(generic::Empty {})) {
+        Ok(match order.unwrap_or(V::None(generic::Empty {})) {
             V::None(_) => SortMode::NoOrder,
             V::Custom(s) => SortMode::Custom(s),
-            V::Builtin(b) => SortMode::Builtin {
-                column: Column::from_str(&b.column).unwrap_or_default(),
-                reverse: b.reverse,
+            V::Builtin(b) => match b.recent_review_days {
+                Some(days) => SortMode::RecentReviews {
+                    days,
+                    reverse: b.reverse,
+                },
+                None => match SortMode::from_column_key(&b.column, b.reverse)? {
+                    SortMode::Builtin {
+                        column: Column::SortField,
+                        reverse,
+                    } if b.numeric => SortMode::NumericSortField { reverse },
+                    mode => mode,
+                },
             },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anki_proto::search::search_node::group::Joiner;
+
+    use super::*;
+    use crate::services::SearchService;
+    use crate::tests::DeckAdder;
+    use crate::tests::NoteAdder;
+
+    #[test]
+    fn joining_several_nodes_matches_chained_joins() -> Result<()> {
+        let existing = || Node::Search(SearchNode::from_deck_name("foo"));
+        let additional = || {
+            vec![
+                Node::Search(SearchNode::from_tag_name("bar")),
+                Node::Group(vec![
+                    Node::Search(SearchNode::from_tag_name("baz")),
+                    Node::Or,
+                    Node::Search(SearchNode::from_tag_name("qux")),
+                ]),
+                Node::Not(Box::new(Node::Search(SearchNode::from_deck_name("quux")))),
+            ]
+        };
+        for joiner in [Joiner::And, Joiner::Or] {
+            let mut chained = join_search_nodes(existing(), vec![], joiner, false);
+            for node in additional() {
+                let previous = Node::Group(parse_search(&chained)?);
+                chained = join_search_nodes(previous, vec![node], joiner, false);
+            }
+            assert_eq!(
+                join_search_nodes(existing(), additional(), joiner, false),
+                chained
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn joining_nodes_without_duplicates() -> Result<()> {
+        let search = |text: &str| Ok::<_, AnkiError>(Node::Group(parse_search(text)?));
+        let join = |existing: &str, additional: &[&str], joiner| {
+            let additional = additional
+                .iter()
+                .map(|text| search(text))
+                .collect::<Result<Vec<_>>>()?;
+            Ok::<_, AnkiError>(join_search_nodes(
+                search(existing)?,
+                additional,
+                joiner,
+                true,
+            ))
+        };
+
+        assert_eq!(
+            join(
+                "tag:foo",
+                &["tag:foo", "-deck:x", "-deck:x", "(a or b)", "deck:x", "(a or b)"],
+                Joiner::And
+            )?,
+            "tag:foo -deck:x (a OR b) deck:x"
+        );
+        assert_eq!(join("a or b", &["b", "c"], Joiner::Or)?, "a OR b OR c");
+        // terms of a group with a different joiner are left alone
+        assert_eq!(
+            join("tag:foo deck:x", &["tag:foo"], Joiner::Or)?,
+            "(tag:foo deck:x) OR tag:foo"
+        );
+        assert_eq!(join("a b", &["a or c"], Joiner::And)?, "a b (a OR c)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn field_regex_nodes() -> Result<()> {
+        use anki_proto::search::search_node::Field;
+        use anki_proto::search::search_node::Filter;
+
+        let field_node = |text: &str, is_re| {
+            Node::try_from(anki_proto::search::SearchNode {
+                filter: Some(Filter::Field(Field {
+                    field_name: "Front".into(),
+                    text: text.into(),
+                    is_re,
+                    match_case: false,
+                })),
+            })
+        };
+        // wildcards are only escaped outside a regex
+        assert_eq!(
+            SearchBuilder::from(field_node("^Hel*o_", true)?).write(),
+            "Front:re:^Hel*o_"
+        );
+        assert_eq!(
+            SearchBuilder::from(field_node("Hel*o_", false)?).write(),
+            r"Front:Hel\*o\_"
+        );
+        assert!(matches!(
+            field_node("(Hello", true),
+            Err(AnkiError::InvalidRegex { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tag_regex_nodes() -> Result<()> {
+        use anki_proto::search::search_node::Filter;
+
+        let tag_node = |re: &str| {
+            Node::try_from(anki_proto::search::SearchNode {
+                filter: Some(Filter::TagRegex(re.into())),
+            })
+        };
+        assert_eq!(
+            SearchBuilder::from(tag_node("^proj")?).write(),
+            "tag:re:^proj"
+        );
+        assert!(matches!(
+            tag_node("(proj"),
+            Err(AnkiError::InvalidRegex { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn notetype_id_nodes() -> Result<()> {
+        let node = Node::try_from(anki_proto::search::SearchNode {
+            filter: Some(anki_proto::search::search_node::Filter::NotetypeId(
+                1234567890,
+            )),
+        })?;
+        assert_eq!(SearchBuilder::from(node).write(), "mid:1234567890");
+
+        Ok(())
+    }
+
+    #[test]
+    fn card_id_list_nodes() -> Result<()> {
+        use anki_proto::search::search_node::Filter;
+        use anki_proto::search::search_node::IdList;
+
+        let mut col = Collection::new();
+        for _ in 0..3 {
+            NoteAdder::basic(&mut col).add(&mut col);
         }
+        let mut cids = col.search_cards("", SortMode::NoOrder)?;
+        cids.sort();
+        let node = Node::try_from(anki_proto::search::SearchNode {
+            filter: Some(Filter::Cids(IdList {
+                ids: vec![cids[0].0, cids[2].0],
+            })),
+        })?;
+        assert_eq!(
+            SearchBuilder::from(node.clone()).write(),
+            format!("cid:{},{}", cids[0], cids[2])
+        );
+        let mut found = col.search_cards(node, SortMode::NoOrder)?;
+        found.sort();
+        assert_eq!(found, [cids[0], cids[2]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn preset_learning_steps_nodes() -> Result<()> {
+        use anki_proto::search::search_node::Filter;
+        use anki_proto::search::search_node::PresetLearningSteps;
+
+        let node = |operator: &str| {
+            Node::try_from(anki_proto::search::SearchNode {
+                filter: Some(Filter::PresetLearningSteps(PresetLearningSteps {
+                    operator: operator.into(),
+                    count: 2,
+                })),
+            })
+        };
+        assert_eq!(SearchBuilder::from(node(">")?).write(), "preset:steps>2");
+        assert!(node("=>").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn browser_rows_with_requested_columns() -> Result<()> {
+        let mut col = Collection::new();
+        let mut note = NoteAdder::basic(&mut col).fields(&["front", ""]).note();
+        note.tags = vec!["tag".into()];
+        col.add_note(&mut note, DeckId(1))?;
+        let cid = col.search_cards(note.id, SortMode::NoOrder)?[0];
+
+        let cells = |col: &mut Collection, columns: &[&str]| -> Result<Vec<String>> {
+            let rows = SearchService::browser_rows_for_ids(
+                col,
+                anki_proto::search::BrowserRowsRequest {
+                    ids: vec![cid.0],
+                    columns: columns.iter().map(ToString::to_string).collect(),
+                },
+            )?;
+            Ok(rows.rows[0]
+                .cells
+                .iter()
+                .map(|cell| cell.text.clone())
+                .collect())
+        };
+        assert_eq!(
+            cells(&mut col, &["noteFld", "noSuchColumn", "noteTags"])?,
+            ["front", "", "tag"]
+        );
+        assert_eq!(cells(&mut col, &["noteTags", "noteFld"])?, ["tag", "front"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn buried_state_nodes() -> Result<()> {
+        use anki_proto::search::search_node::CardState;
+        use anki_proto::search::search_node::Filter;
+
+        let written = |state: CardState| {
+            Node::try_from(anki_proto::search::SearchNode {
+                filter: Some(Filter::CardState(state as i32)),
+            })
+            .map(|node| SearchBuilder::from(node).write())
+        };
+        assert_eq!(written(CardState::Buried)?, "is:buried");
+        assert_eq!(written(CardState::BuriedManually)?, "is:buried-manually");
+        assert_eq!(written(CardState::BuriedSibling)?, "is:buried-sibling");
+        assert_eq!(written(CardState::NoteSuspended)?, "is:note-suspended");
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_cards_excluding_filtered() -> Result<()> {
+        let mut col = Collection::new();
+        let filtered = DeckAdder::new("filtered").filtered(true).add(&mut col);
+        let normal = NoteAdder::basic(&mut col).add(&mut col);
+        let pulled = NoteAdder::basic(&mut col).add(&mut col);
+        col.storage.db.execute(
+            "update cards set did = ?, odid = 1 where nid = ?",
+            (filtered.id, pulled.id),
+        )?;
+        let mut search = |search: &str, exclude_filtered| {
+            SearchService::search_cards(
+                &mut col,
+                anki_proto::search::SearchRequest {
+                    search: search.into(),
+                    exclude_filtered,
+                    ..Default::default()
+                },
+            )
+            .map(|resp| resp.ids.len())
+        };
+
+        assert_eq!(search("", false)?, 2);
+        assert_eq!(search("", true)?, 1);
+        // an alternative that matches filtered cards doesn't bring them back
+        assert_eq!(search("deck:default or deck:filtered", true)?, 1);
+        assert_eq!(search("deck:filtered", true)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_notes_collapsing_duplicates() -> Result<()> {
+        use anki_proto::search::SortOrder;
+
+        let mut col = Collection::new();
+        let basic = col.basic_notetype();
+        let reversed = col.basic_rev_notetype();
+        let mut add = |notetype: &Notetype, front: &str| {
+            NoteAdder::new(notetype)
+                .fields(&[front, ""])
+                .add(&mut col)
+                .id
+        };
+        let first = add(&basic, "<b>dupe</b>");
+        let unique = add(&basic, "unique");
+        let second = add(&basic, "dupe");
+        let other_notetype = add(&reversed, "dupe");
+        let empty = [add(&basic, ""), add(&basic, "<br>")];
+        let mut search = |search: &str, limit, offset| {
+            SearchService::search_notes(
+                &mut col,
+                anki_proto::search::SearchRequest {
+                    search: search.into(),
+                    order: Some(SortOrder {
+                        value: Some(SortOrderProto::Custom("n.id desc".into())),
+                    }),
+                    limit,
+                    offset,
+                    include_total: true,
+                    collapse_duplicates: true,
+                    ..Default::default()
+                },
+            )
+            .map(|resp| (to_note_ids(resp.ids), resp.total))
+        };
+
+        // the oldest note of a group stands in for it, in its own position
+        assert_eq!(
+            search("", None, None)?,
+            (
+                vec![empty[1], empty[0], other_notetype, unique, first],
+                Some(5)
+            )
+        );
+        // paging applies to the collapsed results
+        assert_eq!(
+            search("", Some(2), Some(2))?,
+            (vec![other_notetype, unique], Some(5))
+        );
+        // only duplicates within the results are collapsed
+        assert_eq!(
+            search(&format!("-nid:{first}"), None, Some(3))?,
+            (vec![second, unique], Some(5))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn blank_field_nodes() -> Result<()> {
+        use anki_proto::search::search_node::Field;
+        use anki_proto::search::search_node::Filter;
+
+        let node = Node::try_from(anki_proto::search::SearchNode {
+            filter: Some(Filter::Field(Field {
+                field_name: "Front".into(),
+                text: "".into(),
+                is_re: false,
+                match_case: false,
+            })),
+        })?;
+        assert_eq!(SearchBuilder::from(node).write(), "Front:");
+
+        Ok(())
+    }
+
+    #[test]
+    fn any_flag_node() -> Result<()> {
+        use anki_proto::search::search_node::Filter;
+        use anki_proto::search::search_node::Flag;
+
+        let node = Node::try_from(anki_proto::search::SearchNode {
+            filter: Some(Filter::Flag(Flag::Any as i32)),
+        })?;
+        assert_eq!(node, Node::Search(SearchNode::AnyFlag));
+        assert_eq!(SearchBuilder::from(node.negated()).write(), "-flag:any");
+
+        Ok(())
     }
 }
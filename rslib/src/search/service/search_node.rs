@@ -3,6 +3,7 @@
 
 use anki_proto::search::search_node::IdList;
 use itertools::Itertools;
+use regex::Regex;
 
 use crate::prelude::*;
 use crate::search::parse_search;
@@ -13,6 +14,7 @@ use crate::search::RatingKind;
 use crate::search::SearchNode;
 use crate::search::StateKind;
 use crate::search::TemplateKind;
+use crate::storage::write_comma_separated_ids;
 use crate::text::escape_anki_wildcards;
 use crate::text::escape_anki_wildcards_for_search_node;
 
@@ -26,21 +28,39 @@ impl TryFrom<anki_proto::search::SearchNode> for Node {
         Ok(if let Some(filter) = msg.filter {
             match filter {
                 Filter::Tag(s) => SearchNode::from_tag_name(&s).into(),
+                Filter::TagRegex(re) => {
+                    Regex::new(&format!("(?i){re}"))?;
+                    Node::Search(SearchNode::Tag {
+                        tag: re,
+                        is_re: true,
+                    })
+                }
                 Filter::Deck(s) => SearchNode::from_deck_name(&s).into(),
                 Filter::Note(s) => SearchNode::from_notetype_name(&s).into(),
+                Filter::Preset(s) => Node::Search(SearchNode::Preset(escape_anki_wildcards(&s))),
+                Filter::MediaRef(s) => {
+                    Node::Search(SearchNode::MediaRef(escape_anki_wildcards(&s)))
+                }
+                Filter::RawText(s) => Node::Search(SearchNode::RawText(escape_anki_wildcards(&s))),
                 Filter::Template(u) => {
                     Node::Search(SearchNode::CardTemplate(TemplateKind::Ordinal(u as u16)))
                 }
                 Filter::Nid(nid) => Node::Search(SearchNode::NoteIds(nid.to_string())),
                 Filter::Nids(nids) => Node::Search(SearchNode::NoteIds(id_list_to_string(nids))),
+                Filter::Cids(cids) => Node::Search(SearchNode::CardIds(id_list_to_string(cids))),
                 Filter::Dupe(dupe) => Node::Search(SearchNode::Duplicates {
                     notetype_id: dupe.notetype_id.into(),
                     text: dupe.first_field,
+                    fold: dupe.fold,
                 }),
                 Filter::FieldName(s) => Node::Search(SearchNode::SingleField {
                     field: escape_anki_wildcards_for_search_node(&s),
                     text: "_*".to_string(),
                     is_re: false,
+                    match_case: false,
+                }),
+                Filter::Rated(rated) if rated.session => Node::Search(SearchNode::RatedInSession {
+                    ease: rated.rating().into(),
                 }),
                 Filter::Rated(rated) => Node::Search(SearchNode::Rated {
                     days: rated.days,
@@ -62,17 +82,27 @@ impl TryFrom<anki_proto::search::SearchNode> for Node {
                         .unwrap_or_default()
                         .into(),
                 )),
-                Filter::Flag(flag) => match Flag::try_from(flag).unwrap_or(Flag::Any) {
-                    Flag::None => Node::Search(SearchNode::Flag(0)),
-                    Flag::Any => Node::Not(Box::new(Node::Search(SearchNode::Flag(0)))),
-                    Flag::Red => Node::Search(SearchNode::Flag(1)),
-                    Flag::Orange => Node::Search(SearchNode::Flag(2)),
-                    Flag::Green => Node::Search(SearchNode::Flag(3)),
-                    Flag::Blue => Node::Search(SearchNode::Flag(4)),
-                    Flag::Pink => Node::Search(SearchNode::Flag(5)),
-                    Flag::Turquoise => Node::Search(SearchNode::Flag(6)),
-                    Flag::Purple => Node::Search(SearchNode::Flag(7)),
-                },
+                Filter::Flag(flag) => {
+                    match flag_number(Flag::try_from(flag).unwrap_or(Flag::Any)) {
+                        Some(flag) => Node::Search(SearchNode::Flag(vec![flag])),
+                        None => Node::Search(SearchNode::AnyFlag),
+                    }
+                }
+                Filter::Flags(list) => {
+                    let flags: Option<Vec<_>> = list
+                        .flags
+                        .into_iter()
+                        .map(|flag| flag_number(Flag::try_from(flag).unwrap_or(Flag::Any)))
+                        .collect();
+                    match flags {
+                        Some(mut flags) if !flags.is_empty() => {
+                            flags.sort_unstable();
+                            flags.dedup();
+                            Node::Search(SearchNode::Flag(flags))
+                        }
+                        _ => invalid_input!("flag list must contain specific flags"),
+                    }
+                }
                 Filter::Negated(term) => Node::try_from(*term)?.negated(),
                 Filter::Group(mut group) => {
                     match group.nodes.len() {
@@ -104,15 +134,44 @@ impl TryFrom<anki_proto::search::SearchNode> for Node {
                         Node::Group(nodes)
                     }
                 }
-                Filter::Field(field) => Node::Search(SearchNode::SingleField {
-                    field: escape_anki_wildcards(&field.field_name),
-                    text: escape_anki_wildcards(&field.text),
-                    is_re: field.is_re,
-                }),
+                Filter::Field(field) => {
+                    // a regex is passed through as is, so it's checked here
+                    // instead of failing when the search is run
+                    let text = if field.is_re {
+                        let text = if field.match_case {
+                            format!("(?-i){}", field.text)
+                        } else {
+                            field.text
+                        };
+                        Regex::new(&format!("(?i){}", text))?;
+                        text
+                    } else {
+                        escape_anki_wildcards(&field.text)
+                    };
+                    Node::Search(SearchNode::SingleField {
+                        field: escape_anki_wildcards(&field.field_name),
+                        text,
+                        is_re: field.is_re,
+                        match_case: field.match_case && !field.is_re,
+                    })
+                }
                 Filter::LiteralText(text) => {
                     let text = escape_anki_wildcards(&text);
                     Node::Search(SearchNode::UnqualifiedText(text))
                 }
+                Filter::Untagged(_) => Node::Search(SearchNode::Untagged),
+                Filter::NotetypeId(id) => Node::Search(SearchNode::NotetypeId(id.into())),
+                Filter::PresetLearningSteps(steps) => {
+                    require!(
+                        ["<", ">", "<=", ">=", "=", "!="].contains(&steps.operator.as_str()),
+                        "invalid operator: {}",
+                        steps.operator
+                    );
+                    Node::Search(SearchNode::PresetLearningSteps {
+                        operator: steps.operator,
+                        count: steps.count,
+                    })
+                }
             }
         } else {
             Node::Search(SearchNode::WholeCollection)
@@ -129,6 +188,7 @@ impl From<anki_proto::search::search_node::Rating> for RatingKind {
             anki_proto::search::search_node::Rating::Easy => RatingKind::AnswerButton(4),
             anki_proto::search::search_node::Rating::Any => RatingKind::AnyAnswerButton,
             anki_proto::search::search_node::Rating::ByReschedule => RatingKind::ManualReschedule,
+            anki_proto::search::search_node::Rating::Lapse => RatingKind::Lapse,
         }
     }
 }
@@ -142,14 +202,32 @@ impl From<anki_proto::search::search_node::CardState> for StateKind {
             anki_proto::search::search_node::CardState::Due => StateKind::Due,
             anki_proto::search::search_node::CardState::Suspended => StateKind::Suspended,
             anki_proto::search::search_node::CardState::Buried => StateKind::Buried,
+            anki_proto::search::search_node::CardState::Scheduled => StateKind::Scheduled,
+            anki_proto::search::search_node::CardState::BuriedManually => StateKind::UserBuried,
+            anki_proto::search::search_node::CardState::BuriedSibling => StateKind::SchedBuried,
+            anki_proto::search::search_node::CardState::NoteSuspended => StateKind::NoteSuspended,
         }
     }
 }
 
+/// The number stored on cards, or None for [Flag::Any].
+fn flag_number(flag: anki_proto::search::search_node::Flag) -> Option<u8> {
+    use anki_proto::search::search_node::Flag;
+    Some(match flag {
+        Flag::None => 0,
+        Flag::Any => return None,
+        Flag::Red => 1,
+        Flag::Orange => 2,
+        Flag::Green => 3,
+        Flag::Blue => 4,
+        Flag::Pink => 5,
+        Flag::Turquoise => 6,
+        Flag::Purple => 7,
+    })
+}
+
 fn id_list_to_string(list: IdList) -> String {
-    list.ids
-        .iter()
-        .map(|i| i.to_string())
-        .collect::<Vec<_>>()
-        .join(",")
+    let mut buf = String::new();
+    write_comma_separated_ids(&mut buf, list.ids);
+    buf
 }
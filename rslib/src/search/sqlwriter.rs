@@ -2,10 +2,14 @@
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::Write;
 use std::ops::Range;
 
+use chrono::Datelike;
+use chrono::Weekday;
 use itertools::Itertools;
+use unicase::UniCase;
 
 use super::parser::Node;
 use super::parser::PropertyKind;
@@ -17,13 +21,18 @@ use super::ReturnItemType;
 use crate::card::CardQueue;
 use crate::card::CardType;
 use crate::collection::Collection;
+use crate::config::ConfigKey;
+use crate::config::StringKey;
 use crate::error::Result;
 use crate::notes::field_checksum;
 use crate::notetype::NotetypeId;
 use crate::prelude::*;
+use crate::revlog::RevlogReviewKind;
 use crate::storage::ids_to_string;
+use crate::text::escape_anki_wildcards_for_search_node;
 use crate::text::glob_matcher;
 use crate::text::is_glob;
+use crate::text::media_filename_for_comparison;
 use crate::text::normalize_to_nfc;
 use crate::text::strip_html_preserving_media_filenames;
 use crate::text::to_custom_re;
@@ -33,6 +42,9 @@ use crate::text::to_text;
 use crate::text::without_combining;
 use crate::timestamp::TimestampSecs;
 
+/// The number of fields stored in a note, counting empty ones.
+const NOTE_FIELD_COUNT: &str = "(length(n.flds) - length(replace(n.flds, char(31), '')) + 1)";
+
 pub(crate) struct SqlWriter<'a> {
     col: &'a mut Collection,
     sql: String,
@@ -63,23 +75,34 @@ impl SqlWriter<'_> {
         table: RequiredTable,
     ) -> Result<(String, Vec<String>)> {
         self.table = self.table.combine(table.combine(node.required_table()));
-        self.write_table_sql();
-        self.write_node_to_sql(node)?;
+        // an empty search needs no filtering, which spares SQLite evaluating
+        // a clause on every row of a large collection
+        let filtered = !matches_whole_collection(node);
+        self.write_table_sql(filtered);
+        if filtered {
+            self.write_node_to_sql(node)?;
+        }
         Ok((self.sql, self.args))
     }
 
-    fn write_table_sql(&mut self) {
-        let sql = match self.table {
-            RequiredTable::Cards => "select c.id from cards c where ",
-            RequiredTable::Notes => "select n.id from notes n where ",
+    fn write_table_sql(&mut self, filtered: bool) {
+        let (sql, joined) = match self.table {
+            RequiredTable::Cards => ("select c.id from cards c", false),
+            RequiredTable::Notes => ("select n.id from notes n", false),
             _ => match self.item_type {
-                ReturnItemType::Cards => "select c.id from cards c, notes n where c.nid=n.id and ",
-                ReturnItemType::Notes => {
-                    "select distinct n.id from cards c, notes n where c.nid=n.id and "
+                ReturnItemType::Cards => {
+                    ("select c.id from cards c, notes n where c.nid=n.id", true)
                 }
+                ReturnItemType::Notes => (
+                    "select distinct n.id from cards c, notes n where c.nid=n.id",
+                    true,
+                ),
             },
         };
         self.sql.push_str(sql);
+        if filtered {
+            self.sql.push_str(if joined { " and " } else { " where " });
+        }
     }
 
     /// As an optimization we can omit the cards or notes tables from
@@ -103,8 +126,14 @@ impl SqlWriter<'_> {
             }
             Node::Group(nodes) => {
                 write!(self.sql, "(").unwrap();
-                for node in nodes {
-                    self.write_node_to_sql(node)?;
+                if let Some(decks) = or_joined_decks(nodes) {
+                    // a single clause is cheaper to evaluate, especially when
+                    // negated, as each deck would otherwise need its own subqueries
+                    self.write_decks(&decks)?;
+                } else {
+                    for node in nodes {
+                        self.write_node_to_sql(node)?;
+                    }
                 }
                 write!(self.sql, ")").unwrap();
             }
@@ -136,11 +165,22 @@ impl SqlWriter<'_> {
                     self.col.get_config_bool(BoolKey::IgnoreAccentsInSearch),
                 )?
             }
-            SearchNode::SingleField { field, text, is_re } => {
-                self.write_field(&norm(field), &self.norm_note(text), *is_re)?
-            }
-            SearchNode::Duplicates { notetype_id, text } => {
-                self.write_dupe(*notetype_id, &self.norm_note(text))?
+            SearchNode::SingleField {
+                field,
+                text,
+                is_re,
+                match_case,
+            } => self.write_field(&norm(field), &self.norm_note(text), *is_re, *match_case)?,
+            SearchNode::Duplicates {
+                notetype_id,
+                text,
+                fold,
+            } => {
+                if *fold {
+                    self.write_folded_dupe(*notetype_id, &self.norm_note(text))?
+                } else {
+                    self.write_dupe(*notetype_id, &self.norm_note(text))?
+                }
             }
             SearchNode::Regex(re) => self.write_regex(&self.norm_note(re), false)?,
             SearchNode::NoCombining(text) => self.write_unqualified(&self.norm_note(text), true)?,
@@ -148,8 +188,16 @@ impl SqlWriter<'_> {
 
             // other
             SearchNode::AddedInDays(days) => self.write_added(*days)?,
+            SearchNode::AddedInDayRange { min, max } => self.write_added_range(*min, *max)?,
             SearchNode::EditedInDays(days) => self.write_edited(*days)?,
+            SearchNode::AddedInHours(hours) => {
+                write!(self.sql, "c.id > {}", hours_ago(*hours).as_millis()).unwrap()
+            }
+            SearchNode::EditedInHours(hours) => {
+                write!(self.sql, "n.mod > {}", hours_ago(*hours)).unwrap()
+            }
             SearchNode::IntroducedInDays(days) => self.write_introduced(*days)?,
+            SearchNode::DueOnWeekdays(weekdays) => self.write_due_weekdays(weekdays)?,
             SearchNode::CardTemplate(template) => match template {
                 TemplateKind::Ordinal(_) => self.write_template(template),
                 TemplateKind::Name(name) => {
@@ -166,12 +214,22 @@ impl SqlWriter<'_> {
             SearchNode::DeckIdWithChildren(did) => self.write_deck_id_with_children(*did)?,
             SearchNode::Notetype(notetype) => self.write_notetype(&norm(notetype)),
             SearchNode::Rated { days, ease } => self.write_rated(">", -i64::from(*days), ease)?,
+            SearchNode::RatedInSession { ease } => self.write_rated_in_session(ease)?,
+            SearchNode::LastRating(button) => self.write_last_rating(*button),
 
             SearchNode::Tag { tag, is_re } => self.write_tag(&norm(tag), *is_re),
             SearchNode::State(state) => self.write_state(state)?,
-            SearchNode::Flag(flag) => {
-                write!(self.sql, "(c.flags & 7) == {}", flag).unwrap();
-            }
+            SearchNode::StaleChecksum => self.write_stale_checksum(),
+            SearchNode::PositionCollision => self.write_position_collision(),
+            SearchNode::FieldCountMismatch => self.write_field_count_mismatch(),
+            SearchNode::AnyDuplicate => self.write_any_dupe()?,
+            SearchNode::Untagged => self.write_untagged(),
+            SearchNode::Leech => self.write_leech(),
+            SearchNode::Flag(flags) => match flags.as_slice() {
+                [flag] => write!(self.sql, "(c.flags & 7) == {}", flag).unwrap(),
+                flags => write!(self.sql, "(c.flags & 7) in ({})", flags.iter().join(",")).unwrap(),
+            },
+            SearchNode::AnyFlag => write!(self.sql, "(c.flags & 7) != 0").unwrap(),
             SearchNode::NoteIds(nids) => {
                 write!(self.sql, "{} in ({})", self.note_id_column(), nids).unwrap();
             }
@@ -180,8 +238,14 @@ impl SqlWriter<'_> {
             }
             SearchNode::Property { operator, kind } => self.write_prop(operator, kind)?,
             SearchNode::CustomData(key) => self.write_custom_data(key)?,
+            SearchNode::DataKey(key) => self.write_data_key(key),
             SearchNode::WholeCollection => write!(self.sql, "true").unwrap(),
             SearchNode::Preset(name) => self.write_deck_preset(name)?,
+            SearchNode::PresetLearningSteps { operator, count } => {
+                self.write_preset_learning_steps(operator, *count)?
+            }
+            SearchNode::MediaRef(fname) => self.write_media_ref(fname),
+            SearchNode::RawText(text) => self.write_raw_text(&self.norm_note(text)),
         };
         Ok(())
     }
@@ -264,14 +328,18 @@ impl SqlWriter<'_> {
             write!(self.sql, "regexp_tags(?{}, n.tags)", self.args.len()).unwrap();
         } else {
             match tag {
-                "none" => {
-                    write!(self.sql, "n.tags = ''").unwrap();
-                }
+                "none" => self.write_untagged(),
                 "*" => {
                     write!(self.sql, "true").unwrap();
                 }
                 s if s.contains(' ') => write!(self.sql, "false").unwrap(),
                 text => {
+                    // children are always included, so a trailing separator
+                    // changes nothing
+                    let text = match text.strip_suffix("::") {
+                        Some(parent) if !parent.is_empty() => parent,
+                        _ => text,
+                    };
                     write!(self.sql, "n.tags regexp ?").unwrap();
                     let re = &to_custom_re(text, r"\S");
                     self.args.push(format!("(?i).* {}(::| ).*", re));
@@ -280,69 +348,155 @@ impl SqlWriter<'_> {
         }
     }
 
+    /// No tags are normally stored as an empty string, but notes from older
+    /// clients may have only separators left.
+    fn write_untagged(&mut self) {
+        write!(self.sql, "trim(n.tags, ' ' || char(12288)) = ''").unwrap();
+    }
+
+    fn write_leech(&mut self) {
+        let tag = self.col.get_config_string(StringKey::LeechTag);
+        self.write_tag(&escape_anki_wildcards_for_search_node(&tag), false);
+    }
+
     fn write_rated(&mut self, op: &str, days: i64, ease: &RatingKind) -> Result<()> {
         let today_cutoff = self.col.timing_today()?.next_day_at;
         let target_cutoff_ms = today_cutoff.adding_secs(86_400 * days).as_millis();
         let day_before_cutoff_ms = today_cutoff.adding_secs(86_400 * (days - 1)).as_millis();
 
         write!(self.sql, "c.id in (select cid from revlog where id").unwrap();
+        self.write_day_comparison(op, day_before_cutoff_ms.0, target_cutoff_ms.0);
+        self.write_rating_kind(ease);
 
-        match op {
-            ">" => write!(self.sql, " >= {}", target_cutoff_ms),
-            ">=" => write!(self.sql, " >= {}", day_before_cutoff_ms),
-            "<" => write!(self.sql, " < {}", day_before_cutoff_ms),
-            "<=" => write!(self.sql, " < {}", target_cutoff_ms),
-            "=" => write!(
-                self.sql,
-                " between {} and {}",
-                day_before_cutoff_ms,
-                target_cutoff_ms.0 - 1
-            ),
-            "!=" => write!(
-                self.sql,
-                " not between {} and {}",
-                day_before_cutoff_ms,
-                target_cutoff_ms.0 - 1
-            ),
-            _ => unreachable!("unexpected op"),
-        }
+        Ok(())
+    }
+
+    /// Answers since [Collection::get_study_session_start], or since the start
+    /// of today if none has been recorded.
+    fn write_rated_in_session(&mut self, ease: &RatingKind) -> Result<()> {
+        let start = match self.col.get_study_session_start() {
+            Some(start) => start,
+            None => self.col.timing_today()?.next_day_at.adding_secs(-86_400),
+        };
+        write!(
+            self.sql,
+            "c.id in (select cid from revlog where id >= {}",
+            start.as_millis().0
+        )
         .unwrap();
+        self.write_rating_kind(ease);
+
+        Ok(())
+    }
 
+    /// Completes a revlog subquery started by the caller.
+    fn write_rating_kind(&mut self, ease: &RatingKind) {
         match ease {
             RatingKind::AnswerButton(u) => write!(self.sql, " and ease = {})", u),
+            RatingKind::AnswerButtonRange(low, high) => {
+                write!(self.sql, " and ease between {} and {})", low, high)
+            }
             RatingKind::AnyAnswerButton => write!(self.sql, " and ease > 0)"),
             RatingKind::ManualReschedule => write!(self.sql, " and ease = 0)"),
+            RatingKind::Lapse => write!(
+                self.sql,
+                " and ease = 1 and type = {})",
+                RevlogReviewKind::Review as u8
+            ),
+        }
+        .unwrap();
+    }
+
+    /// Compares the preceding column to the day running from `day_start` up
+    /// to `day_end`, so that eg '>' is true for any later time.
+    fn write_day_comparison(&mut self, op: &str, day_start: i64, day_end: i64) {
+        match op {
+            ">" => write!(self.sql, " >= {}", day_end),
+            ">=" => write!(self.sql, " >= {}", day_start),
+            "<" => write!(self.sql, " < {}", day_start),
+            "<=" => write!(self.sql, " < {}", day_end),
+            "=" => write!(self.sql, " between {} and {}", day_start, day_end - 1),
+            "!=" => write!(self.sql, " not between {} and {}", day_start, day_end - 1),
+            _ => unreachable!("unexpected op"),
         }
         .unwrap();
+    }
+
+    /// `days` is relative to today, so 0 is notes edited today.
+    fn write_prop_edited(&mut self, op: &str, days: i64) -> Result<()> {
+        let today_cutoff = self.col.timing_today()?.next_day_at;
+        let target_cutoff = today_cutoff.adding_secs(86_400 * days);
+        let day_before_cutoff = today_cutoff.adding_secs(86_400 * (days - 1));
+
+        write!(self.sql, "n.mod").unwrap();
+        self.write_day_comparison(op, day_before_cutoff.0, target_cutoff.0);
 
         Ok(())
     }
 
+    /// Manual reschedulings are not answers, so they're skipped when looking
+    /// for the latest rating.
+    fn write_last_rating(&mut self, button: u8) {
+        write!(
+            self.sql,
+            "(select ease from revlog where cid = c.id and ease > 0 order by id desc limit 1) = {button}"
+        )
+        .unwrap();
+    }
+
     fn write_prop(&mut self, op: &str, kind: &PropertyKind) -> Result<()> {
         let timing = self.col.timing_today()?;
 
         match kind {
             PropertyKind::Due(days) => {
                 let day = days + (timing.days_elapsed as i32);
+                // Learning cards are due at a timestamp, which is counted in
+                // days from the last rollover. SQL's integer division rounds
+                // towards zero, so times before the rollover are adjusted to
+                // round down instead; otherwise a card due early tomorrow
+                // and one due late yesterday would both count as today.
+                let start = timing.next_day_at.adding_secs(-86_400);
                 write!(
                     self.sql,
-                    // SQL does integer division if both parameters are integers
                     "(\
-                    (c.queue in ({rev},{daylrn}) and 
-                        (case when c.odue != 0 then c.odue else c.due end) {op} {day}) or \
-                    (c.queue in ({lrn},{previewrepeat}) and 
-                        (((case when c.odue != 0 then c.odue else c.due end) - {cutoff}) / 86400) {op} {days})\
+                    (c.queue in ({rev},{daylrn}) and {due} {op} {day}) or \
+                    (c.queue in ({lrn},{previewrepeat}) and \
+                    (case when {due} >= {start} then ({due} - {start}) / 86400 \
+                    else ({due} - {start} - 86399) / 86400 end) {op} {days})\
                     )",
+                    due = "(case when c.odue != 0 then c.odue else c.due end)",
                     rev = CardQueue::Review as u8,
                     daylrn = CardQueue::DayLearn as u8,
-                    op = op,
-                    day = day,
                     lrn = CardQueue::Learn as i8,
                     previewrepeat = CardQueue::PreviewRepeat as i8,
-                    cutoff = timing.next_day_at,
-                    days = days
-                ).unwrap()
+                )
+                .unwrap()
+            }
+            PropertyKind::DueOnDate(date) => {
+                // the current scheduling day began a day before the next
+                // rollover, which may be after midnight
+                let today = timing
+                    .next_day_at
+                    .adding_secs(-86_400)
+                    .local_datetime()?
+                    .date_naive();
+                let days = date.signed_duration_since(today).num_days() as i32;
+                self.write_prop(op, &PropertyKind::Due(days))?
             }
+            PropertyKind::DueAfterLastReview(days) => write!(
+                self.sql,
+                // the latest answer's day is counted back from the next
+                // rollover; cards never answered yield null, so don't match
+                "(c.queue in ({rev},{daylrn}) and \
+                (case when c.odue != 0 then c.odue else c.due end) - \
+                ({today} - (({cutoff} - 1 - (select max(id) from revlog \
+                where cid = c.id and ease > 0) / 1000) / 86400)) {op} {days})",
+                rev = CardQueue::Review as u8,
+                daylrn = CardQueue::DayLearn as u8,
+                today = timing.days_elapsed,
+                cutoff = timing.next_day_at,
+            )
+            .unwrap(),
             PropertyKind::Position(pos) => write!(
                 self.sql,
                 "(c.type = {t} and (case when c.odue != 0 then c.odue else c.due end) {op} {pos})",
@@ -351,13 +505,44 @@ impl SqlWriter<'_> {
                 pos = pos
             )
             .unwrap(),
-            PropertyKind::Interval(ivl) => write!(self.sql, "ivl {} {}", op, ivl).unwrap(),
+            PropertyKind::FieldCount(count) => {
+                write!(self.sql, "{NOTE_FIELD_COUNT} {op} {count}").unwrap()
+            }
+            PropertyKind::TagCount(count) => {
+                write!(self.sql, "tag_count(n.tags) {op} {count}").unwrap()
+            }
+            PropertyKind::CardCount(count) => write!(
+                self.sql,
+                "(select count() from cards where nid = n.id) {op} {count}"
+            )
+            .unwrap(),
+            PropertyKind::Interval { days, .. } => write!(self.sql, "ivl {} {}", op, days).unwrap(),
             PropertyKind::Reps(reps) => write!(self.sql, "reps {} {}", op, reps).unwrap(),
             PropertyKind::Lapses(days) => write!(self.sql, "lapses {} {}", op, days).unwrap(),
-            PropertyKind::Ease(ease) => {
-                write!(self.sql, "factor {} {}", op, (ease * 1000.0) as u32).unwrap()
+            PropertyKind::Ease { value, percent } => {
+                // the factor is stored per mille
+                let factor = if *percent {
+                    value * 10.0
+                } else {
+                    value * 1000.0
+                };
+                write!(self.sql, "factor {} {}", op, factor as u32).unwrap()
             }
+            PropertyKind::EaseDeviation(dev) => self.write_ease_deviation(op, *dev)?,
             PropertyKind::Rated(days, ease) => self.write_rated(op, i64::from(*days), ease)?,
+            PropertyKind::Edited(days) => self.write_prop_edited(op, i64::from(*days))?,
+            PropertyKind::RatedCount { days, count } => {
+                write!(
+                    self.sql,
+                    "(select count() from revlog where cid = c.id and ease > 0"
+                )
+                .unwrap();
+                if let Some(days) = days {
+                    let cutoff = timing.next_day_at.adding_secs(-86_400 * i64::from(*days));
+                    write!(self.sql, " and id >= {}", cutoff.as_millis()).unwrap();
+                }
+                write!(self.sql, ") {op} {count}").unwrap();
+            }
             PropertyKind::CustomDataNumber { key, value } => {
                 write!(
                     self.sql,
@@ -395,12 +580,85 @@ impl SqlWriter<'_> {
         Ok(())
     }
 
+    /// Compares (ease / home deck average ease) - 1 with the provided value,
+    /// so -0.3 is 30% below average. Only review cards with an SM2 ease and
+    /// no FSRS memory state are considered, both for the averages and the
+    /// matches. The averages are computed with an extra pass over the cards
+    /// table when the search is built, and inlined into the SQL.
+    fn write_ease_deviation(&mut self, op: &str, dev: f32) -> Result<()> {
+        let averages = self.col.storage.average_ease_by_home_deck()?;
+        if averages.is_empty() {
+            write!(self.sql, "false").unwrap();
+            return Ok(());
+        }
+        let home_deck = "(case when c.odid != 0 then c.odid else c.did end)";
+        // averages with no fractional part are written without a decimal
+        // point, so the factor is made real to avoid integer division
+        let cases: String = averages
+            .iter()
+            .map(|(did, avg)| format!(" when {did} then {avg}"))
+            .collect();
+        write!(
+            self.sql,
+            "(c.type in ({rev}, {relrn}) and c.factor > 0 and \
+            extract_fsrs_variable(c.data, 's') is null and \
+            (c.factor * 1.0 / (case {home_deck}{cases} end) - 1) {op} {dev})",
+            rev = CardType::Review as i8,
+            relrn = CardType::Relearn as i8,
+        )
+        .unwrap();
+        Ok(())
+    }
+
     fn write_custom_data(&mut self, key: &str) -> Result<()> {
         write!(self.sql, "extract_custom_data(c.data, '{key}') is not null").unwrap();
 
         Ok(())
     }
 
+    /// json_type() is used instead of json_extract() so that keys holding a
+    /// JSON null still match. Cards with empty or malformed data are skipped
+    /// instead of aborting the search.
+    fn write_data_key(&mut self, key: &str) {
+        write!(
+            self.sql,
+            "(case when json_valid(c.data) then json_type(c.data, '$.{key}') end) is not null"
+        )
+        .unwrap();
+    }
+
+    /// Recomputing the checksum means stripping and hashing the first field
+    /// of every note, so unlike dupe: this can't use the csum index and is
+    /// slow on large collections.
+    fn write_stale_checksum(&mut self) {
+        write!(self.sql, "n.csum != first_field_checksum(n.flds)").unwrap();
+    }
+
+    /// Intended for finding notes affected by a notetype change that didn't
+    /// reach them, before Check Database pads or merges their fields. Only
+    /// the number of fields can be compared, as the stored fields carry no
+    /// names. Notes of a missing notetype always match.
+    fn write_field_count_mismatch(&mut self) {
+        write!(
+            self.sql,
+            "{NOTE_FIELD_COUNT} != (select count() from fields where ntid = n.mid)"
+        )
+        .unwrap();
+    }
+
+    /// Only cards in the new queue have a position, so other cards never
+    /// match. Cards of the same note are expected to share a position, and
+    /// don't count as a collision.
+    fn write_position_collision(&mut self) {
+        write!(
+            self.sql,
+            "(c.queue = {new} and exists (select 1 from cards c2 where c2.queue = {new} \
+            and c2.did = c.did and c2.due = c.due and c2.nid != c.nid))",
+            new = CardQueue::New as i8,
+        )
+        .unwrap();
+    }
+
     fn write_state(&mut self, state: &StateKind) -> Result<()> {
         let timing = self.col.timing_today()?;
         match state {
@@ -424,6 +682,11 @@ impl SqlWriter<'_> {
                 CardQueue::UserBuried as i8
             ),
             StateKind::Suspended => write!(self.sql, "c.queue = {}", CardQueue::Suspended as i8),
+            StateKind::NoteSuspended => write!(
+                self.sql,
+                "not exists (select 1 from cards c2 where c2.nid = c.nid and c2.queue != {})",
+                CardQueue::Suspended as i8
+            ),
             StateKind::Due => write!(
                 self.sql,
                 "(\
@@ -437,6 +700,27 @@ impl SqlWriter<'_> {
                 previewrepeat = CardQueue::PreviewRepeat as i8,
                 learncutoff = TimestampSecs::now().0 + (self.col.learn_ahead_secs() as i64),
             ),
+            // the queues exclude suspended and buried cards
+            StateKind::Scheduled => write!(
+                self.sql,
+                "(\
+                (c.queue in ({rev},{daylrn}) and c.due > {today}) or \
+                (c.queue in ({lrn},{previewrepeat}) and c.due > {learncutoff})\
+                )",
+                rev = CardQueue::Review as i8,
+                daylrn = CardQueue::DayLearn as i8,
+                today = timing.days_elapsed,
+                lrn = CardQueue::Learn as i8,
+                previewrepeat = CardQueue::PreviewRepeat as i8,
+                learncutoff = TimestampSecs::now().0 + (self.col.learn_ahead_secs() as i64),
+            ),
+            // the review queue excludes new, suspended and buried cards
+            StateKind::Overdue => write!(
+                self.sql,
+                "(c.queue = {rev} and {today} - (case when c.odue != 0 then c.odue else c.due end) > c.ivl)",
+                rev = CardQueue::Review as i8,
+                today = timing.days_elapsed,
+            ),
             StateKind::UserBuried => write!(self.sql, "c.queue = {}", CardQueue::UserBuried as i8),
             StateKind::SchedBuried => {
                 write!(self.sql, "c.queue = {}", CardQueue::SchedBuried as i8)
@@ -450,37 +734,84 @@ impl SqlWriter<'_> {
         match deck {
             "*" => write!(self.sql, "true").unwrap(),
             "filtered" => write!(self.sql, "c.odid != 0").unwrap(),
-            deck => {
-                // rewrite "current" to the current deck name
-                let native_deck = if deck == "current" {
-                    let current_did = self.col.get_current_deck_id();
-                    regex::escape(
-                        self.col
-                            .storage
-                            .get_deck(current_did)?
-                            .map(|d| d.name)
-                            .unwrap_or_else(|| NativeDeckName::from_native_str("Default"))
-                            .as_native_str(),
-                    )
-                } else {
-                    NativeDeckName::from_human_name(to_re(deck))
-                        .as_native_str()
-                        .to_string()
-                };
+            deck => match self.native_deck_regex(deck)? {
+                Some(native_deck) => self.write_deck_regex(&native_deck),
+                None => write!(self.sql, "false").unwrap(),
+            },
+        };
+        Ok(())
+    }
 
-                // convert to a regex that includes child decks
-                self.args.push(format!("(?i)^{}($|\x1f)", native_deck));
-                let arg_idx = self.args.len();
-                self.sql.push_str(&format!(concat!(
-                    "(c.did in (select id from decks where name regexp ?{n})",
-                    " or (c.odid != 0 and c.odid in (select id from decks where name regexp ?{n})))"),
-                    n=arg_idx
-                ));
+    /// Match any of the provided decks, which must not include the special
+    /// "*" and "filtered" names.
+    fn write_decks(&mut self, decks: &[&str]) -> Result<()> {
+        let mut native_decks = vec![];
+        for deck in decks {
+            if let Some(native_deck) = self.native_deck_regex(&normalize_to_nfc(deck))? {
+                native_decks.push(native_deck);
             }
-        };
+        }
+        if native_decks.is_empty() {
+            write!(self.sql, "false").unwrap();
+        } else {
+            self.write_deck_regex(&format!("(?:{})", native_decks.join("|")));
+        }
         Ok(())
     }
 
+    /// None if the deck is "current", and no deck is selected or the selected
+    /// one no longer exists.
+    fn native_deck_regex(&mut self, deck: &str) -> Result<Option<String>> {
+        // rewrite "current" to the current deck name
+        Ok(if deck == "current" {
+            let current_did: Option<DeckId> =
+                self.col.get_config_optional(ConfigKey::CurrentDeckId);
+            match current_did {
+                Some(did) => self
+                    .col
+                    .get_deck(did)?
+                    .map(|deck| regex::escape(deck.name.as_native_str())),
+                None => None,
+            }
+        } else {
+            Some(
+                NativeDeckName::from_human_name(to_re(deck))
+                    .as_native_str()
+                    .to_string(),
+            )
+        })
+    }
+
+    /// Cards in a filtered deck are matched by their home deck, unless
+    /// the filtered deck itself is named. As filtered decks can't have
+    /// children, a card from another deck that was moved into a filtered
+    /// child of the searched deck doesn't match.
+    fn write_deck_regex(&mut self, native_deck: &str) {
+        // convert to a regex that includes child decks
+        self.args.push(format!("(?i)^{}($|\x1f)", native_deck));
+        let arg_idx = self.args.len();
+        if self.col.get_config_bool(BoolKey::SearchDecksByCurrentDeck) {
+            self.sql.push_str(&format!(
+                concat!(
+                    "(c.did in (select id from decks where name regexp ?{n})",
+                    " or (c.odid != 0 and c.odid in (select id from decks where name regexp ?{n})))"
+                ),
+                n = arg_idx
+            ));
+            return;
+        }
+        self.args.push(format!("(?i)^{}$", native_deck));
+        self.sql.push_str(&format!(
+            concat!(
+                "((c.odid = 0 and c.did in (select id from decks where name regexp ?{n}))",
+                " or (c.odid != 0 and (c.odid in (select id from decks where name regexp ?{n})",
+                " or c.did in (select id from decks where name regexp ?{exact}))))"
+            ),
+            n = arg_idx,
+            exact = arg_idx + 1,
+        ));
+    }
+
     fn write_deck_id_with_children(&mut self, deck_id: DeckId) -> Result<()> {
         if let Some(parent) = self.col.get_deck(deck_id)? {
             let ids = self.col.storage.deck_id_with_children(&parent)?;
@@ -529,16 +860,28 @@ impl SqlWriter<'_> {
         }
     }
 
-    fn write_field(&mut self, field_name: &str, val: &str, is_re: bool) -> Result<()> {
+    fn write_field(
+        &mut self,
+        field_name: &str,
+        val: &str,
+        is_re: bool,
+        match_case: bool,
+    ) -> Result<()> {
         if matches!(field_name, "*" | "_*" | "*_") {
             if is_re {
                 self.write_all_fields_regexp(val);
+            } else if match_case {
+                self.write_all_fields_regexp(&format!("(?-i)^{}$", to_re(val)));
             } else {
                 self.write_all_fields(val);
             }
             Ok(())
         } else if is_re {
             self.write_single_field_regexp(field_name, val)
+        } else if match_case && !val.is_empty() {
+            // LIKE ignores case, so the wildcards are converted to a regex
+            // of the whole field instead
+            self.write_single_field_regexp(field_name, &format!("(?-i)^{}$", to_re(val)))
         } else {
             self.write_single_field(field_name, val)
         }
@@ -578,6 +921,9 @@ impl SqlWriter<'_> {
     }
 
     fn write_single_field(&mut self, field_name: &str, val: &str) -> Result<()> {
+        if val.is_empty() {
+            return self.write_blank_single_field(field_name);
+        }
         let field_indicies_by_notetype =
             self.num_fields_and_fields_indices_by_notetype(field_name)?;
         if field_indicies_by_notetype.is_empty() {
@@ -622,6 +968,27 @@ impl SqlWriter<'_> {
         Ok(())
     }
 
+    /// An empty search text matches fields that look empty, ignoring HTML
+    /// and whitespace. Notetypes without the field never match.
+    fn write_blank_single_field(&mut self, field_name: &str) -> Result<()> {
+        let field_indicies_by_notetype = self.fields_indices_by_notetype(field_name)?;
+        if field_indicies_by_notetype.is_empty() {
+            write!(self.sql, "false").unwrap();
+            return Ok(());
+        }
+
+        let all_notetype_clauses = field_indicies_by_notetype
+            .iter()
+            .map(|(mid, field_indices)| {
+                let field_index_list = field_indices.iter().join(", ");
+                format!("(n.mid = {mid} and blank_fields(n.flds, {field_index_list}))")
+            })
+            .join(" or ");
+        write!(self.sql, "({all_notetype_clauses})").unwrap();
+
+        Ok(())
+    }
+
     fn num_fields_and_fields_indices_by_notetype(
         &mut self,
         field_name: &str,
@@ -760,6 +1127,54 @@ impl SqlWriter<'_> {
         Ok(())
     }
 
+    /// Like [Self::write_dupe], but for every first field in the collection.
+    /// Notes with an empty first field are never considered duplicates.
+    fn write_any_dupe(&mut self) -> Result<()> {
+        let mut groups: HashMap<(NotetypeId, String), Vec<NoteId>> = HashMap::new();
+        for (ntid, nid, field) in self.col.storage.notes_with_shared_checksum()? {
+            let stripped = strip_html_preserving_media_filenames(&field);
+            if !stripped.trim().is_empty() {
+                groups
+                    .entry((ntid, stripped.into_owned()))
+                    .or_default()
+                    .push(nid);
+            }
+        }
+        let mut nids: Vec<_> = groups
+            .into_values()
+            .filter(|nids| nids.len() > 1)
+            .flatten()
+            .collect();
+        nids.sort_unstable();
+
+        self.sql += "n.id in ";
+        ids_to_string(&mut self.sql, &nids);
+
+        Ok(())
+    }
+
+    /// As the checksum is of the unfolded text, this has to compare against
+    /// the first field of every note of the notetype. Empty fields never
+    /// match.
+    fn write_folded_dupe(&mut self, ntid: NotetypeId, text: &str) -> Result<()> {
+        let key = dupe_fold_key(text);
+        let nids: Vec<_> = if key.trim().is_empty() {
+            vec![]
+        } else {
+            self.col
+                .storage
+                .note_first_fields_of_notetype(ntid)?
+                .into_iter()
+                .filter_map(|(nid, field)| (dupe_fold_key(&field) == key).then_some(nid))
+                .collect()
+        };
+
+        self.sql += "n.id in ";
+        ids_to_string(&mut self.sql, &nids);
+
+        Ok(())
+    }
+
     fn previous_day_cutoff(&mut self, days_back: u32) -> Result<TimestampSecs> {
         let timing = self.col.timing_today()?;
         Ok(timing.next_day_at.adding_secs(-86_400 * days_back as i64))
@@ -771,6 +1186,15 @@ impl SqlWriter<'_> {
         Ok(())
     }
 
+    /// Like `added:{max}`, but leaving out cards that `added:{min - 1}`
+    /// would match.
+    fn write_added_range(&mut self, min: u32, max: u32) -> Result<()> {
+        let start = self.previous_day_cutoff(max)?.as_millis();
+        let end = self.previous_day_cutoff(min - 1)?.as_millis();
+        write!(self.sql, "c.id between {} and {}", start.0 + 1, end).unwrap();
+        Ok(())
+    }
+
     fn write_edited(&mut self, days: u32) -> Result<()> {
         let cutoff = self.previous_day_cutoff(days)?;
         write!(self.sql, "n.mod > {}", cutoff).unwrap();
@@ -794,6 +1218,33 @@ impl SqlWriter<'_> {
         Ok(())
     }
 
+    /// Review cards store their due date as a day number, where the current
+    /// day is `days_elapsed`. Days begin at the rollover hour, so the weekday
+    /// of a day number is derived from the local date on which the current
+    /// scheduler day started.
+    fn write_due_weekdays(&mut self, weekdays: &[Weekday]) -> Result<()> {
+        let timing = self.col.timing_today()?;
+        let today_weekday = timing
+            .next_day_at
+            .adding_secs(-86_400)
+            .local_datetime()?
+            .weekday();
+        let offset = (today_weekday.num_days_from_monday() as i64 - timing.days_elapsed as i64)
+            .rem_euclid(7);
+        let weekdays = weekdays
+            .iter()
+            .map(|day| day.num_days_from_monday())
+            .unique()
+            .join(",");
+        write!(
+            self.sql,
+            "(c.queue = {rev} and ((case when c.odue != 0 then c.odue else c.due end) + {offset}) % 7 in ({weekdays}))",
+            rev = CardQueue::Review as i8,
+        )
+        .unwrap();
+        Ok(())
+    }
+
     fn write_regex(&mut self, word: &str, no_combining: bool) -> Result<()> {
         let flds_expr = if no_combining {
             "coalesce(without_combining(n.flds), n.flds)"
@@ -837,8 +1288,56 @@ impl SqlWriter<'_> {
             self.col.get_config_bool(BoolKey::IgnoreAccentsInSearch),
         )
     }
+    fn write_media_ref(&mut self, fname: &str) {
+        let fname = media_filename_for_comparison(&to_text(fname));
+        self.args.push(fname);
+        write!(self.sql, "refers_to_media(n.flds, ?{})", self.args.len()).unwrap();
+    }
+
+    fn write_raw_text(&mut self, text: &str) {
+        self.args.push(format!("%{}%", to_sql(text)));
+        write!(self.sql, "n.flds like ?{} escape '\\'", self.args.len()).unwrap();
+    }
+
     fn write_deck_preset(&mut self, name: &str) -> Result<()> {
-        let dcid = self.col.storage.get_deck_config_id_by_name(name)?;
+        let Some(dcid) = self
+            .col
+            .storage
+            .get_deck_config_id_by_name(&to_text(name))?
+        else {
+            // filtered decks have no preset, so must not match a missing one
+            write!(self.sql, "false").unwrap();
+            return Ok(());
+        };
+        self.write_decks_using_presets(&[dcid])
+    }
+
+    fn write_preset_learning_steps(&mut self, op: &str, count: u32) -> Result<()> {
+        let count = count as usize;
+        let dcids: Vec<_> = self
+            .col
+            .storage
+            .all_deck_config()?
+            .into_iter()
+            .filter(|config| {
+                let steps = config.inner.learn_steps.len();
+                match op {
+                    "<" => steps < count,
+                    ">" => steps > count,
+                    "<=" => steps <= count,
+                    ">=" => steps >= count,
+                    "=" => steps == count,
+                    "!=" => steps != count,
+                    _ => unreachable!("unexpected op"),
+                }
+            })
+            .map(|config| config.id)
+            .collect();
+        self.write_decks_using_presets(&dcids)
+    }
+
+    /// Cards in normal decks using any of the presets, or filtered from them.
+    fn write_decks_using_presets(&mut self, dcids: &[DeckConfigId]) -> Result<()> {
         let mut str_ids = String::new();
         let deck_ids = self
             .col
@@ -846,7 +1345,7 @@ impl SqlWriter<'_> {
             .get_all_decks()?
             .into_iter()
             .filter_map(|d| {
-                if d.config_id() == dcid {
+                if d.config_id().is_some_and(|dcid| dcids.contains(&dcid)) {
                     Some(d.id)
                 } else {
                     None
@@ -858,6 +1357,47 @@ impl SqlWriter<'_> {
     }
 }
 
+/// True for the empty search, whether or not the parser wrapped it in a group.
+fn matches_whole_collection(node: &Node) -> bool {
+    match node {
+        Node::Search(SearchNode::WholeCollection) => true,
+        Node::Group(nodes) => matches!(&nodes[..], [node] if matches_whole_collection(node)),
+        _ => false,
+    }
+}
+
+fn hours_ago(hours: u32) -> TimestampSecs {
+    TimestampSecs::now().adding_secs(-3_600 * i64::from(hours))
+}
+
+/// The stripped text with combining characters removed, compared using the
+/// same case folding as the unicase collation used for sorting.
+fn dupe_fold_key(text: &str) -> UniCase<String> {
+    let stripped = strip_html_preserving_media_filenames(text);
+    UniCase::new(without_combining(&stripped).into_owned())
+}
+
+/// If the group consists only of two or more ordinary deck searches joined by
+/// "or", return their names.
+fn or_joined_decks(nodes: &[Node]) -> Option<Vec<&str>> {
+    if nodes.len() < 3 || nodes.len() % 2 == 0 {
+        return None;
+    }
+    let mut decks = vec![];
+    for (idx, node) in nodes.iter().enumerate() {
+        match node {
+            Node::Search(SearchNode::Deck(deck))
+                if idx % 2 == 0 && !matches!(deck.as_str(), "*" | "filtered") =>
+            {
+                decks.push(deck.as_str())
+            }
+            Node::Or if idx % 2 == 1 => (),
+            _ => return None,
+        }
+    }
+    Some(decks)
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum RequiredTable {
     Notes,
@@ -963,29 +1503,52 @@ impl Node {
 impl SearchNode {
     fn required_table(&self) -> RequiredTable {
         match self {
-            SearchNode::AddedInDays(_) => RequiredTable::Cards,
+            SearchNode::AddedInDays(_) | SearchNode::AddedInDayRange { .. } => RequiredTable::Cards,
+            SearchNode::AddedInHours(_) => RequiredTable::Cards,
             SearchNode::IntroducedInDays(_) => RequiredTable::Cards,
+            SearchNode::DueOnWeekdays(_) => RequiredTable::Cards,
             SearchNode::Deck(_) => RequiredTable::Cards,
             SearchNode::DeckIdsWithoutChildren(_) => RequiredTable::Cards,
             SearchNode::DeckIdWithChildren(_) => RequiredTable::Cards,
-            SearchNode::Rated { .. } => RequiredTable::Cards,
+            SearchNode::Rated { .. } | SearchNode::RatedInSession { .. } => RequiredTable::Cards,
+            SearchNode::LastRating(_) => RequiredTable::Cards,
             SearchNode::State(_) => RequiredTable::Cards,
+            SearchNode::PositionCollision => RequiredTable::Cards,
             SearchNode::Flag(_) => RequiredTable::Cards,
+            SearchNode::AnyFlag => RequiredTable::Cards,
             SearchNode::CardIds(_) => RequiredTable::Cards,
+            SearchNode::Property {
+                kind:
+                    PropertyKind::FieldCount(_)
+                    | PropertyKind::CardCount(_)
+                    | PropertyKind::TagCount(_)
+                    | PropertyKind::Edited(_),
+                ..
+            } => RequiredTable::Notes,
             SearchNode::Property { .. } => RequiredTable::Cards,
             SearchNode::CustomData { .. } => RequiredTable::Cards,
+            SearchNode::DataKey(_) => RequiredTable::Cards,
             SearchNode::Preset(_) => RequiredTable::Cards,
+            SearchNode::PresetLearningSteps { .. } => RequiredTable::Cards,
+            SearchNode::MediaRef(_) => RequiredTable::Notes,
+            SearchNode::RawText(_) => RequiredTable::Notes,
 
             SearchNode::UnqualifiedText(_) => RequiredTable::Notes,
             SearchNode::SingleField { .. } => RequiredTable::Notes,
             SearchNode::Tag { .. } => RequiredTable::Notes,
             SearchNode::Duplicates { .. } => RequiredTable::Notes,
+            SearchNode::StaleChecksum => RequiredTable::Notes,
+            SearchNode::FieldCountMismatch => RequiredTable::Notes,
+            SearchNode::AnyDuplicate => RequiredTable::Notes,
+            SearchNode::Untagged => RequiredTable::Notes,
+            SearchNode::Leech => RequiredTable::Notes,
             SearchNode::Regex(_) => RequiredTable::Notes,
             SearchNode::NoCombining(_) => RequiredTable::Notes,
             SearchNode::WordBoundary(_) => RequiredTable::Notes,
             SearchNode::NotetypeId(_) => RequiredTable::Notes,
             SearchNode::Notetype(_) => RequiredTable::Notes,
             SearchNode::EditedInDays(_) => RequiredTable::Notes,
+            SearchNode::EditedInHours(_) => RequiredTable::Notes,
 
             SearchNode::NoteIds(_) => RequiredTable::CardsOrNotes,
             SearchNode::WholeCollection => RequiredTable::CardsOrNotes,
@@ -1002,8 +1565,15 @@ mod test {
 
     use super::super::parser::parse;
     use super::*;
+    use crate::card::FsrsMemoryState;
     use crate::collection::Collection;
     use crate::collection::CollectionBuilder;
+    use crate::config::ConfigKey;
+    use crate::revlog::RevlogEntry;
+    use crate::revlog::RevlogId;
+    use crate::search::SortMode;
+    use crate::tests::DeckAdder;
+    use crate::tests::NoteAdder;
 
     // shortcut
     fn s(req: &mut Collection, search: &str) -> (String, Vec<String>) {
@@ -1014,6 +1584,28 @@ mod test {
         (writer.sql, writer.args)
     }
 
+    /// Adds a basic note, and writes its card back after passing it to
+    /// `update`.
+    fn add_card_with(col: &mut Collection, update: impl FnOnce(&mut Card)) -> Result<CardId> {
+        let note = NoteAdder::basic(col).add(col);
+        let mut card = col.storage.all_cards_of_note(note.id)?.pop().unwrap();
+        update(&mut card);
+        col.storage.update_card(&card)?;
+        Ok(card.id)
+    }
+
+    fn sorted_cids(col: &mut Collection, search: &str) -> Result<Vec<CardId>> {
+        let mut cids = col.search_cards(search, SortMode::NoOrder)?;
+        cids.sort();
+        Ok(cids)
+    }
+
+    fn sorted_nids(col: &mut Collection, search: &str) -> Result<Vec<NoteId>> {
+        let mut nids = col.search_notes_unordered(search)?;
+        nids.sort();
+        Ok(nids)
+    }
+
     #[test]
     fn sql() {
         // re-use the mediacheck .anki2 file for now
@@ -1089,6 +1681,14 @@ mod test {
             format!("(c.id > {})", (timing.next_day_at.0 - (86_400 * 3)) * 1_000)
         );
         assert_eq!(s(ctx, "added:0").0, s(ctx, "added:1").0,);
+        assert_eq!(
+            s(ctx, "added:3-7").0,
+            format!(
+                "(c.id between {} and {})",
+                (timing.next_day_at.0 - (86_400 * 7)) * 1_000 + 1,
+                (timing.next_day_at.0 - (86_400 * 2)) * 1_000
+            )
+        );
 
         // introduced
         assert_eq!(
@@ -1107,30 +1707,59 @@ mod test {
         assert_eq!(
             s(ctx, "deck:default"),
             (
-                "((c.did in (select id from decks where name regexp ?1) or (c.odid != 0 and \
-                c.odid in (select id from decks where name regexp ?1))))"
+                "(((c.odid = 0 and c.did in (select id from decks where name regexp ?1)) or \
+                (c.odid != 0 and (c.odid in (select id from decks where name regexp ?1) or \
+                c.did in (select id from decks where name regexp ?2)))))"
                     .into(),
-                vec!["(?i)^default($|\u{1f})".into()]
+                vec!["(?i)^default($|\u{1f})".into(), "(?i)^default$".into()]
             )
         );
         assert_eq!(
-            s(ctx, "deck:current").1,
-            vec!["(?i)^Default($|\u{1f})".to_string()]
+            s(ctx, "deck:current").1[0],
+            "(?i)^Default($|\u{1f})".to_string()
         );
-        assert_eq!(s(ctx, "deck:d*").1, vec!["(?i)^d.*($|\u{1f})".to_string()]);
+        assert_eq!(s(ctx, "deck:d*").1[0], "(?i)^d.*($|\u{1f})".to_string());
         assert_eq!(s(ctx, "deck:filtered"), ("(c.odid != 0)".into(), vec![],));
-
-        // card
+        // decks joined by "or" share a single clause
         assert_eq!(
-            s(ctx, r#""card:card 1""#),
+            s(ctx, "-(deck:a or deck:b*)"),
             (
-                "((n.mid,c.ord) in (select ntid,ord from templates where name = ?))".into(),
-                vec!["card 1".into()]
+                "(not (((c.odid = 0 and c.did in (select id from decks where name regexp ?1)) or \
+                (c.odid != 0 and (c.odid in (select id from decks where name regexp ?1) or \
+                c.did in (select id from decks where name regexp ?2))))))"
+                    .into(),
+                vec!["(?i)^(?:a|b.*)($|\u{1f})".into(), "(?i)^(?:a|b.*)$".into()]
             )
         );
-
-        // IDs
-        assert_eq!(s(ctx, "mid:3"), ("(n.mid = 3)".into(), vec![]));
+        // but not when mixed with other searches
+        assert!(s(ctx, "-(deck:a or deck:filtered)")
+            .0
+            .ends_with(" or c.odid != 0))"));
+        assert_eq!(s(ctx, "-(deck:a or tag:b)").1.len(), 3);
+        // matching the current deck of filtered cards instead
+        ctx.set_config_bool(BoolKey::SearchDecksByCurrentDeck, true, false)?;
+        assert_eq!(
+            s(ctx, "deck:default"),
+            (
+                "((c.did in (select id from decks where name regexp ?1) or (c.odid != 0 and \
+                c.odid in (select id from decks where name regexp ?1))))"
+                    .into(),
+                vec!["(?i)^default($|\u{1f})".into()]
+            )
+        );
+        ctx.set_config_bool(BoolKey::SearchDecksByCurrentDeck, false, false)?;
+
+        // card
+        assert_eq!(
+            s(ctx, r#""card:card 1""#),
+            (
+                "((n.mid,c.ord) in (select ntid,ord from templates where name = ?))".into(),
+                vec!["card 1".into()]
+            )
+        );
+
+        // IDs
+        assert_eq!(s(ctx, "mid:3"), ("(n.mid = 3)".into(), vec![]));
         assert_eq!(s(ctx, "nid:3"), ("(n.id in (3))".into(), vec![]));
         assert_eq!(s(ctx, "nid:3,4"), ("(n.id in (3,4))".into(), vec![]));
         assert_eq!(s(ctx, "cid:3,4"), ("(c.id in (3,4))".into(), vec![]));
@@ -1138,6 +1767,11 @@ mod test {
         // flags
         assert_eq!(s(ctx, "flag:2"), ("((c.flags & 7) == 2)".into(), vec![]));
         assert_eq!(s(ctx, "flag:0"), ("((c.flags & 7) == 0)".into(), vec![]));
+        assert_eq!(s(ctx, "flag:any"), ("((c.flags & 7) != 0)".into(), vec![]));
+        assert_eq!(
+            s(ctx, "flag:2,1"),
+            ("((c.flags & 7) in (1,2))".into(), vec![])
+        );
 
         // dupes
         assert_eq!(s(ctx, "dupe:123,test"), ("(n.id in ())".into(), vec![]));
@@ -1157,6 +1791,13 @@ mod test {
                 vec!["(?i).* foo::bar(::| ).*".into()]
             )
         );
+        assert_eq!(
+            s(ctx, r"tag:foo::"),
+            (
+                "(n.tags regexp ?)".into(),
+                vec!["(?i).* foo(::| ).*".into()]
+            )
+        );
 
         assert_eq!(
             s(ctx, r"tag:o*n\*et%w%oth_re\_e"),
@@ -1165,7 +1806,11 @@ mod test {
                 vec![r"(?i).* o\S*n\*et%w%oth\Sre_e(::| ).*".into()]
             )
         );
-        assert_eq!(s(ctx, "tag:none"), ("(n.tags = '')".into(), vec![]));
+        assert_eq!(
+            s(ctx, "tag:none"),
+            ("(trim(n.tags, ' ' || char(12288)) = '')".into(), vec![])
+        );
+        assert_eq!(s(ctx, "is:untagged"), s(ctx, "tag:none"));
         assert_eq!(s(ctx, "tag:*"), ("(true)".into(), vec![]));
         assert_eq!(
             s(ctx, "tag:re:.ne|tw."),
@@ -1180,10 +1825,42 @@ mod test {
             s(ctx, "is:suspended").0,
             format!("(c.queue = {})", CardQueue::Suspended as i8)
         );
+        assert_eq!(
+            s(ctx, "is:note-suspended").0,
+            format!(
+                "(not exists (select 1 from cards c2 where c2.nid = c.nid and c2.queue != {}))",
+                CardQueue::Suspended as i8
+            )
+        );
         assert_eq!(
             s(ctx, "is:new").0,
             format!("(c.type = {})", CardType::New as i8)
         );
+        assert_eq!(
+            s(ctx, "is:buried").0,
+            format!(
+                "(c.queue in ({},{}))",
+                CardQueue::SchedBuried as i8,
+                CardQueue::UserBuried as i8
+            )
+        );
+        assert_eq!(
+            s(ctx, "is:buried-manually").0,
+            format!("(c.queue = {})", CardQueue::UserBuried as i8)
+        );
+        assert_eq!(
+            s(ctx, "is:buried-sibling").0,
+            format!("(c.queue = {})", CardQueue::SchedBuried as i8)
+        );
+        assert_eq!(
+            s(ctx, "is:fieldmismatch").0,
+            "((length(n.flds) - length(replace(n.flds, char(31), '')) + 1) \
+            != (select count() from fields where ntid = n.mid))"
+        );
+        assert_eq!(
+            s(ctx, "is:staledupecheck"),
+            ("(n.csum != first_field_checksum(n.flds))".into(), vec![])
+        );
 
         // rated
         assert_eq!(
@@ -1200,7 +1877,43 @@ mod test {
                 (timing.next_day_at.0 - (86_400 * 400)) * 1_000
             )
         );
+        assert_eq!(
+            s(ctx, "rated:7:2-3").0,
+            format!(
+                "(c.id in (select cid from revlog where id >= {} and ease between 2 and 3))",
+                (timing.next_day_at.0 - (86_400 * 7)) * 1_000
+            )
+        );
+        assert_eq!(
+            s(ctx, "prop:ed=0").0,
+            format!(
+                "(n.mod between {} and {})",
+                timing.next_day_at.0 - 86_400,
+                timing.next_day_at.0 - 1
+            )
+        );
+        assert_eq!(
+            s(ctx, "prop:ed>-7").0,
+            format!("(n.mod >= {})", timing.next_day_at.0 - 86_400 * 7)
+        );
+        // a range of one button is a single button
+        assert_eq!(s(ctx, "rated:7:3-3").0, s(ctx, "rated:7:3").0);
         assert_eq!(s(ctx, "rated:0").0, s(ctx, "rated:1").0);
+        // without a recorded session, the session started today
+        assert_eq!(s(ctx, "rated:session:1").0, s(ctx, "rated:1:1").0);
+        assert_eq!(
+            s(ctx, "rated:7:lapse").0,
+            format!(
+                "(c.id in (select cid from revlog where id >= {} and ease = 1 and type = 1))",
+                (timing.next_day_at.0 - (86_400 * 7)) * 1_000
+            )
+        );
+
+        // last rating
+        assert_eq!(
+            s(ctx, "lastrating:hard").0,
+            "((select ease from revlog where cid = c.id and ease > 0 order by id desc limit 1) = 2)"
+        );
 
         // resched
         assert_eq!(
@@ -1213,18 +1926,58 @@ mod test {
 
         // props
         assert_eq!(s(ctx, "prop:lapses=3").0, "(lapses = 3)".to_string());
+        assert_eq!(s(ctx, "prop:ivl>=3w").0, "(ivl >= 21)".to_string());
+        assert_eq!(
+            s(ctx, "prop:s>30").0,
+            "(extract_fsrs_variable(c.data, 's') > 30)"
+        );
+        // difficulty is stored in the 1-10 range
+        assert_eq!(
+            s(ctx, "prop:d<0.3").0,
+            format!(
+                "(extract_fsrs_variable(c.data, 'd') < {})",
+                0.3f32 * 9.0 + 1.0
+            )
+        );
         assert_eq!(s(ctx, "prop:ease>=2.5").0, "(factor >= 2500)".to_string());
+        assert_eq!(s(ctx, "prop:ease>=250%").0, s(ctx, "prop:ease>=2.5").0);
         assert_eq!(
             s(ctx, "prop:due!=-1").0,
             format!(
-                "(((c.queue in (2,3) and \n                        (case when \
-c.odue != 0 then c.odue else c.due end) != {days}) or (c.queue in (1,4) and 
-                        (((case when c.odue != 0 then c.odue else c.due end) - {cutoff}) / 86400) != -1)))",
+                "(((c.queue in (2,3) and {due} != {days}) or (c.queue in (1,4) and \
+                (case when {due} >= {start} then ({due} - {start}) / 86400 \
+                else ({due} - {start} - 86399) / 86400 end) != -1)))",
+                due = "(case when c.odue != 0 then c.odue else c.due end)",
                 days = timing.days_elapsed - 1,
-                cutoff = timing.next_day_at
+                start = timing.next_day_at.0 - 86_400
             )
         );
+        let today = timing
+            .next_day_at
+            .adding_secs(-86_400)
+            .local_datetime()
+            .unwrap()
+            .date_naive();
+        assert_eq!(
+            s(ctx, &format!("prop:due<={}", today.succ_opt().unwrap())).0,
+            s(ctx, "prop:due<=1").0
+        );
+        assert_eq!(
+            s(ctx, &format!("prop:due={today}")).0,
+            s(ctx, "prop:due=0").0
+        );
         assert_eq!(s(ctx, "prop:rated>-5:3").0, s(ctx, "rated:5:3").0);
+        assert_eq!(
+            s(ctx, "prop:rated:14>3").0,
+            format!(
+                "((select count() from revlog where cid = c.id and ease > 0 and id >= {}) > 3)",
+                (timing.next_day_at.0 - (86_400 * 14)) * 1_000
+            )
+        );
+        assert_eq!(
+            s(ctx, "prop:rated:<=2").0,
+            "((select count() from revlog where cid = c.id and ease > 0) <= 2)"
+        );
         assert_eq!(
             &s(ctx, "prop:cdn:r=1").0,
             "(cast(extract_custom_data(c.data, 'r') as float) = 1)"
@@ -1279,6 +2032,981 @@ c.odue != 0 then c.odue else c.due end) != {days}) or (c.queue in (1,4) and
             &s(ctx, "has-cd:r").0,
             "(extract_custom_data(c.data, 'r') is not null)"
         );
+        assert_eq!(
+            &s(ctx, "datakey:pos").0,
+            "((case when json_valid(c.data) then json_type(c.data, '$.pos') end) is not null)"
+        );
+    }
+
+    #[test]
+    fn due_weekday() -> Result<()> {
+        let mut col = Collection::new();
+        let timing = col.timing_today()?;
+        let mut cards = vec![];
+        for offset in 0..7 {
+            let cid = add_card_with(&mut col, |card| {
+                card.ctype = CardType::Review;
+                card.queue = CardQueue::Review;
+                card.interval = 1;
+                card.due = (timing.days_elapsed + offset) as i32;
+            })?;
+            let weekday = timing
+                .next_day_at
+                .adding_secs(86_400 * (offset as i64 - 1))
+                .local_datetime()?
+                .weekday();
+            cards.push((cid, weekday));
+        }
+        // a new card never matches
+        NoteAdder::basic(&mut col).add(&mut col);
+
+        for (cid, weekday) in &cards {
+            let search = format!("dueweekday:{weekday}");
+            assert_eq!(
+                col.search_cards(search.as_str(), SortMode::NoOrder)?,
+                [*cid]
+            );
+        }
+        assert_eq!(
+            col.search_cards("dueweekday:mon,tue,wed,thu,fri,sat,sun", SortMode::NoOrder)?
+                .len(),
+            7
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn last_rating() -> Result<()> {
+        let mut col = Collection::new();
+        let add_card_with_history = |col: &mut Collection, buttons: &[u8]| -> Result<CardId> {
+            let note = NoteAdder::basic(col).add(col);
+            let cid = col.storage.card_ids_of_notes(&[note.id])?[0];
+            for (idx, &button) in buttons.iter().enumerate() {
+                col.storage.add_revlog_entry(
+                    &RevlogEntry {
+                        id: RevlogId(cid.0 * 10 + idx as i64),
+                        cid,
+                        button_chosen: button,
+                        ..Default::default()
+                    },
+                    false,
+                )?;
+            }
+            Ok(cid)
+        };
+        let hard = add_card_with_history(&mut col, &[3, 1, 2])?;
+        let hard_then_rescheduled = add_card_with_history(&mut col, &[1, 2, 0])?;
+        let good_after_hard = add_card_with_history(&mut col, &[2, 2, 3])?;
+        let _unreviewed = add_card_with_history(&mut col, &[])?;
+
+        assert_eq!(
+            sorted_cids(&mut col, "lastrating:hard")?,
+            [hard, hard_then_rescheduled]
+        );
+        assert_eq!(
+            col.search_cards("lastrating:good", SortMode::NoOrder)?,
+            [good_after_hard]
+        );
+        assert!(col
+            .search_cards("lastrating:again", SortMode::NoOrder)?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn learning_due_days_follow_rollover() -> Result<()> {
+        let mut col = Collection::new();
+        col.set_rollover_for_current_scheduler(4)?;
+        let next_day_at = col.timing_today()?.next_day_at;
+        let add_learning_card = |col: &mut Collection, due: TimestampSecs| {
+            add_card_with(col, |card| {
+                card.ctype = CardType::Learn;
+                card.queue = CardQueue::Learn;
+                card.due = due.0 as i32;
+            })
+        };
+        let before_rollover = add_learning_card(&mut col, next_day_at.adding_secs(-60))?;
+        let after_rollover = add_learning_card(&mut col, next_day_at.adding_secs(3600))?;
+        let yesterday = add_learning_card(&mut col, next_day_at.adding_secs(-86_400 - 60))?;
+
+        let search = |col: &mut Collection, text| col.search_cards(text, SortMode::NoOrder);
+        assert_eq!(search(&mut col, "prop:due=0")?, [before_rollover]);
+        assert_eq!(search(&mut col, "prop:due=1")?, [after_rollover]);
+        assert_eq!(search(&mut col, "prop:due=-1")?, [yesterday]);
+        assert_eq!(
+            sorted_cids(&mut col, "prop:due<=0")?,
+            [before_rollover, yesterday]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn learning_card_due_in_minutes_is_due_today() -> Result<()> {
+        use chrono::Timelike;
+
+        let mut col = Collection::new();
+        // keep the rollover well away from now, so the card can't be pushed
+        // into tomorrow
+        let hour = TimestampSecs::now().local_datetime()?.hour();
+        col.set_rollover_for_current_scheduler(((hour + 12) % 24) as u8)?;
+        let learning = add_card_with(&mut col, |card| {
+            card.ctype = CardType::Learn;
+            card.queue = CardQueue::Learn;
+            card.due = TimestampSecs::now().adding_secs(180).0 as i32;
+        })?;
+        // a review card due today is compared in days, not seconds
+        let today = col.timing_today()?.days_elapsed as i32;
+        let review = add_card_with(&mut col, |card| {
+            card.ctype = CardType::Review;
+            card.queue = CardQueue::Review;
+            card.due = today;
+        })?;
+
+        for search in ["prop:due=0", "prop:due<=0"] {
+            assert_eq!(sorted_cids(&mut col, search)?, [learning, review]);
+        }
+        for search in ["prop:due>0", "prop:due<0", "prop:due=1"] {
+            assert!(col.search_cards(search, SortMode::NoOrder)?.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn due_after_last_review() -> Result<()> {
+        let mut col = Collection::new();
+        let today = col.timing_today()?.days_elapsed;
+        let add_review_card = |col: &mut Collection, due: u32, answered: bool| {
+            let cid = add_card_with(col, |card| {
+                card.ctype = CardType::Review;
+                card.queue = CardQueue::Review;
+                card.due = (today + due) as i32;
+            })?;
+            if answered {
+                col.storage.add_revlog_entry(
+                    &RevlogEntry {
+                        id: RevlogId(TimestampMillis::now().0),
+                        cid,
+                        button_chosen: 3,
+                        ..Default::default()
+                    },
+                    true,
+                )?;
+            }
+            Ok::<_, AnkiError>(cid)
+        };
+        let short = add_review_card(&mut col, 1, true)?;
+        let long = add_review_card(&mut col, 5, true)?;
+        let _unanswered = add_review_card(&mut col, 1, false)?;
+
+        assert_eq!(
+            col.search_cards("prop:duegap<2", SortMode::NoOrder)?,
+            [short]
+        );
+        assert_eq!(
+            col.search_cards("prop:duegap>=2", SortMode::NoOrder)?,
+            [long]
+        );
+        assert_eq!(
+            col.search_cards("prop:duegap=5", SortMode::NoOrder)?,
+            [long]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rated_in_session() -> Result<()> {
+        let mut col = Collection::new();
+        let now = TimestampSecs::now();
+        let answer = |col: &mut Collection, secs_ago: i64, button_chosen: u8| {
+            let note = NoteAdder::basic(col).add(col);
+            let cid = col.storage.card_ids_of_notes(&[note.id])?[0];
+            col.storage.add_revlog_entry(
+                &RevlogEntry {
+                    id: RevlogId(now.adding_secs(-secs_ago).as_millis().0),
+                    cid,
+                    button_chosen,
+                    ..Default::default()
+                },
+                true,
+            )?;
+            Ok::<_, AnkiError>(cid)
+        };
+        let days_ago = answer(&mut col, 3 * 86_400, 3)?;
+        let before_session = answer(&mut col, 2 * 3_600, 3)?;
+        let good = answer(&mut col, 60, 3)?;
+        let again = answer(&mut col, 30, 1)?;
+
+        col.set_study_session_start(now.adding_secs(-3_600))?;
+        assert_eq!(sorted_cids(&mut col, "rated:session")?, [good, again]);
+        assert_eq!(sorted_cids(&mut col, "rated:session:1")?, [again]);
+        // the session may span days
+        col.set_study_session_start(now.adding_secs(-4 * 86_400))?;
+        assert_eq!(
+            sorted_cids(&mut col, "rated:session:3")?,
+            [days_ago, before_session, good]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom_data_number() -> Result<()> {
+        let mut col = Collection::new();
+        let mut add_card = |custom_data: &str| {
+            add_card_with(&mut col, |card| card.custom_data = custom_data.into())
+        };
+        let low = add_card(r#"{"r":2}"#)?;
+        let high = add_card(r#"{"r":"7.5"}"#)?;
+        let _other_key = add_card(r#"{"s":7}"#)?;
+        let _no_data = add_card("")?;
+
+        assert_eq!(col.search_cards("prop:cdn:r>5", SortMode::NoOrder)?, [high]);
+        assert_eq!(col.search_cards("prop:cdn:r<=2", SortMode::NoOrder)?, [low]);
+        // cards without the key don't match, even when negated
+        assert_eq!(col.search_cards("-prop:cdn:r>5", SortMode::NoOrder)?, [low]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ease_deviation() -> Result<()> {
+        let mut col = Collection::new();
+        assert!(col
+            .search_cards("prop:easedev<0", SortMode::NoOrder)?
+            .is_empty());
+
+        let add_review_card = |col: &mut Collection, factor: u16| {
+            add_card_with(col, |card| {
+                card.ctype = CardType::Review;
+                card.queue = CardQueue::Review;
+                card.ease_factor = factor;
+            })
+        };
+        // average ease is 2.0
+        let low = add_review_card(&mut col, 1300)?;
+        add_review_card(&mut col, 2200)?;
+        let high = add_review_card(&mut col, 2500)?;
+        // new cards don't count towards the average
+        NoteAdder::basic(&mut col).add(&mut col);
+
+        assert_eq!(
+            col.search_cards("prop:easedev<-0.3", SortMode::NoOrder)?,
+            [low]
+        );
+        assert_eq!(
+            col.search_cards("prop:easedev>=0.2", SortMode::NoOrder)?,
+            [high]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn data_key() -> Result<()> {
+        let mut col = Collection::new();
+        let mut cids = vec![];
+        for data in ["", "{\"pos\":3}", "{\"pos\":null}", "{\"cd\":{}}", "{bad"] {
+            let note = NoteAdder::basic(&mut col).add(&mut col);
+            let cid = col.storage.card_ids_of_notes(&[note.id])?[0];
+            col.storage
+                .db
+                .execute("update cards set data = ? where id = ?", (data, cid))?;
+            cids.push(cid);
+        }
+
+        assert_eq!(sorted_cids(&mut col, "datakey:pos")?, [cids[1], cids[2]]);
+        assert_eq!(
+            col.search_cards("datakey:cd", SortMode::NoOrder)?,
+            [cids[3]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_collision() -> Result<()> {
+        let mut col = Collection::new();
+        let add_new_card =
+            |col: &mut Collection, position: i32| add_card_with(col, |card| card.due = position);
+        let first = add_new_card(&mut col, 1)?;
+        let second = add_new_card(&mut col, 1)?;
+        add_new_card(&mut col, 2)?;
+        // a review card with a matching due number is not a new card position
+        add_card_with(&mut col, |card| {
+            card.ctype = CardType::Review;
+            card.queue = CardQueue::Review;
+            card.due = 2;
+        })?;
+        // nor are siblings of the same note in the same position
+        let reversed = col
+            .get_notetype_by_name("Basic (and reversed card)")?
+            .unwrap();
+        let note = NoteAdder::new(&reversed)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        for mut card in col.storage.all_cards_of_note(note.id)? {
+            card.due = 3;
+            col.storage.update_card(&card)?;
+        }
+
+        assert_eq!(sorted_cids(&mut col, "is:poscollision")?, [first, second]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn field_count_mismatch() -> Result<()> {
+        let mut col = Collection::new();
+        let ok = NoteAdder::basic(&mut col)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        let extra = NoteAdder::basic(&mut col)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        let missing = NoteAdder::basic(&mut col)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        NoteAdder::cloze(&mut col)
+            .fields(&["{{c1::text}}", ""])
+            .add(&mut col);
+        col.storage.db.execute(
+            "update notes set flds = ? where id = ?",
+            ("front\x1fback\x1fstale", extra.id),
+        )?;
+        col.storage.db.execute(
+            "update notes set flds = ? where id = ?",
+            ("front", missing.id),
+        )?;
+
+        let found = sorted_nids(&mut col, "is:fieldmismatch")?;
+        assert_eq!(found, [extra.id, missing.id]);
+        assert!(!found.contains(&ok.id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn field_count() -> Result<()> {
+        let mut col = Collection::new();
+        let basic = NoteAdder::basic(&mut col).add(&mut col);
+        let short = NoteAdder::basic(&mut col).add(&mut col);
+        let cloze = NoteAdder::cloze(&mut col)
+            .fields(&["{{c1::text}}", ""])
+            .add(&mut col);
+        let long = NoteAdder::basic(&mut col).add(&mut col);
+        col.storage.db.execute(
+            "update notes set flds = ? where id = ?",
+            ("front", short.id),
+        )?;
+        // trailing empty fields are counted
+        col.storage.db.execute(
+            "update notes set flds = ? where id = ?",
+            ("front\x1fback\x1f\x1f", long.id),
+        )?;
+
+        assert_eq!(sorted_nids(&mut col, "prop:fields<2")?, [short.id]);
+        assert_eq!(
+            sorted_nids(&mut col, "prop:fields<=2")?,
+            [basic.id, short.id, cloze.id]
+        );
+        assert_eq!(
+            sorted_nids(&mut col, "prop:fields=2")?,
+            [basic.id, cloze.id]
+        );
+        assert_eq!(
+            sorted_nids(&mut col, "prop:fields>=2")?,
+            [basic.id, cloze.id, long.id]
+        );
+        assert_eq!(sorted_nids(&mut col, "prop:fields>2")?, [long.id]);
+        assert_eq!(sorted_nids(&mut col, "prop:fields=4")?, [long.id]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn card_count() -> Result<()> {
+        let mut col = Collection::new();
+        let reversed = col.basic_rev_notetype();
+        let single = NoteAdder::basic(&mut col).add(&mut col);
+        let both = NoteAdder::new(&reversed)
+            .fields(&["front", "back"])
+            .add(&mut col);
+        let cloze = NoteAdder::cloze(&mut col)
+            .fields(&["{{c1::one}} {{c2::two}} {{c3::three}}", ""])
+            .add(&mut col);
+
+        assert_eq!(sorted_nids(&mut col, "prop:cards<2")?, [single.id]);
+        assert_eq!(sorted_nids(&mut col, "prop:cards>=2")?, [both.id, cloze.id]);
+        assert_eq!(
+            sorted_nids(&mut col, "prop:cards!=2")?,
+            [single.id, cloze.id]
+        );
+        assert_eq!(sorted_nids(&mut col, "prop:cards=3")?, [cloze.id]);
+        // the whole note is counted, not just the matching card
+        assert_eq!(sorted_nids(&mut col, "card:2 prop:cards=2")?, [both.id]);
+        assert_eq!(
+            col.search_cards("card:1 prop:cards>2", SortMode::NoOrder)?
+                .len(),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tag_count() -> Result<()> {
+        let mut col = Collection::new();
+        let mut add_note = |tags: &[&str]| -> Result<NoteId> {
+            let mut note = NoteAdder::basic(&mut col).note();
+            note.tags = tags.iter().map(ToString::to_string).collect();
+            col.add_note(&mut note, DeckId(1))?;
+            Ok(note.id)
+        };
+        let untagged = add_note(&[])?;
+        let one = add_note(&["a"])?;
+        let two = add_note(&["a", "b::c"])?;
+
+        assert_eq!(sorted_nids(&mut col, "prop:tags=0")?, [untagged]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags=1")?, [one]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags>1")?, [two]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags>=1")?, [one, two]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags<2")?, [untagged, one]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags<=2")?, [untagged, one, two]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags!=1")?, [untagged, two]);
+
+        // older clients could leave ideographic spaces and repeated
+        // separators, which don't count as tags
+        col.storage.db.execute(
+            "update notes set tags = ? where id = ?",
+            (" a\u{3000}\u{3000}b  ", one),
+        )?;
+        col.storage.db.execute(
+            "update notes set tags = ? where id = ?",
+            ("\u{3000} ", untagged),
+        )?;
+        assert_eq!(sorted_nids(&mut col, "prop:tags=2")?, [one, two]);
+        assert_eq!(sorted_nids(&mut col, "prop:tags=0")?, [untagged]);
+        assert_eq!(
+            sorted_nids(&mut col, "prop:tags=0")?,
+            sorted_nids(&mut col, "tag:none")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn preset() -> Result<()> {
+        let mut col = Collection::new();
+        let deck = DeckAdder::new("deck")
+            .with_config(|config| config.name = "my_preset*".into())
+            .add(&mut col);
+        let note = NoteAdder::basic(&mut col).deck(deck.id).add(&mut col);
+        let filtered = DeckAdder::new("filtered").filtered(true).add(&mut col);
+        let other = NoteAdder::basic(&mut col).add(&mut col);
+        col.storage.db.execute(
+            "update cards set did = ?, odid = 1 where nid = ?",
+            (filtered.id, other.id),
+        )?;
+
+        // the name is matched literally, once unescaped, so * and _ are not
+        // wildcards
+        for search in [r"preset:my\_preset\*", "preset:my_preset*"] {
+            assert_eq!(col.search_notes_unordered(search)?, [note.id], "{search}");
+        }
+        for search in ["preset:my*", "preset:my_preset_", "preset:missing"] {
+            assert!(col.search_notes_unordered(search)?.is_empty(), "{search}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn preset_learning_steps() -> Result<()> {
+        let mut col = Collection::new();
+        let deck = DeckAdder::new("deck")
+            .with_config(|config| config.inner.learn_steps = vec![1.0, 10.0, 60.0])
+            .add(&mut col);
+        let three_steps = NoteAdder::basic(&mut col).deck(deck.id).add(&mut col);
+        // the default preset has two steps
+        let two_steps = NoteAdder::basic(&mut col).add(&mut col);
+
+        assert_eq!(
+            col.search_notes_unordered("preset:steps>2")?,
+            [three_steps.id]
+        );
+        assert_eq!(
+            col.search_notes_unordered("preset:steps=2")?,
+            [two_steps.id]
+        );
+        assert_eq!(col.search_notes_unordered("preset:steps>=2")?.len(), 2);
+        assert!(col.search_notes_unordered("preset:steps<2")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn new_card_position() -> Result<()> {
+        let mut col = Collection::new();
+        let mut cids = vec![];
+        for (due, ctype) in [
+            (1, CardType::New),
+            (2, CardType::New),
+            (1, CardType::Review),
+        ] {
+            cids.push(add_card_with(&mut col, |card| {
+                card.due = due;
+                card.ctype = ctype;
+            })?);
+        }
+
+        // cards that aren't new are never matched
+        assert_eq!(sorted_cids(&mut col, "prop:pos<=1")?, [cids[0]]);
+        assert_eq!(sorted_cids(&mut col, "prop:pos>=1")?, cids[..2]);
+        assert_eq!(sorted_cids(&mut col, "prop:pos!=1")?, [cids[1]]);
+        assert_eq!(sorted_cids(&mut col, "prop:pos=2")?, [cids[1]]);
+        assert!(sorted_cids(&mut col, "prop:pos>2")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_id_lists() -> Result<()> {
+        let mut col = Collection::new();
+        let note = NoteAdder::basic(&mut col).add(&mut col);
+        // the ids are written into the SQL rather than bound, so they aren't
+        // subject to SQLite's variable limit
+        let search = format!("nid:{}", (1..50_000).chain([note.id.0]).join(","));
+        assert_eq!(col.search_notes_unordered(search.as_str())?, [note.id]);
+        assert!(col
+            .search_cards(search.replace("nid", "-nid").as_str(), SortMode::NoOrder)?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn current_deck() -> Result<()> {
+        let mut col = Collection::new();
+        let deck = DeckAdder::new("studying").add(&mut col);
+        let current = NoteAdder::basic(&mut col).deck(deck.id).add(&mut col);
+        let default = NoteAdder::basic(&mut col).add(&mut col);
+
+        col.set_current_deck(deck.id)?;
+        assert_eq!(col.search_notes_unordered("deck:current")?, [current.id]);
+
+        // a missing deck matches nothing, rather than the default deck
+        col.set_config(ConfigKey::CurrentDeckId, &DeckId(1234))?;
+        assert!(col.search_notes_unordered("deck:current")?.is_empty());
+        assert_eq!(
+            col.search_notes_unordered("deck:current or deck:default")?,
+            [default.id]
+        );
+
+        // as does having no deck selected
+        col.remove_config(ConfigKey::CurrentDeckId.into())?;
+        assert!(col.search_notes_unordered("deck:current")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn scheduled() -> Result<()> {
+        let mut col = Collection::new();
+        let today = col.timing_today()?.days_elapsed as i32;
+        let tomorrow_secs = TimestampSecs::now().adding_secs(86_400).0 as i32;
+        let mut cids = vec![];
+        for (queue, due) in [
+            (CardQueue::Review, today + 5),
+            (CardQueue::Learn, tomorrow_secs),
+            (CardQueue::Review, today),
+            (CardQueue::Suspended, today + 5),
+            (CardQueue::UserBuried, today + 5),
+            (CardQueue::New, today + 5),
+        ] {
+            cids.push(add_card_with(&mut col, |card| {
+                card.queue = queue;
+                card.due = due;
+            })?);
+        }
+
+        assert_eq!(sorted_cids(&mut col, "is:scheduled")?, cids[..2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn overdue() -> Result<()> {
+        let mut col = Collection::new();
+        let today = col.timing_today()?.days_elapsed as i32;
+        let mut cids = vec![];
+        for (queue, due, ivl) in [
+            (CardQueue::Review, today - 11, 10),
+            (CardQueue::Review, today - 10, 10),
+            (CardQueue::Review, today - 2, 1),
+            (CardQueue::Suspended, today - 11, 10),
+            (CardQueue::New, today - 11, 10),
+        ] {
+            cids.push(add_card_with(&mut col, |card| {
+                card.queue = queue;
+                card.due = due;
+                card.interval = ivl;
+            })?);
+        }
+
+        assert_eq!(sorted_cids(&mut col, "is:overdue")?, [cids[0], cids[2]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn excluding_or_joined_decks() -> Result<()> {
+        let mut col = Collection::new();
+        for name in ["a", "a::child", "b", "c"] {
+            let deck = DeckAdder::new(name).add(&mut col);
+            NoteAdder::basic(&mut col).deck(deck.id).add(&mut col);
+        }
+        NoteAdder::basic(&mut col).add(&mut col);
+
+        let combined = sorted_cids(&mut col, "-(deck:a or deck:b)")?;
+        let separate = sorted_cids(&mut col, "-deck:a -deck:b")?;
+        assert_eq!(combined.len(), 2);
+        assert_eq!(combined, separate);
+        assert_eq!(
+            col.search_cards("deck:a or deck:b", SortMode::NoOrder)?
+                .len(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn decks_of_filtered_cards() -> Result<()> {
+        let mut col = Collection::new();
+        let spanish = DeckAdder::new("Spanish").add(&mut col);
+        let french = DeckAdder::new("French").add(&mut col);
+        let filtered_child = DeckAdder::new("Spanish::Cram").filtered(true).add(&mut col);
+        let other_filtered = DeckAdder::new("Other").filtered(true).add(&mut col);
+        let mut add_card = |home: DeckId, current: Option<DeckId>| {
+            add_card_with(&mut col, |card| match current {
+                Some(current) => {
+                    card.deck_id = current;
+                    card.original_deck_id = home;
+                }
+                None => card.deck_id = home,
+            })
+        };
+        let at_home = add_card(spanish.id, None)?;
+        let spanish_elsewhere = add_card(spanish.id, Some(other_filtered.id))?;
+        let french_in_child = add_card(french.id, Some(filtered_child.id))?;
+
+        // home decks are matched
+        assert_eq!(
+            sorted_cids(&mut col, "deck:Spanish")?,
+            [at_home, spanish_elsewhere]
+        );
+        assert_eq!(sorted_cids(&mut col, "deck:French")?, [french_in_child]);
+        assert_eq!(
+            sorted_cids(&mut col, "deck:Spanish -deck:filtered")?,
+            [at_home]
+        );
+        // and so are filtered decks that are named
+        assert_eq!(
+            sorted_cids(&mut col, "deck:Spanish::Cram")?,
+            [french_in_child]
+        );
+        assert_eq!(sorted_cids(&mut col, "deck:Other")?, [spanish_elsewhere]);
+        // optionally, a filtered child of a searched deck counts too
+        col.set_config_bool(BoolKey::SearchDecksByCurrentDeck, true, false)?;
+        assert_eq!(
+            sorted_cids(&mut col, "deck:Spanish")?,
+            [at_home, spanish_elsewhere, french_in_child]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn media_refs() -> Result<()> {
+        let mut col = Collection::new();
+        let image = NoteAdder::basic(&mut col)
+            .fields(&[r#"<img src="My%20Pic.JPG">"#, ""])
+            .add(&mut col);
+        let sound = NoteAdder::basic(&mut col)
+            .fields(&["", "[sound:my pic.jpg]"])
+            .add(&mut col);
+        NoteAdder::basic(&mut col)
+            .fields(&["my pic.jpg", r#"<img src="other.jpg">"#])
+            .add(&mut col);
+
+        assert_eq!(
+            sorted_nids(&mut col, r#""media:my pic.jpg""#)?,
+            [image.id, sound.id]
+        );
+        assert_eq!(col.search_notes_unordered("media:my%20pic.jpg")?.len(), 2);
+        assert!(col.search_notes_unordered("media:pic.jpg")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn note_suspended() -> Result<()> {
+        use anki_proto::scheduler::bury_or_suspend_cards_request::Mode as BuryOrSuspendMode;
+
+        let mut col = Collection::new();
+        let notetype = col.basic_rev_notetype();
+        let mut add_note = |suspended_cards: usize| -> Result<Vec<CardId>> {
+            let note = NoteAdder::new(&notetype).add(&mut col);
+            let mut cids = col.storage.card_ids_of_notes(&[note.id])?;
+            cids.sort();
+            col.bury_or_suspend_cards(&cids[..suspended_cards], BuryOrSuspendMode::Suspend)?;
+            Ok(cids)
+        };
+        add_note(0)?;
+        let partly = add_note(1)?;
+        let fully = add_note(2)?;
+
+        assert_eq!(sorted_cids(&mut col, "is:note-suspended")?, fully);
+        assert_eq!(
+            col.search_notes_unordered("is:note-suspended")?,
+            [col.storage.get_card(fully[0])?.unwrap().note_id]
+        );
+        // is:suspended matches suspended cards of either note
+        assert_eq!(
+            sorted_cids(&mut col, "is:suspended")?,
+            [partly[0], fully[0], fully[1]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_state_props() -> Result<()> {
+        let mut col = Collection::new();
+        let cid = add_card_with(&mut col, |card| {
+            card.memory_state = Some(FsrsMemoryState {
+                stability: 45.5,
+                difficulty: 2.8,
+            })
+        })?;
+        // cards without a memory state never match
+        NoteAdder::basic(&mut col).add(&mut col);
+
+        for search in ["prop:s>30", "prop:s<45.6", "prop:d<0.3", "prop:d>0.1"] {
+            assert_eq!(col.search_cards(search, SortMode::NoOrder)?, [cid]);
+        }
+        for search in ["prop:s>45.5", "prop:d>=0.3"] {
+            assert!(col.search_cards(search, SortMode::NoOrder)?.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn folded_dupes() -> Result<()> {
+        let mut col = Collection::new();
+        let ntid = col.basic_notetype().id;
+        let add = |col: &mut Collection, front: &str| {
+            NoteAdder::basic(col).fields(&[front, ""]).add(col).id
+        };
+        let plain = add(&mut col, "Cafe");
+        let accented = add(&mut col, "<b>CAFÉ</b>");
+        add(&mut col, "Coffee");
+        let empty = add(&mut col, "");
+        add(&mut col, "");
+
+        let search = format!("dupefold:{ntid},café");
+        assert_eq!(sorted_nids(&mut col, &search)?, [plain, accented]);
+        // without folding, only exact matches are found
+        let search = format!("dupe:{ntid},Cafe");
+        assert_eq!(col.search_notes_unordered(search.as_str())?, [plain]);
+        // empty fields are not duplicates of each other
+        let search = format!("dupefold:{ntid},");
+        assert!(col.search_notes_unordered(search.as_str())?.is_empty());
+        let search = format!("dupe:{ntid},");
+        assert!(col
+            .search_notes_unordered(search.as_str())?
+            .contains(&empty));
+
+        Ok(())
+    }
+
+    #[test]
+    fn any_dupes() -> Result<()> {
+        let mut col = Collection::new();
+        let add = |col: &mut Collection, front: &str| {
+            NoteAdder::basic(col).fields(&[front, ""]).add(col).id
+        };
+        let plain = add(&mut col, "one");
+        let formatted = add(&mut col, "<b>one</b>");
+        add(&mut col, "One");
+        add(&mut col, "two");
+        add(&mut col, "");
+        add(&mut col, "");
+        // same first field, but a different notetype
+        let reversed = col.basic_rev_notetype();
+        NoteAdder::new(&reversed).fields(&["two", ""]).add(&mut col);
+
+        assert_eq!(sorted_nids(&mut col, "is:duplicate")?, [plain, formatted]);
+        assert!(col.search_notes_unordered("is:duplicate -one")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn case_sensitive_field() -> Result<()> {
+        let mut col = Collection::new();
+        let upper = NoteAdder::basic(&mut col)
+            .fields(&["Hello", "x"])
+            .add(&mut col);
+        let lower = NoteAdder::basic(&mut col)
+            .fields(&["hello", "x"])
+            .add(&mut col);
+
+        assert_eq!(sorted_nids(&mut col, "front:hello")?, [upper.id, lower.id]);
+        assert_eq!(sorted_nids(&mut col, "front:cs:Hello")?, [upper.id]);
+        assert_eq!(sorted_nids(&mut col, "front:cs:h*")?, [lower.id]);
+        assert_eq!(sorted_nids(&mut col, "front:cs:H_llo")?, [upper.id]);
+        // wildcards can still be escaped, and the whole field must match
+        assert!(sorted_nids(&mut col, r"front:cs:H\*")?.is_empty());
+        assert!(sorted_nids(&mut col, "front:cs:Hell")?.is_empty());
+        assert_eq!(sorted_nids(&mut col, "*:cs:hello")?, [lower.id]);
+        assert!(sorted_nids(&mut col, "missing:cs:hello")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn blank_field() -> Result<()> {
+        let mut col = Collection::new();
+        let empty = NoteAdder::basic(&mut col).fields(&["", "b"]).add(&mut col);
+        let markup = NoteAdder::basic(&mut col)
+            .fields(&["<div><br></div> &nbsp;", "b"])
+            .add(&mut col);
+        NoteAdder::basic(&mut col)
+            .fields(&["<img src=a.jpg>", ""])
+            .add(&mut col);
+        NoteAdder::basic(&mut col).fields(&["a", ""]).add(&mut col);
+        // cloze notes have no Front field
+        NoteAdder::cloze(&mut col)
+            .fields(&["{{c1::a}}", ""])
+            .add(&mut col);
+
+        assert_eq!(sorted_nids(&mut col, "front:")?, [empty.id, markup.id]);
+        assert!(col.search_notes_unordered("missing:")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn raw_text() -> Result<()> {
+        let mut col = Collection::new();
+        let image = NoteAdder::basic(&mut col)
+            .fields(&["a", r#"<img src="a.jpg">"#])
+            .add(&mut col);
+        NoteAdder::basic(&mut col)
+            .fields(&["&lt;img", "b"])
+            .add(&mut col);
+        // the sort field is stored without HTML
+        let sort_field = NoteAdder::basic(&mut col)
+            .fields(&["<b>bold</b>", "c"])
+            .add(&mut col);
+
+        assert_eq!(col.search_notes_unordered("raw:<img")?, [image.id]);
+        assert_eq!(col.search_notes_unordered("raw:<b>")?, [sort_field.id]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn untagged() -> Result<()> {
+        let mut col = Collection::new();
+        let tagged = NoteAdder::basic(&mut col).add(&mut col);
+        col.storage
+            .db
+            .execute("update notes set tags = ' foo ' where id = ?", [tagged.id])?;
+        let untagged = NoteAdder::basic(&mut col).add(&mut col);
+        let separators_only = NoteAdder::basic(&mut col).add(&mut col);
+        col.storage.db.execute(
+            "update notes set tags = ' \u{3000} ' where id = ?",
+            [separators_only.id],
+        )?;
+
+        assert_eq!(
+            sorted_nids(&mut col, "is:untagged")?,
+            [untagged.id, separators_only.id]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn leech() -> Result<()> {
+        let mut col = Collection::new();
+        let mut tagged = NoteAdder::basic(&mut col).add(&mut col);
+        tagged.tags = vec!["leech".into()];
+        col.update_note(&mut tagged)?;
+        let mut custom = NoteAdder::basic(&mut col).add(&mut col);
+        custom.tags = vec!["my_leech".into()];
+        col.update_note(&mut custom)?;
+        assert_eq!(col.search_notes_unordered("is:leech")?, [tagged.id]);
+
+        // the configured name is matched literally
+        col.set_config_string_inner(StringKey::LeechTag, "my_leech")?;
+        assert_eq!(col.search_notes_unordered("is:leech")?, [custom.id]);
+        col.set_config_string_inner(StringKey::LeechTag, "my*")?;
+        assert!(col.search_notes_unordered("is:leech")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn added_and_edited_in_hours() -> Result<()> {
+        let mut col = Collection::new();
+        let note = NoteAdder::basic(&mut col).add(&mut col);
+        assert_eq!(col.search_notes_unordered("added:1h")?, [note.id]);
+        assert_eq!(col.search_notes_unordered("edited:1h")?, [note.id]);
+
+        // a note edited yesterday is outside the window
+        let day_ago = TimestampSecs::now().adding_secs(-86_400);
+        col.storage
+            .db
+            .execute("update notes set mod = ?", [day_ago])?;
+        assert!(col.search_notes_unordered("edited:23h")?.is_empty());
+        assert_eq!(col.search_notes_unordered("edited:25h")?, [note.id]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_checksum() -> Result<()> {
+        let mut col = Collection::new();
+        let fresh = NoteAdder::basic(&mut col)
+            .fields(&["<b>one</b>", ""])
+            .add(&mut col);
+        let stale = NoteAdder::basic(&mut col)
+            .fields(&["two", ""])
+            .add(&mut col);
+        assert!(col.search_notes_unordered("is:staledupecheck")?.is_empty());
+
+        col.storage
+            .db
+            .execute("update notes set csum = 1 where id = ?", [stale.id])?;
+        assert_eq!(col.search_notes_unordered("is:staledupecheck")?, [stale.id]);
+        assert_eq!(
+            col.search_cards("is:staledupecheck", SortMode::NoOrder)?,
+            col.storage.card_ids_of_notes(&[stale.id])?
+        );
+        assert!(!col
+            .search_notes_unordered("is:staledupecheck")?
+            .contains(&fresh.id));
+
+        Ok(())
     }
 
     #[test]
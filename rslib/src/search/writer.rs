@@ -1,8 +1,11 @@
 // Copyright: Ankitects Pty Ltd and contributors
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
+use std::fmt::Display;
 use std::mem;
 
+use chrono::Weekday;
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
 
@@ -43,10 +46,128 @@ pub fn replace_search_node(mut existing: Vec<Node>, replacement: Node) -> String
     write_nodes(&existing)
 }
 
+/// Like [replace_search_node], but instances of the search are removed
+/// instead, along with an operator next to each, so the rest of the search
+/// keeps its meaning. Groups left with a single term are unwrapped, and if
+/// nothing is left, the result is an empty search, matching the whole
+/// collection.
+pub fn remove_search_node(mut existing: Vec<Node>, target: Node) -> String {
+    if let Node::Search(search_node) = target {
+        remove_matching_nodes(&mut existing, &search_node);
+    }
+    write_nodes(&existing)
+}
+
+fn remove_matching_nodes(nodes: &mut Vec<Node>, target: &SearchNode) {
+    let mut idx = 0;
+    while idx < nodes.len() {
+        if !remove_matches_inside(&mut nodes[idx], target) {
+            idx += 1;
+            continue;
+        }
+        nodes.remove(idx);
+        let before = idx.checked_sub(1);
+        let after = (idx < nodes.len()).then_some(idx);
+        // "and" binds more tightly than "or", so if there's one on either
+        // side, the term was part of that conjunction
+        let operator = match (before.map(|i| &nodes[i]), after.map(|i| &nodes[i])) {
+            (_, Some(Node::And)) => after,
+            (Some(Node::And), _) => before,
+            (_, Some(Node::Or)) => after,
+            (Some(Node::Or), _) => before,
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            nodes.remove(operator);
+            if operator < idx {
+                idx -= 1;
+            }
+        }
+    }
+}
+
+/// Removes matching terms inside a negation or group, and returns true if
+/// the node itself should be removed.
+fn remove_matches_inside(node: &mut Node, target: &SearchNode) -> bool {
+    match node {
+        Node::Search(search) => mem::discriminant(search) == mem::discriminant(target),
+        Node::Not(inner) => remove_matches_inside(inner, target),
+        Node::Group(nodes) => {
+            remove_matching_nodes(nodes, target);
+            if nodes.len() == 1 {
+                let only = nodes.pop().unwrap();
+                *node = only;
+                false
+            } else {
+                nodes.is_empty()
+            }
+        }
+        Node::And | Node::Or => false,
+    }
+}
+
 pub(super) fn write_nodes(nodes: &[Node]) -> String {
     nodes.iter().map(write_node).collect()
 }
 
+/// Like [write_nodes], but written so that searches differing only in the
+/// order of terms joined by the same operator, or in redundant parentheses,
+/// give the same string.
+///
+/// Terms of a group joined only by AND, or only by OR, are sorted by their
+/// written form, so the ordering is purely syntactic. Nested groups with the
+/// same joiner, and groups of a single term, are unwrapped. Groups mixing AND
+/// and OR keep their order, as moving a term across operators of different
+/// precedence could change the meaning. Negated terms are canonicalized
+/// inside, but never moved out of their negation.
+pub(super) fn write_nodes_canonical(nodes: &[Node]) -> String {
+    write_nodes(&canonical_nodes(nodes.to_vec()))
+}
+
+fn canonical_nodes(nodes: Vec<Node>) -> Vec<Node> {
+    let nodes: Vec<Node> = nodes.into_iter().map(canonical_node).collect();
+    let Some(joiner) = common_joiner(&nodes) else {
+        return nodes;
+    };
+    let mut terms = vec![];
+    for node in nodes {
+        match node {
+            Node::And | Node::Or => (),
+            Node::Group(inner) if common_joiner(&inner).as_ref() == Some(&joiner) => terms.extend(
+                inner
+                    .into_iter()
+                    .filter(|n| !matches!(n, Node::And | Node::Or)),
+            ),
+            node => terms.push(node),
+        }
+    }
+    terms.sort_by_cached_key(write_node);
+    Itertools::intersperse(terms.into_iter(), joiner).collect()
+}
+
+fn canonical_node(node: Node) -> Node {
+    match node {
+        Node::Not(inner) => Node::Not(Box::new(canonical_node(*inner))),
+        Node::Group(nodes) => {
+            let mut nodes = canonical_nodes(nodes);
+            if nodes.len() == 1 {
+                nodes.pop().unwrap()
+            } else {
+                Node::Group(nodes)
+            }
+        }
+        node => node,
+    }
+}
+
+/// The operator joining all the terms, [Node::And] if there is only one, or
+/// None if both are used.
+fn common_joiner(nodes: &[Node]) -> Option<Node> {
+    let mut joiners = nodes.iter().filter(|n| matches!(n, Node::And | Node::Or));
+    let first = joiners.next().cloned().unwrap_or(Node::And);
+    joiners.all(|n| *n == first).then_some(first)
+}
+
 impl ToString for Node {
     fn to_string(&self) -> String {
         write_node(self)
@@ -68,10 +189,19 @@ fn write_search_node(node: &SearchNode) -> String {
     use SearchNode::*;
     match node {
         UnqualifiedText(s) => maybe_quote(&s.replace(':', "\\:")),
-        SingleField { field, text, is_re } => write_single_field(field, text, *is_re),
+        SingleField {
+            field,
+            text,
+            is_re,
+            match_case,
+        } => write_single_field(field, text, *is_re, *match_case),
         AddedInDays(u) => format!("added:{}", u),
+        AddedInDayRange { min, max } => format!("added:{min}-{max}"),
         EditedInDays(u) => format!("edited:{}", u),
+        AddedInHours(u) => format!("added:{}h", u),
+        EditedInHours(u) => format!("edited:{}h", u),
         IntroducedInDays(u) => format!("introduced:{}", u),
+        DueOnWeekdays(days) => format!("dueweekday:{}", days.iter().map(write_weekday).join(",")),
         CardTemplate(t) => write_template(t),
         Deck(s) => maybe_quote(&format!("deck:{}", s)),
         DeckIdsWithoutChildren(s) => format!("did:{}", s),
@@ -80,10 +210,23 @@ fn write_search_node(node: &SearchNode) -> String {
         NotetypeId(NotetypeIdType(i)) => format!("mid:{}", i),
         Notetype(s) => maybe_quote(&format!("note:{}", s)),
         Rated { days, ease } => write_rated(days, ease),
+        RatedInSession { ease } => write_rated("session", ease),
+        LastRating(button) => write_last_rating(*button),
         Tag { tag, is_re } => write_single_field("tag", tag, *is_re),
-        Duplicates { notetype_id, text } => write_dupe(notetype_id, text),
+        Duplicates {
+            notetype_id,
+            text,
+            fold,
+        } => write_dupe(notetype_id, text, *fold),
         State(k) => write_state(k),
-        Flag(u) => format!("flag:{}", u),
+        StaleChecksum => "is:staledupecheck".to_string(),
+        PositionCollision => "is:poscollision".to_string(),
+        FieldCountMismatch => "is:fieldmismatch".to_string(),
+        AnyDuplicate => "is:duplicate".to_string(),
+        Untagged => "is:untagged".to_string(),
+        Leech => "is:leech".to_string(),
+        Flag(flags) => format!("flag:{}", flags.iter().join(",")),
+        AnyFlag => "flag:any".to_string(),
         NoteIds(s) => format!("nid:{}", s),
         CardIds(s) => format!("cid:{}", s),
         Property { operator, kind } => write_property(operator, kind),
@@ -92,7 +235,11 @@ fn write_search_node(node: &SearchNode) -> String {
         NoCombining(s) => maybe_quote(&format!("nc:{}", s)),
         WordBoundary(s) => maybe_quote(&format!("w:{}", s)),
         CustomData(k) => maybe_quote(&format!("has-cd:{}", k)),
+        DataKey(k) => format!("datakey:{}", k),
         Preset(s) => maybe_quote(&format!("preset:{}", s)),
+        PresetLearningSteps { operator, count } => format!("preset:steps{operator}{count}"),
+        MediaRef(s) => maybe_quote(&format!("media:{}", s)),
+        RawText(s) => maybe_quote(&format!("raw:{}", s)),
     }
 }
 
@@ -115,14 +262,25 @@ fn needs_quotation(txt: &str) -> bool {
 }
 
 /// Also used by tag search, which has the same syntax.
-fn write_single_field(field: &str, text: &str, is_re: bool) -> String {
-    let re = if is_re { "re:" } else { "" };
-    let text = if !is_re && text.starts_with("re:") {
+fn write_single_field(field: &str, text: &str, is_re: bool, match_case: bool) -> String {
+    let prefix = if is_re {
+        "re:"
+    } else if match_case {
+        "cs:"
+    } else {
+        ""
+    };
+    let text = if prefix.is_empty() && (text.starts_with("re:") || text.starts_with("cs:")) {
         text.replacen(':', "\\:", 1)
     } else {
         text.to_string()
     };
-    maybe_quote(&format!("{}:{}{}", field.replace(':', "\\:"), re, &text))
+    maybe_quote(&format!(
+        "{}:{}{}",
+        field.replace(':', "\\:"),
+        prefix,
+        &text
+    ))
 }
 
 fn write_template(template: &TemplateKind) -> String {
@@ -132,19 +290,33 @@ fn write_template(template: &TemplateKind) -> String {
     }
 }
 
-fn write_rated(days: &u32, ease: &RatingKind) -> String {
+fn write_rated(days: impl Display, ease: &RatingKind) -> String {
     use RatingKind::*;
     match ease {
         AnswerButton(n) => format!("rated:{}:{}", days, n),
+        AnswerButtonRange(low, high) => format!("rated:{}:{}-{}", days, low, high),
         AnyAnswerButton => format!("rated:{}", days),
         ManualReschedule => format!("resched:{}", days),
+        Lapse => format!("rated:{}:lapse", days),
     }
 }
 
 /// Escape double quotes and backslashes: \"
-fn write_dupe(notetype_id: &NotetypeId, text: &str) -> String {
+fn write_dupe(notetype_id: &NotetypeId, text: &str, fold: bool) -> String {
     let esc = text.replace('\\', r"\\");
-    maybe_quote(&format!("dupe:{},{}", notetype_id, esc))
+    let key = if fold { "dupefold" } else { "dupe" };
+    maybe_quote(&format!("{}:{},{}", key, notetype_id, esc))
+}
+
+fn write_last_rating(button: u8) -> String {
+    let name = match button {
+        1 => "again",
+        2 => "hard",
+        3 => "good",
+        4 => "easy",
+        _ => return format!("lastrating:{}", button),
+    };
+    format!("lastrating:{}", name)
 }
 
 fn write_state(kind: &StateKind) -> String {
@@ -156,31 +328,73 @@ fn write_state(kind: &StateKind) -> String {
             Review => "review",
             Learning => "learn",
             Due => "due",
+            Scheduled => "scheduled",
+            Overdue => "overdue",
             Buried => "buried",
             UserBuried => "buried-manually",
             SchedBuried => "buried-sibling",
             Suspended => "suspended",
+            NoteSuspended => "note-suspended",
         }
     )
 }
 
+fn write_weekday(weekday: &Weekday) -> &'static str {
+    use Weekday::*;
+    match weekday {
+        Mon => "mon",
+        Tue => "tue",
+        Wed => "wed",
+        Thu => "thu",
+        Fri => "fri",
+        Sat => "sat",
+        Sun => "sun",
+    }
+}
+
 fn write_property(operator: &str, kind: &PropertyKind) -> String {
     use PropertyKind::*;
     match kind {
         Due(i) => format!("prop:due{}{}", operator, i),
-        Interval(u) => format!("prop:ivl{}{}", operator, u),
+        DueOnDate(date) => format!("prop:due{}{}", operator, date.format("%Y-%m-%d")),
+        DueAfterLastReview(days) => format!("prop:duegap{operator}{days}"),
+        Edited(i) => format!("prop:ed{}{}", operator, i),
+        Interval { days, unit } => match unit {
+            Some(unit) => format!(
+                "prop:ivl{}{}{}",
+                operator,
+                days / unit.days(),
+                unit.suffix()
+            ),
+            None => format!("prop:ivl{}{}", operator, days),
+        },
         Reps(u) => format!("prop:reps{}{}", operator, u),
         Lapses(u) => format!("prop:lapses{}{}", operator, u),
-        Ease(f) => format!("prop:ease{}{}", operator, f),
+        Ease { value, percent } => {
+            let suffix = if *percent { "%" } else { "" };
+            format!("prop:ease{operator}{value}{suffix}")
+        }
+        EaseDeviation(f) => format!("prop:easedev{}{}", operator, f),
         Position(u) => format!("prop:pos{}{}", operator, u),
+        FieldCount(u) => format!("prop:fields{}{}", operator, u),
+        CardCount(u) => format!("prop:cards{operator}{u}"),
+        TagCount(u) => format!("prop:tags{operator}{u}"),
         Stability(u) => format!("prop:s{}{}", operator, u),
         Difficulty(u) => format!("prop:d{}{}", operator, u),
         Retrievability(u) => format!("prop:r{}{}", operator, u),
         Rated(u, ease) => match ease {
             RatingKind::AnswerButton(val) => format!("prop:rated{}{}:{}", operator, u, val),
+            RatingKind::AnswerButtonRange(low, high) => {
+                format!("prop:rated{}{}:{}-{}", operator, u, low, high)
+            }
             RatingKind::AnyAnswerButton => format!("prop:rated{}{}", operator, u),
             RatingKind::ManualReschedule => format!("prop:resched{}{}", operator, u),
+            RatingKind::Lapse => format!("prop:rated{}{}:lapse", operator, u),
         },
+        RatedCount { days, count } => {
+            let days = days.map(|days| days.to_string()).unwrap_or_default();
+            format!("prop:rated:{days}{operator}{count}")
+        }
         CustomDataNumber { key, value } => format!("prop:cdn:{key}{operator}{value}"),
         CustomDataString { key, value } => {
             maybe_quote(&format!("prop:cds:{key}{operator}{value}",))
@@ -204,6 +418,25 @@ mod test {
     use crate::error::Result;
     use crate::search::parse_search as parse;
 
+    #[test]
+    fn canonical_form() -> Result<()> {
+        let canonical = |text| parse(text).map(|nodes| write_nodes_canonical(&nodes));
+
+        assert_eq!(canonical("b a")?, "a b");
+        assert_eq!(canonical("c (b a)")?, "a b c");
+        assert_eq!(canonical("b or (c or a)")?, "a OR b OR c");
+        assert_eq!(canonical("((a))")?, "a");
+        assert_eq!(canonical("c (b or a)")?, canonical("(a or b) and c")?);
+        assert_eq!(canonical("-(b a)")?, "-(a b)");
+        assert_eq!(canonical("-(b)")?, "-b");
+        // groups with both operators, and their negations, keep their terms
+        assert_eq!(canonical("b a or c")?, "b a OR c");
+        assert_eq!(canonical("d (b a or c)")?, "(b a OR c) d");
+        assert_ne!(canonical("a b")?, canonical("-(a b)")?);
+
+        Ok(())
+    }
+
     #[test]
     fn normalizing() {
         // remove redundant quotes
@@ -212,6 +445,20 @@ mod test {
             normalize_search(r#""foo" "b a r""#).unwrap()
         );
         assert_eq!("field:foo", normalize_search(r#"field:"foo""#).unwrap());
+        assert_eq!("Front:", normalize_search(r#""Front:""#).unwrap());
+        assert_eq!("Front:cs:Foo*", normalize_search("Front:cs:Foo*").unwrap());
+        assert_eq!(
+            r"Front:cs\:foo",
+            normalize_search(r"Front:cs\:foo").unwrap()
+        );
+        assert_eq!(
+            r#"deck:French deck:a\"b "deck:a b" "deck:(a)""#,
+            normalize_search(r#"deck:"French" "deck:a\"b" deck:"a b" "deck:(a)""#).unwrap()
+        );
+        assert_eq!(
+            "deck:current",
+            normalize_search(r#""deck:current""#).unwrap()
+        );
         // escape by quoting where possible
         assert_eq!(r#""(" ")""#, normalize_search(r"\( \)").unwrap());
         assert_eq!(r#""-foo""#, normalize_search(r"\-foo").unwrap());
@@ -227,6 +474,116 @@ mod test {
         assert_eq!(r#""aNd" "oR""#, normalize_search(r#""aNd" "oR""#).unwrap());
         // normalize numbers
         assert_eq!("prop:ease>1", normalize_search("prop:ease>1.0").unwrap());
+        assert_eq!(
+            "prop:ease>=250% prop:ease<2.5",
+            normalize_search("prop:ease>=250.0% prop:ease<2.50").unwrap()
+        );
+        assert_eq!(
+            "prop:pos<=100 prop:pos!=3",
+            normalize_search("prop:pos<=100 prop:pos!=3").unwrap()
+        );
+        assert_eq!(
+            "prop:s>=2.55 prop:d<0.3",
+            normalize_search("prop:s>=2.55 prop:d<0.30").unwrap()
+        );
+        assert_eq!(
+            "dupefold:1,Café",
+            normalize_search("DupeFold:1,Café").unwrap()
+        );
+        assert_eq!(
+            "flag:5 OR flag:7",
+            normalize_search("flag:5 or flag:7").unwrap()
+        );
+        assert_eq!("flag:0,1,2", normalize_search("flag:2,0,1,1").unwrap());
+        assert_eq!("flag:any", normalize_search("flag:ANY").unwrap());
+        assert_eq!("raw:<img", normalize_search("raw:<img").unwrap());
+        assert_eq!(
+            r#""raw:<b class=x>""#,
+            normalize_search(r#""raw:<b class=x>""#).unwrap()
+        );
+        assert_eq!(
+            "prop:cdn:r>=5 prop:cdn:r<0.5",
+            normalize_search("prop:cdn:r>=5 prop:cdn:r<0.5").unwrap()
+        );
+        assert_eq!("datakey:pos", normalize_search("DataKey:pos").unwrap());
+        assert_eq!("introduced:7", normalize_search("introduced:7").unwrap());
+        assert_eq!(
+            "is:poscollision",
+            normalize_search("is:poscollision").unwrap()
+        );
+        assert_eq!(
+            "prop:easedev<-0.3",
+            normalize_search("prop:easedev<-0.30").unwrap()
+        );
+        assert_eq!(
+            "prop:fields>=2",
+            normalize_search("prop:fields>=2").unwrap()
+        );
+        assert_eq!(
+            "prop:cards<2 prop:cards=0",
+            normalize_search("prop:cards<2 prop:cards=00").unwrap()
+        );
+        assert_eq!(
+            "prop:tags>=1 prop:tags!=3",
+            normalize_search("prop:tags>=1 prop:tags!=3").unwrap()
+        );
+        assert_eq!("tag:parent::", normalize_search("tag:parent::").unwrap());
+        assert_eq!("rated:7:lapse", normalize_search("rated:7:lapse").unwrap());
+        assert_eq!(
+            "rated:session rated:session:1 resched:session",
+            normalize_search("rated:Session rated:session:1 resched:SESSION").unwrap()
+        );
+        assert_eq!(
+            "rated:7:2-3 prop:rated>-7:1-2",
+            normalize_search("rated:7:2-3 prop:rated>-7:1-2").unwrap()
+        );
+        assert_eq!(
+            "prop:rated:14>3 prop:rated:!=0 prop:rated:1=2",
+            normalize_search("prop:rated:14>3 prop:rated:!=0 prop:rated:0=2").unwrap()
+        );
+        assert_eq!(
+            r#""media:my pic.jpg""#,
+            normalize_search(r#""Media:my pic.jpg""#).unwrap()
+        );
+        assert_eq!(
+            "prop:rated>-7:lapse",
+            normalize_search("prop:rated>-7:lapse").unwrap()
+        );
+        // answer buttons are written by name
+        assert_eq!("lastrating:hard", normalize_search("lastrating:2").unwrap());
+        // weekdays are written in their short form
+        assert_eq!(
+            "dueweekday:sat,sun",
+            normalize_search("dueweekday:Saturday,sun").unwrap()
+        );
+        assert_eq!("is:duplicate", normalize_search("is:duplicate").unwrap());
+        assert_eq!("is:untagged", normalize_search("is:untagged").unwrap());
+        assert_eq!("is:leech", normalize_search("is:leech").unwrap());
+        assert_eq!(
+            "preset:steps>2",
+            normalize_search("preset:steps>2").unwrap()
+        );
+        assert_eq!(
+            "edited:7 prop:ed=0 prop:ed<-3",
+            normalize_search("edited:7 prop:ed=0 prop:ed<-3").unwrap()
+        );
+        assert_eq!(
+            "added:6h edited:1h added:1",
+            normalize_search("added:6h edited:1h added:1").unwrap()
+        );
+        assert_eq!(
+            "added:3-7 added:2-2",
+            normalize_search("added:7-3 added:2-2").unwrap()
+        );
+        // interval units are kept
+        assert_eq!(
+            "prop:ivl>=3w prop:ivl<21 prop:ivl=2m",
+            normalize_search("prop:ivl>=3w prop:ivl<21 prop:ivl=2m").unwrap()
+        );
+        assert_eq!(
+            "prop:due=2024-01-15 prop:due>-1 prop:duegap<2",
+            normalize_search("prop:due=2024-01-15 prop:due>-1 prop:duegap<2").unwrap()
+        );
     }
 
     #[test]
@@ -249,6 +606,15 @@ mod test {
             ),
             "bar OR (-bar tag:baz)",
         );
+        // every term of the same kind is replaced, whatever its value, including
+        // inside negated groups
+        assert_eq!(
+            replace_search_node(
+                parse("note:a (deck:x or -(deck:y note:b))")?,
+                parse("deck:z")?.pop().unwrap()
+            ),
+            "note:a (deck:z OR -(deck:z note:b))",
+        );
         assert_eq!(
             replace_search_node(parse("is:due")?, parse("-is:new")?.pop().unwrap()),
             "is:due"
@@ -260,4 +626,34 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn removing() -> Result<()> {
+        let remove = |search: &str, target: &str| -> Result<String> {
+            Ok(remove_search_node(
+                parse(search)?,
+                parse(target)?.pop().unwrap(),
+            ))
+        };
+
+        assert_eq!(remove("deck:a tag:x", "deck:b")?, "tag:x");
+        assert_eq!(remove("tag:x deck:a", "deck:b")?, "tag:x");
+        // an "and" is removed in preference to an "or", as it binds more
+        // tightly
+        assert_eq!(remove("a or deck:x b", "deck:x")?, "a OR b");
+        assert_eq!(remove("a deck:x or b", "deck:x")?, "a OR b");
+        assert_eq!(remove("a or deck:x or b", "deck:x")?, "a OR b");
+        // negated terms go too, and groups left with one term are unwrapped
+        assert_eq!(remove("a (-deck:x or b)", "deck:x")?, "a b");
+        assert_eq!(remove("-(deck:x b) c", "deck:x")?, "-b c");
+        assert_eq!(remove("a (deck:x or -deck:y)", "deck:x")?, "a");
+        assert_eq!(remove("(deck:x) or a", "deck:x")?, "a");
+        // every term of the kind is removed
+        assert_eq!(remove("deck:x a deck:y", "deck:z")?, "a");
+        // removing everything leaves a search for the whole collection
+        assert_eq!(remove("deck:x", "deck:x")?, "");
+        assert_eq!(remove("a", "deck:x")?, "a");
+
+        Ok(())
+    }
 }
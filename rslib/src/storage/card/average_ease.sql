@@ -0,0 +1,12 @@
+SELECT (
+    CASE
+      WHEN odid != 0 THEN odid
+      ELSE did
+    END
+  ) AS home_did,
+  avg(factor)
+FROM cards
+WHERE type IN (2, 3)
+  AND factor > 0
+  AND extract_fsrs_variable(data, 's') IS NULL
+GROUP BY home_did
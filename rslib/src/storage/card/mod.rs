@@ -388,6 +388,15 @@ impl super::SqliteStorage {
             .collect()
     }
 
+    /// Average ease factor of the SM2 review cards in each deck, with cards in
+    /// filtered decks counted towards their home deck.
+    pub(crate) fn average_ease_by_home_deck(&self) -> Result<Vec<(DeckId, f64)>> {
+        self.db
+            .prepare(include_str!("average_ease.sql"))?
+            .query_and_then([], |r| -> Result<_> { Ok((r.get(0)?, r.get(1)?)) })?
+            .collect()
+    }
+
     pub(crate) fn max_new_card_position(&self) -> Result<u32> {
         self.db
             .prepare("select max(due)+1 from cards where type=0")?
@@ -563,6 +572,31 @@ impl super::SqliteStorage {
             .collect()
     }
 
+    /// Distinct note ids of the cards in 'search_cids', ordered by their first
+    /// card in the table.
+    pub(crate) fn note_ids_of_searched_cards(&self) -> Result<Vec<NoteId>> {
+        self.db
+            .prepare(
+                "select cards.nid from cards, search_cids where cards.id = search_cids.cid \
+                group by cards.nid order by min(search_cids.rowid)",
+            )?
+            .query_and_then([], |r| r.get(0).map_err(Into::into))?
+            .collect()
+    }
+
+    /// The first card of each note in 'search_cids', ordered by their
+    /// position in the table.
+    pub(crate) fn first_card_of_each_note_in_search(&self) -> Result<Vec<CardId>> {
+        // sqlite takes the bare cid from the row that has the min() rowid
+        self.db
+            .prepare(
+                "select search_cids.cid, min(search_cids.rowid) as pos from cards, search_cids \
+                where cards.id = search_cids.cid group by cards.nid order by pos",
+            )?
+            .query_and_then([], |r| r.get(0).map_err(Into::into))?
+            .collect()
+    }
+
     /// Cards will arrive in card id order, not search order.
     pub(crate) fn for_each_card_in_search<F>(&self, mut func: F) -> Result<()>
     where
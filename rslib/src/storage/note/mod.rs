@@ -162,6 +162,17 @@ impl super::SqliteStorage {
             .map(|_| ())
     }
 
+    /// Returns [(nid, field 0)] of all notes of the notetype.
+    pub(crate) fn note_first_fields_of_notetype(
+        &self,
+        ntid: NotetypeId,
+    ) -> Result<Vec<(NoteId, String)>> {
+        self.db
+            .prepare("select id, field_at_index(flds, 0) from notes where mid=?")?
+            .query_and_then([ntid], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect()
+    }
+
     /// Returns [(nid, field 0)] of notes with the same checksum.
     /// The caller should strip the fields and compare to see if they actually
     /// match.
@@ -176,6 +187,32 @@ impl super::SqliteStorage {
             .collect()
     }
 
+    /// Returns [(ntid, nid, field 0)] of notes sharing their checksum with
+    /// another note of the same notetype. The caller should strip the fields
+    /// and compare to see if they actually match.
+    pub(crate) fn notes_with_shared_checksum(&self) -> Result<Vec<(NotetypeId, NoteId, String)>> {
+        self.db
+            .prepare(
+                "select mid, id, field_at_index(flds, 0) from notes where (mid, csum) in \
+                (select mid, csum from notes group by mid, csum having count() > 1)",
+            )?
+            .query_and_then([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+            .collect()
+    }
+
+    /// Returns [(nid, ntid, field 0)] of the notes in the search_nids table.
+    pub(crate) fn first_fields_of_searched_notes(
+        &self,
+    ) -> Result<Vec<(NoteId, NotetypeId, String)>> {
+        self.db
+            .prepare(
+                "select id, mid, field_at_index(flds, 0) from notes \
+                where id in (select nid from search_nids)",
+            )?
+            .query_and_then([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+            .collect()
+    }
+
     /// Returns [(nid, field 0)] of notes with the same checksum.
     /// The caller should strip the fields and compare to see if they actually
     /// match.
@@ -23,16 +23,62 @@ use super::upgrades::SCHEMA_STARTING_VERSION;
 use super::SchemaVersion;
 use crate::config::schema11::schema11_config_as_string;
 use crate::error::DbErrorKind;
+use crate::notes::field_checksum;
 use crate::prelude::*;
 use crate::scheduler::timing::local_minutes_west_for_stamp;
 use crate::scheduler::timing::v1_creation_date;
 use crate::storage::card::data::CardData;
+use crate::tags::split_tags;
+use crate::text::extract_media_refs;
+use crate::text::media_filename_for_comparison;
+use crate::text::strip_html_preserving_media_filenames;
 use crate::text::without_combining;
 
 fn unicase_compare(s1: &str, s2: &str) -> Ordering {
     UniCase::new(s1).cmp(&UniCase::new(s2))
 }
 
+/// Like [unicase_compare], but runs of digits are compared by their value, so
+/// "Chapter 2" sorts before "Chapter 10". Digits still sort before letters, so
+/// text without numbers is ordered as usual.
+fn natural_compare(s1: &str, s2: &str) -> Ordering {
+    let mut chunks1 = digit_and_text_runs(s1);
+    let mut chunks2 = digit_and_text_runs(s2);
+    loop {
+        let ordering = match (chunks1.next(), chunks2.next()) {
+            // eg "2" and "02"
+            (None, None) => return unicase_compare(s1, s2),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) if a.starts_with(is_digit) && b.starts_with(is_digit) => {
+                let a = a.trim_start_matches('0');
+                let b = b.trim_start_matches('0');
+                a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+            }
+            (Some(a), Some(b)) => unicase_compare(a, b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_ascii_digit()
+}
+
+fn digit_and_text_runs(mut text: &str) -> impl Iterator<Item = &str> {
+    std::iter::from_fn(move || {
+        let digits = is_digit(text.chars().next()?);
+        let end = text
+            .find(|c: char| is_digit(c) != digits)
+            .unwrap_or(text.len());
+        let (run, rest) = text.split_at(end);
+        text = rest;
+        Some(run)
+    })
+}
+
 // fixme: rollback savepoint when tags not changed
 // fixme: need to drop out of wal prior to vacuuming to fix page size of older
 // collections
@@ -65,9 +111,13 @@ fn open_or_create_collection_db(path: &Path) -> Result<Connection> {
     db.set_prepared_statement_cache_capacity(50);
 
     add_field_index_function(&db)?;
+    add_first_field_checksum_function(&db)?;
+    add_refers_to_media_function(&db)?;
     add_regexp_function(&db)?;
     add_regexp_fields_function(&db)?;
+    add_blank_fields_function(&db)?;
     add_regexp_tags_function(&db)?;
+    add_tag_count_function(&db)?;
     add_without_combining_function(&db)?;
     add_fnvhash_function(&db)?;
     add_extract_custom_data_function(&db)?;
@@ -76,6 +126,7 @@ fn open_or_create_collection_db(path: &Path) -> Result<Connection> {
     add_extract_fsrs_relative_overdueness(&db)?;
 
     db.create_collation("unicase", unicase_compare)?;
+    db.create_collation("natural", natural_compare)?;
 
     Ok(db)
 }
@@ -104,6 +155,38 @@ fn add_field_index_function(db: &Connection) -> rusqlite::Result<()> {
     )
 }
 
+/// Adds sql function first_field_checksum(flds) -> csum
+/// to recompute the duplicate check checksum of a note's first field.
+fn add_first_field_checksum_function(db: &Connection) -> rusqlite::Result<()> {
+    db.create_scalar_function(
+        "first_field_checksum",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let first_field = ctx.get_raw(0).as_str()?.split('\x1f').next().unwrap_or("");
+            let stripped = strip_html_preserving_media_filenames(first_field);
+            Ok(field_checksum(&stripped))
+        },
+    )
+}
+
+/// Adds sql function refers_to_media(flds, fname) -> is_match
+/// where fname has been passed through media_filename_for_comparison().
+fn add_refers_to_media_function(db: &Connection) -> rusqlite::Result<()> {
+    db.create_scalar_function(
+        "refers_to_media",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let fields = ctx.get_raw(0).as_str()?;
+            let fname = ctx.get_raw(1).as_str()?;
+            Ok(extract_media_refs(fields)
+                .iter()
+                .any(|media_ref| media_filename_for_comparison(&media_ref.fname_decoded) == fname))
+        },
+    )
+}
+
 fn add_without_combining_function(db: &Connection) -> rusqlite::Result<()> {
     db.create_scalar_function(
         "without_combining",
@@ -185,6 +268,32 @@ fn add_regexp_fields_function(db: &Connection) -> rusqlite::Result<()> {
     )
 }
 
+/// Adds sql function `blank_fields(note_flds, indices...) -> is_match`, true
+/// if any of the indexed fields is empty once HTML and whitespace are
+/// stripped. Media references don't count as blank.
+fn add_blank_fields_function(db: &Connection) -> rusqlite::Result<()> {
+    db.create_scalar_function(
+        "blank_fields",
+        -1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert!(ctx.len() > 1, "not enough arguments");
+
+            let fields = ctx.get_raw(0).as_str()?.split('\x1f');
+            let indices: HashSet<usize> = (1..ctx.len())
+                .map(|i| ctx.get(i))
+                .collect::<rusqlite::Result<_>>()?;
+
+            Ok(fields.enumerate().any(|(idx, field)| {
+                indices.contains(&idx)
+                    && strip_html_preserving_media_filenames(field)
+                        .trim()
+                        .is_empty()
+            }))
+        },
+    )
+}
+
 /// Adds sql function `regexp_tags(regex, tags) -> is_match`.
 fn add_regexp_tags_function(db: &Connection) -> rusqlite::Result<()> {
     db.create_scalar_function(
@@ -205,6 +314,14 @@ fn add_regexp_tags_function(db: &Connection) -> rusqlite::Result<()> {
     )
 }
 
+/// Adds sql function `tag_count(tags) -> count`, splitting the tags the same
+/// way notes do, so repeated or ideographic-space separators don't add tags.
+fn add_tag_count_function(db: &Connection) -> rusqlite::Result<()> {
+    db.create_scalar_function("tag_count", 1, FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+        Ok(split_tags(ctx.get_raw(0).as_str()?).count() as i64)
+    })
+}
+
 /// eg. extract_custom_data(card.data, 'r') -> string | null
 fn add_extract_custom_data_function(db: &Connection) -> rusqlite::Result<()> {
     db.create_scalar_function(
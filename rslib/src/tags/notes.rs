@@ -1,6 +1,7 @@
 // Copyright: Ankitects Pty Ltd and contributors
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 
 use unicase::UniCase;
@@ -26,4 +27,58 @@ impl Collection {
             })?;
         Ok(all_tags)
     }
+
+    /// The tags used by notes matching the search, with the number of those
+    /// notes that have each tag, in case-insensitive order. Tags differing
+    /// only in case are counted together, under the first spelling found.
+    pub fn tags_in_search(&mut self, search: impl TryIntoSearch) -> Result<Vec<(String, usize)>> {
+        let guard = self.search_notes_into_table(search)?;
+        let mut counts: HashMap<UniCase<String>, usize> = HashMap::new();
+        guard
+            .col
+            .storage
+            .for_each_note_tag_in_searched_notes(|tags| {
+                for tag in split_tags(tags) {
+                    *counts.entry(UniCase::new(tag.to_string())).or_default() += 1;
+                }
+            })?;
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(counts
+            .into_iter()
+            .map(|(tag, count)| (tag.into_inner(), count))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tests::NoteAdder;
+
+    #[test]
+    fn tags_in_search() -> Result<()> {
+        let mut col = Collection::new();
+        for (front, tags) in [
+            ("one", vec!["b::child", "a"]),
+            ("one", vec!["B::Child", "Ä"]),
+            ("two", vec!["c"]),
+        ] {
+            let mut note = NoteAdder::basic(&mut col).fields(&[front, ""]).note();
+            note.tags = tags.into_iter().map(Into::into).collect();
+            col.add_note(&mut note, DeckId(1))?;
+        }
+
+        assert_eq!(
+            col.tags_in_search("front:one")?,
+            [
+                ("a".to_string(), 1),
+                ("b::child".into(), 2),
+                ("Ä".into(), 1)
+            ]
+        );
+        assert!(col.tags_in_search("front:none")?.is_empty());
+
+        Ok(())
+    }
 }
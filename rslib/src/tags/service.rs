@@ -104,4 +104,21 @@ impl crate::services::TagsService for Collection {
         let tags = Collection::complete_tag(self, &input.input, input.match_limit as usize)?;
         Ok(anki_proto::tags::CompleteTagResponse { tags })
     }
+
+    fn tags_in_search(
+        &mut self,
+        input: generic::String,
+    ) -> error::Result<anki_proto::tags::TagsInSearchResponse> {
+        let tags = self
+            .tags_in_search(input.val.as_str())?
+            .into_iter()
+            .map(
+                |(name, notes)| anki_proto::tags::tags_in_search_response::Tag {
+                    name,
+                    notes: notes as u32,
+                },
+            )
+            .collect();
+        Ok(anki_proto::tags::TagsInSearchResponse { tags })
+    }
 }
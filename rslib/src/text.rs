@@ -218,6 +218,33 @@ pub fn strip_html_preserving_entities(html: &str) -> Cow<str> {
     HTML.replace_all(html, "")
 }
 
+/// Passes each run of text between tags to `map`, keeping tags, comments and
+/// style or script blocks as they are.
+pub(crate) fn map_html_text<'a>(
+    html: &'a str,
+    mut map: impl FnMut(&str) -> Cow<str>,
+) -> Cow<'a, str> {
+    let mut out = String::new();
+    let mut changed = false;
+    let mut last = 0;
+    let mut map_run = |out: &mut String, run: &str| {
+        let mapped = map(run);
+        changed |= matches!(mapped, Cow::Owned(_));
+        out.push_str(&mapped);
+    };
+    for tag in HTML.find_iter(html) {
+        map_run(&mut out, &html[last..tag.start()]);
+        out.push_str(tag.as_str());
+        last = tag.end();
+    }
+    map_run(&mut out, &html[last..]);
+    if changed {
+        out.into()
+    } else {
+        html.into()
+    }
+}
+
 pub fn decode_entities(html: &str) -> Cow<str> {
     if html.contains('&') {
         match htmlescape::decode_html(html) {
@@ -296,6 +323,12 @@ pub(crate) fn extract_media_refs(text: &str) -> Vec<MediaRef> {
     out
 }
 
+/// Percent-decodes and lowercases a media filename, so that references that
+/// differ only in encoding or case compare equal.
+pub(crate) fn media_filename_for_comparison(fname: &str) -> String {
+    percent_decode_str(fname).decode_utf8_lossy().to_lowercase()
+}
+
 /// Calls `replacer` for every media reference in `text`, and optionally
 /// replaces it with something else. [None] if no reference was found.
 pub fn replace_media_refs(